@@ -0,0 +1,85 @@
+//! Compares chains of 64 `fmap`/`bind` steps over a `CFuture` ("inline") with the same
+//! chains built the way they were before ready values were kept inline, where every
+//! step boxes and shares a new future ("boxed"), starting from a ready value or from a
+//! pending future.
+//!
+//! Measured with `cargo bench --bench cfuture_chains` (median):
+//!
+//! | start          | inline   | boxed    |
+//! |----------------|----------|----------|
+//! | fmap, ready    | 5.11 µs  | 22.1 µs  |
+//! | fmap, pending  | 23.5 µs  | 21.4 µs  |
+//! | bind, ready    | 7.41 µs  | 25.5 µs  |
+//! | bind, pending  | 32.3 µs  | 29.2 µs  |
+//!
+//! Chains over ready values run three to four times faster, as no step allocates a
+//! shared future; chains over pending futures still box and share every step, and pay
+//! about a tenth more for checking whether their input is ready.
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use futures::FutureExt;
+use rust_effects::prelude::*;
+use rust_effects::types::executor::block_on;
+use std::hint::black_box;
+
+const DEPTH: u64 = 64;
+
+fn fmap_chain(start: CFuture<u64>) -> CFuture<u64> {
+    (0..DEPTH).fold(start, |fut, i| fmap(fut, move |a| a.wrapping_mul(31) + i))
+}
+
+fn bind_chain(start: CFuture<u64>) -> CFuture<u64> {
+    (0..DEPTH).fold(start, |fut, i| {
+        bind(fut, move |a| pure::<CFuture<_>>(a.wrapping_mul(31) + i))
+    })
+}
+
+/// `fmap_chain` as `fmap` used to build it, with a new shared future per step
+fn fmap_chain_boxed(start: CFuture<u64>) -> CFuture<u64> {
+    (0..DEPTH).fold(start, |fut, i| {
+        CFuture::new(fut.map(move |a| a.wrapping_mul(31) + i))
+    })
+}
+
+/// `bind_chain` as `bind` used to build it, with a new shared future per step
+fn bind_chain_boxed(start: CFuture<u64>) -> CFuture<u64> {
+    (0..DEPTH).fold(start, |fut, i| {
+        CFuture::new(fut.then(move |a| pure::<CFuture<_>>(a.wrapping_mul(31) + i)))
+    })
+}
+
+fn pending(a: u64) -> CFuture<u64> {
+    CFuture::new(async move { a })
+}
+
+fn bench_cfuture_chains(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cfuture_chains");
+    group.bench_function("fmap_ready", |b| {
+        b.iter(|| block_on(fmap_chain(CFuture::lazy(black_box(1)))))
+    });
+    group.bench_function("fmap_ready_boxed", |b| {
+        b.iter(|| block_on(fmap_chain_boxed(CFuture::lazy(black_box(1)))))
+    });
+    group.bench_function("fmap_pending", |b| {
+        b.iter(|| block_on(fmap_chain(pending(black_box(1)))))
+    });
+    group.bench_function("fmap_pending_boxed", |b| {
+        b.iter(|| block_on(fmap_chain_boxed(pending(black_box(1)))))
+    });
+    group.bench_function("bind_ready", |b| {
+        b.iter(|| block_on(bind_chain(CFuture::lazy(black_box(1)))))
+    });
+    group.bench_function("bind_ready_boxed", |b| {
+        b.iter(|| block_on(bind_chain_boxed(CFuture::lazy(black_box(1)))))
+    });
+    group.bench_function("bind_pending", |b| {
+        b.iter(|| block_on(bind_chain(pending(black_box(1)))))
+    });
+    group.bench_function("bind_pending_boxed", |b| {
+        b.iter(|| block_on(bind_chain_boxed(pending(black_box(1)))))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_cfuture_chains);
+criterion_main!(benches);
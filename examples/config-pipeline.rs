@@ -0,0 +1,98 @@
+//! Runs a pipeline described by a JSON or TOML spec over the lines read from stdin.
+//!
+//! ```text
+//! cargo run --example config-pipeline -- examples/pipeline.toml < input.txt
+//! ```
+use std::io::{BufRead, stdin};
+
+use rust_effects::typeclasses::free_effect::{
+    config::StepRegistry, free_bind::FreeBind, free_map::FreeMap,
+};
+
+fn registry() -> StepRegistry {
+    let mut registry = StepRegistry::new();
+    registry
+        .register("trim", |_| {
+            Ok(FreeMap::<String, String, Vec<String>>::new(|s| {
+                s.trim().to_string()
+            }))
+        })
+        .register("lowercase", |_| {
+            Ok(FreeMap::<String, String, Vec<String>>::new(|s| {
+                s.to_lowercase()
+            }))
+        })
+        .register("uppercase", |_| {
+            Ok(FreeMap::<String, String, Vec<String>>::new(|s| {
+                s.to_uppercase()
+            }))
+        })
+        .register("replace", |params| {
+            let from: String = params.get("from")?;
+            let to: String = params.get_or("to", String::new())?;
+            Ok(FreeMap::<String, String, Vec<String>>::new(move |s| {
+                s.replace(&from, &to)
+            }))
+        })
+        .register("skip_empty", |_| {
+            Ok(FreeBind::<String, String, Vec<String>>::new(|s| {
+                if s.is_empty() { vec![] } else { vec![s] }
+            }))
+        })
+        .register("contains", |params| {
+            let pattern: String = params.get("pattern")?;
+            Ok(FreeBind::<String, String, Vec<String>>::new(move |s| {
+                if s.contains(&pattern) {
+                    vec![s]
+                } else {
+                    vec![]
+                }
+            }))
+        })
+        .register("length", |_| {
+            Ok(FreeMap::<String, usize, Vec<String>>::new(|s| s.len()))
+        })
+        .register("at_least", |params| {
+            let min: usize = params.get("min")?;
+            Ok(FreeBind::<usize, usize, Vec<usize>>::new(move |n| {
+                if n >= min { vec![n] } else { vec![] }
+            }))
+        })
+        .register("to_string", |_| {
+            Ok(FreeMap::<usize, String, Vec<usize>>::new(|n| n.to_string()))
+        });
+    registry
+}
+
+fn main() {
+    let Some(path) = std::env::args().nth(1) else {
+        eprintln!("usage: config-pipeline <spec.json|spec.toml> < input");
+        std::process::exit(2);
+    };
+    let text = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+        eprintln!("could not read {}: {}", path, e);
+        std::process::exit(2);
+    });
+
+    let registry = registry();
+    let free = if path.ends_with(".json") {
+        registry.load_json::<Vec<String>, Vec<String>>(&text)
+    } else {
+        registry.load_toml::<Vec<String>, Vec<String>>(&text)
+    };
+    let free = free.unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        eprintln!("available steps: {}", registry.names().join(", "));
+        std::process::exit(1);
+    });
+    eprint!("{}", free.describe());
+
+    let lines = stdin()
+        .lock()
+        .lines()
+        .collect::<Result<Vec<_>, _>>()
+        .expect("could not read stdin");
+    for line in free.fold_map(lines) {
+        println!("{}", line);
+    }
+}
@@ -1,6 +1,6 @@
 use rust_effects::prelude::*;
 
-pub fn foo<'a, M>(input: impl Monad<u32, MonadT = String, MonadOut = M>) -> M
+pub fn foo<M>(input: impl Monad<u32, MonadT = String, MonadOut = M>) -> M
 where
     M: Monad<u32, MonadT = u32> + Monoid + Applicative<u32>,
 {
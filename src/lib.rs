@@ -1,8 +1,12 @@
+#[cfg(feature = "testing")]
+pub mod testing;
 pub mod typeclasses;
 pub mod types;
 
 pub mod prelude {
-    pub use {macros::*, typeclasses::*, types::*};
+    #[cfg(feature = "cfuture")]
+    pub use types::*;
+    pub use {macros::*, typeclasses::*};
     pub mod typeclasses {
         pub use crate::typeclasses::{
             applicative::{Applicative, pure},
@@ -14,7 +18,8 @@ pub mod prelude {
         };
     }
     pub mod types {
-        pub use crate::types::cfuture::CFuture;
+        #[cfg(feature = "cfuture")]
+        pub use crate::types::{cfuture::CFuture, cstream::CStream, once_future::OnceFuture};
     }
     pub mod macros {
         pub use crate::{lift_m1, lift_m2, pure};
@@ -0,0 +1,400 @@
+pub use crate::types::executor::yield_now;
+use crate::types::{cancel::Cancelled, cfuture::CFuture, clock::TestClock, rng::Rng};
+use futures::{FutureExt, channel::oneshot, future::BoxFuture};
+use std::{
+    any::Any,
+    cell::RefCell,
+    panic::{AssertUnwindSafe, catch_unwind, resume_unwind},
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard},
+    task::{Context, Poll, Wake, Waker},
+};
+
+struct ExecutorState {
+    rng: Rng,
+    /// Tasks by id; a task is taken out while it is being polled, and removed once done
+    tasks: Vec<Option<BoxFuture<'static, ()>>>,
+    /// Ids of the tasks woken since they were last polled, in the order they were woken
+    ready: Vec<usize>,
+    /// Ids of the tasks polled so far, in order
+    trace: Vec<usize>,
+}
+
+thread_local! {
+    /// The state of the executor polling a task on this thread, if any
+    static CURRENT: RefCell<Option<Arc<Mutex<ExecutorState>>>> = const { RefCell::new(None) };
+}
+
+fn lock(state: &Mutex<ExecutorState>) -> MutexGuard<'_, ExecutorState> {
+    state.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Add `fut` to the tasks of an executor, returning its id and a receiver of its
+/// result.
+fn start<A>(
+    state: &Mutex<ExecutorState>,
+    fut: CFuture<A>,
+) -> (usize, oneshot::Receiver<Result<A, Cancelled>>)
+where
+    A: Clone + Send + Sync + 'static,
+{
+    let (send, recv) = oneshot::channel();
+    let task = fut.into_outcome().map(|res| {
+        let _ = send.send(res);
+    });
+    let mut state = lock(state);
+    let id = state.tasks.len();
+    state.tasks.push(Some(task.boxed()));
+    state.ready.push(id);
+    (id, recv)
+}
+
+/// Run `fut` as a task of the executor polling the current task, if there is one.  The
+/// task is dropped along with the returned future, as a branch of `race` which lost
+/// would be.
+pub(crate) fn spawn_branch<A>(fut: CFuture<A>) -> Option<BoxFuture<'static, Result<A, Cancelled>>>
+where
+    A: Clone + Send + Sync + 'static,
+{
+    let state = CURRENT.with(|current| current.borrow().clone())?;
+    let (id, recv) = start(&state, fut);
+    Some(Branch { id, recv, state }.boxed())
+}
+
+/// The result of a task started by `spawn_branch`.
+struct Branch<A> {
+    id: usize,
+    recv: oneshot::Receiver<Result<A, Cancelled>>,
+    state: Arc<Mutex<ExecutorState>>,
+}
+
+impl<A> Future for Branch<A> {
+    type Output = Result<A, Cancelled>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.recv
+            .poll_unpin(cx)
+            .map(|res| res.unwrap_or(Err(Cancelled)))
+    }
+}
+
+impl<A> Drop for Branch<A> {
+    fn drop(&mut self) {
+        // Dropped outside of the lock, as dropping it may wake other tasks
+        let task = lock(&self.state).tasks[self.id].take();
+        drop(task);
+    }
+}
+
+struct TaskWaker {
+    id: usize,
+    state: Arc<Mutex<ExecutorState>>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+    fn wake_by_ref(self: &Arc<Self>) {
+        let mut state = lock(&self.state);
+        if !state.ready.contains(&self.id) {
+            state.ready.push(self.id);
+        }
+    }
+}
+
+/// A single-threaded executor which runs tasks in an order chosen by a seeded random
+/// generator, for testing concurrent `CFuture` programs.
+///
+/// Whenever several tasks are ready, the executor picks the next one to poll at
+/// random, so running a program with many seeds explores the ways its tasks can
+/// interleave, and running it again with a seed which failed replays the same schedule.
+///
+/// Tasks are the future given to `run`, those started with `spawn`, and the branches
+/// of the combinators which poll futures concurrently (`par_map2`, `par_combine`,
+/// `par_traverse`, `race` and the like), which each run as a task of their own.
+/// Sequential combinators such as `combine` or `lift_m2` await one future after the
+/// other, so there is no interleaving of theirs to explore.  Tasks only interleave where
+/// they await; `yield_now` adds scheduling points between steps which aren't otherwise
+/// async.
+///
+/// Time is virtual: `clock` is a `TestClock` which the executor advances to the next
+/// deadline whenever every task is waiting for it, so sleeps take no real time.  Tasks
+/// must not depend on other threads or runtimes to be woken (tokio timers, for
+/// instance); when no task can make progress and no sleep is waiting, `run` panics.
+///
+/// ```rust
+/// use rust_effects::prelude::*;
+/// use rust_effects::testing::executor::{TestExecutor, yield_now};
+/// use std::sync::{Arc, Mutex};
+///
+/// let log = Arc::new(Mutex::new(vec![]));
+/// let exec = TestExecutor::new(7);
+/// let step = |name: &'static str| {
+///     let log = log.clone();
+///     bind(yield_now(), move |_| {
+///         log.lock().unwrap().push(name);
+///         CFuture::lazy(vec![name])
+///     })
+/// };
+/// let both = CFuture::par_combine(step("a"), step("b"));
+/// // Results are combined in order, whichever branch ran first
+/// assert_eq!(exec.run(both), vec!["a", "b"]);
+/// assert_eq!(log.lock().unwrap().len(), 2);
+/// ```
+#[derive(Clone)]
+pub struct TestExecutor {
+    seed: u64,
+    state: Arc<Mutex<ExecutorState>>,
+    clock: TestClock,
+}
+
+impl TestExecutor {
+    pub fn new(seed: u64) -> TestExecutor {
+        TestExecutor {
+            seed,
+            state: Arc::new(Mutex::new(ExecutorState {
+                rng: Rng::new(seed),
+                tasks: vec![],
+                ready: vec![],
+                trace: vec![],
+            })),
+            clock: TestClock::new(),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The virtual clock of this executor.
+    pub fn clock(&self) -> TestClock {
+        self.clock.clone()
+    }
+
+    /// The ids of the tasks polled so far, in order.  Ids are given in the order tasks
+    /// are spawned, starting at 0, and the future given to `run` is a task too.
+    pub fn trace(&self) -> Vec<usize> {
+        self.state().trace.clone()
+    }
+
+    fn state(&self) -> MutexGuard<'_, ExecutorState> {
+        lock(&self.state)
+    }
+
+    /// Start running `fut` as a task of this executor, returning a future of its
+    /// result.  The task keeps running if the returned future is dropped, and is
+    /// cancelled if the executor stops before it finishes.
+    pub fn spawn<A>(&self, fut: CFuture<A>) -> CFuture<A>
+    where
+        A: Clone + Send + Sync + 'static,
+    {
+        let (_, recv) = start(&self.state, fut);
+        CFuture::from_outcome(recv.map(|res| res.unwrap_or(Err(Cancelled))), None)
+    }
+
+    /// Drop the remaining tasks, outside of the lock as dropping them may wake others.
+    fn stop(&self) {
+        let mut state = self.state();
+        state.ready.clear();
+        let tasks: Vec<_> = state.tasks.iter_mut().map(Option::take).collect();
+        drop(state);
+        drop(tasks);
+    }
+
+    /// Run `fut` to completion, along with the tasks it spawns, returning its result.
+    /// Tasks still running when `fut` finishes are dropped.
+    ///
+    /// A panic in any task is propagated.
+    pub fn run<A>(&self, fut: CFuture<A>) -> A
+    where
+        A: Clone + Send + Sync + 'static,
+    {
+        let mut main = self.spawn(fut);
+        let noop = Waker::noop();
+        loop {
+            if let Poll::Ready(a) = main.poll_unpin(&mut Context::from_waker(noop)) {
+                self.stop();
+                return a;
+            }
+            let next = {
+                let mut state = self.state();
+                match state.ready.len() {
+                    0 => None,
+                    n => {
+                        let pick = state.rng.below(n);
+                        let id = state.ready.remove(pick);
+                        state.trace.push(id);
+                        Some((id, state.tasks[id].take()))
+                    }
+                }
+            };
+            match next {
+                Some((id, Some(mut task))) => {
+                    let waker = Waker::from(Arc::new(TaskWaker {
+                        id,
+                        state: self.state.clone(),
+                    }));
+                    let outer = CURRENT.replace(Some(self.state.clone()));
+                    let polled = catch_unwind(AssertUnwindSafe(|| {
+                        task.poll_unpin(&mut Context::from_waker(&waker))
+                    }));
+                    CURRENT.set(outer);
+                    match polled {
+                        Ok(Poll::Pending) => self.state().tasks[id] = Some(task),
+                        Ok(Poll::Ready(())) => {}
+                        Err(panic) => {
+                            self.stop();
+                            resume_unwind(panic)
+                        }
+                    }
+                }
+                // Woken after it finished
+                Some((_, None)) => {}
+                None if self.clock.advance_to_next() => {}
+                None => {
+                    self.stop();
+                    panic!(
+                        "no task can make progress (seed {}): they are waiting on something \
+                         other than this executor",
+                        self.seed
+                    )
+                }
+            }
+        }
+    }
+}
+
+/// Run `test` on a new executor for each seed in `seeds`, panicking with the seed which
+/// failed so that it can be replayed with `TestExecutor::new(seed)`.
+pub fn explore(seeds: impl IntoIterator<Item = u64>, test: impl Fn(&TestExecutor)) {
+    for seed in seeds {
+        let exec = TestExecutor::new(seed);
+        if let Err(panic) = catch_unwind(AssertUnwindSafe(|| test(&exec))) {
+            panic!("failed with seed {}: {}", seed, panic_message(&panic));
+        }
+    }
+}
+
+fn panic_message(panic: &Box<dyn Any + Send>) -> &str {
+    match panic.downcast_ref::<&str>() {
+        Some(msg) => msg,
+        None => panic.downcast_ref::<String>().map_or("", String::as_str),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prelude::*;
+    use crate::types::{cfuture::Either, clock::Clock};
+    use std::time::Duration;
+
+    /// Increments `counter` with a read and a write in separate steps, as a racy
+    /// read-modify-write would.
+    fn increment(counter: &Arc<Mutex<u32>>) -> CFuture<u32> {
+        let counter = counter.clone();
+        let read = *counter.lock().unwrap();
+        bind(yield_now(), move |_| {
+            *counter.lock().unwrap() = read + 1;
+            CFuture::lazy(read + 1)
+        })
+    }
+
+    fn racy(exec: &TestExecutor) -> u32 {
+        let counter = Arc::new(Mutex::new(0));
+        let c = counter.clone();
+        let a = exec.spawn(CFuture::new(async move { increment(&c).await }));
+        let c = counter.clone();
+        let b = exec.spawn(CFuture::new(async move { increment(&c).await }));
+        let add = lift_m2::<CFuture<_>, _, _>(|x: u32, y: u32| x + y);
+        exec.run(add(a, b));
+        *counter.lock().unwrap()
+    }
+
+    #[test]
+    fn test_explores_interleavings() {
+        let totals: Vec<_> = (0..20).map(|seed| racy(&TestExecutor::new(seed))).collect();
+        // Some schedules lose an update, others don't
+        assert!(totals.contains(&1));
+        assert!(totals.contains(&2));
+    }
+
+    #[test]
+    fn test_replays_seed() {
+        for seed in 0..10 {
+            let (first, second) = (TestExecutor::new(seed), TestExecutor::new(seed));
+            assert_eq!(racy(&first), racy(&second));
+            assert_eq!(first.trace(), second.trace());
+        }
+    }
+
+    #[test]
+    fn test_explore_reports_seed() {
+        let failure = catch_unwind(|| explore(0..20, |exec| assert_eq!(racy(exec), 2)));
+        let message = failure.unwrap_err();
+        let message = panic_message(&message);
+        let seed: u64 = message
+            .strip_prefix("failed with seed ")
+            .and_then(|rest| rest.split(':').next())
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(racy(&TestExecutor::new(seed)), 1);
+    }
+
+    #[test]
+    fn test_explores_branches() {
+        let orders: Vec<_> = (0..20)
+            .map(|seed| {
+                let log = Arc::new(Mutex::new(vec![]));
+                let step = |name: &'static str| {
+                    let log = log.clone();
+                    bind(yield_now(), move |_| {
+                        log.lock().unwrap().push(name);
+                        CFuture::lazy(vec![name])
+                    })
+                };
+                let both = CFuture::par_combine(step("a"), step("b"));
+                assert_eq!(TestExecutor::new(seed).run(both), vec!["a", "b"]);
+                log.lock().unwrap().clone()
+            })
+            .collect();
+        // Without `spawn`, the branches still run in either order
+        assert!(orders.contains(&vec!["a", "b"]));
+        assert!(orders.contains(&vec!["b", "a"]));
+    }
+
+    #[test]
+    fn test_race_drops_losing_branch() {
+        let exec = TestExecutor::new(0);
+        let clock = exec.clock();
+        let slow = fmap(clock.sleep(Duration::from_secs(60)), |_| 1);
+        let fast = fmap(clock.sleep(Duration::from_secs(1)), |_| 2);
+        let first = bind(CFuture::race(slow, fast), move |winner| {
+            // The losing branch's task no longer waits on the clock
+            assert_eq!(clock.sleepers(), 0);
+            CFuture::lazy(winner)
+        });
+        assert_eq!(exec.run(first), Either::Right(2));
+    }
+
+    #[test]
+    fn test_virtual_time() {
+        let exec = TestExecutor::new(0);
+        let clock = exec.clock();
+        let slow = exec.spawn(fmap(clock.sleep(Duration::from_secs(60)), |_| 1));
+        let fast = exec.spawn(fmap(clock.sleep(Duration::from_secs(1)), |_| 2));
+        let first = CFuture::race(slow, fast);
+        assert_eq!(exec.run(first), Either::Right(2));
+        assert_eq!(clock.monotonic().wait(), Duration::from_secs(1));
+        assert_eq!(clock.sleepers(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "no task can make progress")]
+    fn test_stuck() {
+        let exec = TestExecutor::new(0);
+        exec.run(CFuture::new(futures::future::pending::<()>()));
+    }
+}
@@ -0,0 +1,624 @@
+use std::{
+    any::{Any, type_name},
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::typeclasses::{
+    free_effect::{
+        FreeEffect,
+        describe::StepInfo,
+        effect_list::EffectList,
+        free::Free,
+        free_bind::FreeBind,
+        free_map::FreeMap,
+        fuse::{FusedMap, MapChain},
+        identity::Identity,
+    },
+    functor::Functor,
+    monad::Monad,
+};
+
+/// A stub, called with the step's input and the callback recording the call
+type Stub<In, Out> = Arc<dyn Fn(In, OnRun) -> Out + Send + Sync>;
+type Capture<In> = Arc<dyn Fn(&In) -> Box<dyn Any + Send> + Send + Sync>;
+type OnRun = Arc<dyn Fn() + Send + Sync>;
+type RunFold<Eff> = fn(&Eff, <Eff as FreeEffect>::In, OnRun) -> <Eff as FreeEffect>::Out;
+
+/// A single recorded run of a step.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Call {
+    pub kind: &'static str,
+    pub label: Option<String>,
+    pub stubbed: bool,
+}
+
+impl Call {
+    /// The label if one was given, otherwise the kind of the step
+    pub fn name(&self) -> &str {
+        self.label.as_deref().unwrap_or(self.kind)
+    }
+}
+
+#[derive(Default)]
+struct HarnessState {
+    stubs: HashMap<String, Box<dyn Any + Send>>,
+    captures: HashMap<String, Box<dyn Any + Send>>,
+    inputs: HashMap<String, Vec<Box<dyn Any + Send>>>,
+    calls: Vec<Call>,
+}
+
+/// Test double support for `Free` pipelines.
+///
+/// `instrument` wraps every step of a pipeline in a `Spied` effect which records a call
+/// with the harness whenever the step runs, and which runs a stub instead of the step
+/// when one was registered for the step's label.  A map or bind step counts as run
+/// when a value reaches it, at most once per `fold`: a step after a `None` or an `Err`
+/// isn't recorded, nor is a step of a `CFuture` which was never polled.  Stubs are
+/// recorded the same way, except the ones given to `replace`, which are recorded when
+/// folded since they replace the whole `fold`.  Stubs take and return the same monads
+/// as the steps they replace, so a `FreeBind` hitting a database inside a `CFuture` can
+/// be replaced by one returning a canned value.
+///
+/// Harnesses are cheap to clone; all clones share the same stubs and records.
+///
+/// ```rust
+/// use rust_effects::{testing::harness::Harness, typeclasses::free_effect::free::Free};
+///
+/// let free = Free::<Option<u32>, ()>::new()
+///     .bind_labeled("lookup", |id| if id == 1 { Some("db user") } else { None })
+///     .map_labeled("greet", |name: &str| format!("hello {}", name));
+///
+/// let harness = Harness::new();
+/// harness.replace_bind::<Option<u32>, &str>("lookup", |_id| Some("stub user"));
+/// harness.capture_inputs::<Option<u32>>("lookup");
+/// let free = harness.instrument(free);
+///
+/// assert_eq!(free.fold_map(Some(7)), Some("hello stub user".to_string()));
+/// assert_eq!(harness.inputs::<Option<u32>>("lookup"), vec![Some(7)]);
+/// harness.assert_order(&["lookup", "greet"]);
+/// ```
+#[derive(Clone, Default)]
+pub struct Harness {
+    state: Arc<Mutex<HarnessState>>,
+}
+
+impl Harness {
+    pub fn new() -> Harness {
+        Harness::default()
+    }
+
+    fn state(&self) -> MutexGuard<'_, HarnessState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Wrap every step of a pipeline so it is recorded, and can be stubbed, by this harness.
+    pub fn instrument<M, U, Eff>(&self, free: Free<M, U, Eff>) -> Free<M, U, Eff::Instrumented>
+    where
+        Eff: Instrument<In = M>,
+        U: Send,
+        M: Monad<U>,
+    {
+        Free::new_effect(free.start_effect.instrument(self))
+    }
+
+    /// Wrap a single effect so it is recorded, and can be stubbed, by this harness.
+    /// As the harness can't see inside a custom effect, it is recorded whenever its
+    /// `fold` runs.
+    pub fn spy<Eff: FreeEffect>(&self, effect: Eff) -> Spied<Eff> {
+        self.spy_with(effect, |effect, source, on_run| {
+            on_run();
+            effect.fold(source)
+        })
+    }
+
+    /// Spy on a map or bind step through its own `fold`, recording a run whenever a
+    /// value reaches the step, just before its function runs.
+    fn spy_step<Eff, T>(&self, effect: Eff) -> Spied<Eff>
+    where
+        Eff: FreeEffect,
+        Eff::In: Functor<T, FuncT = T, FunctorOut = Eff::In>,
+    {
+        self.spy_with(effect, |effect, source, on_run| {
+            effect.fold(Functor::fmap(source, move |t| {
+                on_run();
+                t
+            }))
+        })
+    }
+
+    fn spy_with<Eff: FreeEffect>(&self, effect: Eff, run: RunFold<Eff>) -> Spied<Eff> {
+        Spied {
+            effect,
+            harness: self.clone(),
+            run,
+        }
+    }
+
+    /// Replace the step labelled `label` with a function from its input monad to its
+    /// output monad.  Running an instrumented step whose monads don't match panics.
+    pub fn replace<In, Out>(
+        &self,
+        label: impl Into<String>,
+        stub: impl Fn(In) -> Out + Send + Sync + 'static,
+    ) where
+        In: 'static,
+        Out: 'static,
+    {
+        self.replace_with(label, move |m, on_run: OnRun| {
+            on_run();
+            stub(m)
+        })
+    }
+
+    fn replace_with<In, Out>(
+        &self,
+        label: impl Into<String>,
+        stub: impl Fn(In, OnRun) -> Out + Send + Sync + 'static,
+    ) where
+        In: 'static,
+        Out: 'static,
+    {
+        let stub: Stub<In, Out> = Arc::new(stub);
+        self.state().stubs.insert(label.into(), Box::new(stub));
+    }
+
+    /// Replace the step labelled `label` by an `fmap` of the given function over its
+    /// input monad `In`.
+    pub fn replace_map<In, U>(
+        &self,
+        label: impl Into<String>,
+        func: impl Fn(In::MonadT) -> U + Send + Sync + Clone + 'static,
+    ) where
+        In: Monad<U> + 'static,
+        In::MonadOut: 'static,
+    {
+        self.replace_with(label, move |m: In, on_run: OnRun| {
+            let func = func.clone();
+            In::fmap(m, move |t| {
+                on_run();
+                func(t)
+            })
+        })
+    }
+
+    /// Replace the step labelled `label` by a `bind` of the given function over its
+    /// input monad `In`.
+    pub fn replace_bind<In, U>(
+        &self,
+        label: impl Into<String>,
+        func: impl Fn(In::MonadT) -> In::MonadOut + Send + Sync + Clone + 'static,
+    ) where
+        In: Monad<U> + 'static,
+        In::MonadOut: 'static,
+    {
+        self.replace_with(label, move |m: In, on_run: OnRun| {
+            let func = func.clone();
+            In::bind(m, move |t| {
+                on_run();
+                func(t)
+            })
+        })
+    }
+
+    /// Remove all stubs, so instrumented steps run their own `fold` again.
+    pub fn clear_stubs(&self) {
+        self.state().stubs.clear();
+    }
+
+    /// Keep a clone of every input monad given to the step labelled `label`.
+    pub fn capture_inputs<In>(&self, label: impl Into<String>)
+    where
+        In: Clone + Send + 'static,
+    {
+        let capture: Capture<In> = Arc::new(|m: &In| Box::new(m.clone()));
+        self.state()
+            .captures
+            .insert(label.into(), Box::new(capture));
+    }
+
+    /// The captured inputs of the step labelled `label`, in call order.
+    pub fn inputs<In>(&self, label: &str) -> Vec<In>
+    where
+        In: Clone + 'static,
+    {
+        self.state()
+            .inputs
+            .get(label)
+            .map(|inputs| {
+                inputs
+                    .iter()
+                    .map(|m| {
+                        m.downcast_ref::<In>()
+                            .unwrap_or_else(|| {
+                                panic!("inputs of step '{}' are not {}", label, type_name::<In>())
+                            })
+                            .clone()
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// All recorded calls, in order.
+    pub fn calls(&self) -> Vec<Call> {
+        self.state().calls.clone()
+    }
+
+    pub fn call_count(&self, label: &str) -> usize {
+        self.state()
+            .calls
+            .iter()
+            .filter(|call| call.label.as_deref() == Some(label))
+            .count()
+    }
+
+    /// Clear the recorded calls and captured inputs, keeping stubs and captures.
+    pub fn reset(&self) {
+        let mut state = self.state();
+        state.calls.clear();
+        state.inputs.clear();
+    }
+
+    pub fn assert_called(&self, label: &str) {
+        assert!(
+            self.call_count(label) > 0,
+            "step '{}' was not called",
+            label
+        );
+    }
+
+    pub fn assert_not_called(&self, label: &str) {
+        let count = self.call_count(label);
+        assert_eq!(count, 0, "step '{}' was called {} time(s)", label, count);
+    }
+
+    /// Assert the labelled steps ran in exactly this order.  Unlabelled steps are ignored.
+    pub fn assert_order(&self, labels: &[&str]) {
+        let calls = self.calls();
+        let ran = calls
+            .iter()
+            .filter_map(|call| call.label.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(ran, labels, "labelled steps ran in an unexpected order");
+    }
+
+    fn stub<In: 'static, Out: 'static>(&self, label: &str) -> Option<Stub<In, Out>> {
+        self.state().stubs.get(label).map(|stub| {
+            stub.downcast_ref::<Stub<In, Out>>()
+                .unwrap_or_else(|| {
+                    panic!(
+                        "stub for step '{}' must be a function from {} to {}",
+                        label,
+                        type_name::<In>(),
+                        type_name::<Out>()
+                    )
+                })
+                .clone()
+        })
+    }
+
+    /// A clone of `source` if the inputs of the step labelled `label` are captured.
+    fn capture<In: 'static>(
+        &self,
+        label: Option<&str>,
+        source: &In,
+    ) -> Option<Box<dyn Any + Send>> {
+        let label = label?;
+        let state = self.state();
+        let capture = state.captures.get(label)?;
+        let capture = capture
+            .downcast_ref::<Capture<In>>()
+            .unwrap_or_else(|| panic!("inputs of step '{}' are not {}", label, type_name::<In>()));
+        Some(capture(source))
+    }
+
+    fn record(&self, call: Call, captured: Option<Box<dyn Any + Send>>) {
+        let mut state = self.state();
+        if let (Some(label), Some(captured)) = (&call.label, captured) {
+            state
+                .inputs
+                .entry(label.clone())
+                .or_default()
+                .push(captured);
+        }
+        state.calls.push(call);
+    }
+}
+
+/// A step wrapped by a `Harness`, see `Harness::spy` and `Harness::instrument`.
+pub struct Spied<Eff: FreeEffect> {
+    effect: Eff,
+    harness: Harness,
+    /// Folds the effect, calling the callback when the step runs
+    run: RunFold<Eff>,
+}
+
+impl<Eff> FreeEffect for Spied<Eff>
+where
+    Eff: FreeEffect,
+    Eff::In: 'static,
+    Eff::Out: 'static,
+{
+    type InU = Eff::InU;
+    type OutU = Eff::OutU;
+    type In = Eff::In;
+    type Out = Eff::Out;
+    fn fold(&self, source: Self::In) -> Self::Out {
+        let info = StepInfo::new(self.effect.kind(), self.effect.label());
+        let stub = info
+            .label
+            .as_deref()
+            .and_then(|label| self.harness.stub::<Eff::In, Eff::Out>(label));
+        let captured = self.harness.capture(info.label.as_deref(), &source);
+        let call = Call {
+            kind: info.kind,
+            label: info.label,
+            stubbed: stub.is_some(),
+        };
+        // Recorded the first time the step runs, if it does
+        let harness = self.harness.clone();
+        let pending = Mutex::new(Some((call, captured)));
+        let on_run: OnRun = Arc::new(move || {
+            let pending = pending.lock().unwrap_or_else(|e| e.into_inner()).take();
+            if let Some((call, captured)) = pending {
+                harness.record(call, captured);
+            }
+        });
+        match stub {
+            Some(stub) => stub(source, on_run),
+            None => (self.run)(&self.effect, source, on_run),
+        }
+    }
+    fn label(&self) -> Option<&str> {
+        self.effect.label()
+    }
+    fn kind(&self) -> &'static str {
+        self.effect.kind()
+    }
+    fn describe(&self, steps: &mut Vec<StepInfo>) {
+        self.effect.describe(steps)
+    }
+}
+
+/// Effects whose steps can be wrapped by a `Harness`.
+///
+/// Implemented for `FreeMap`, `FreeBind`, `FusedMap`, `Identity` (which is left
+/// unwrapped) and `EffectList`.  A custom effect can implement it by returning `harness.spy(self)`.
+pub trait Instrument: FreeEffect {
+    type Instrumented: FreeEffect<In = Self::In, Out = Self::Out, InU = Self::InU, OutU = Self::OutU>;
+    fn instrument(self, harness: &Harness) -> Self::Instrumented;
+}
+
+impl<M, U> Instrument for Identity<M, U>
+where
+    U: Send,
+    M: Monad<U>,
+{
+    type Instrumented = Self;
+    fn instrument(self, _harness: &Harness) -> Self::Instrumented {
+        self
+    }
+}
+
+impl<T, U, In> Instrument for FreeMap<T, U, In>
+where
+    T: Send + 'static,
+    U: Send + 'static,
+    In: Monad<U, MonadT = T> + Functor<T, FuncT = T, FunctorOut = In> + Send + 'static,
+    In::MonadOut: 'static,
+{
+    type Instrumented = Spied<Self>;
+    fn instrument(self, harness: &Harness) -> Self::Instrumented {
+        harness.spy_step::<Self, T>(self)
+    }
+}
+
+impl<T, U, In, F> Instrument for FusedMap<T, U, In, F>
+where
+    T: Send + 'static,
+    U: Send + 'static,
+    In: Monad<U, MonadT = T> + Functor<T, FuncT = T, FunctorOut = In> + Send + 'static,
+    In::MonadOut: 'static,
+    F: MapChain<T, U>,
+{
+    type Instrumented = Spied<Self>;
+    fn instrument(self, harness: &Harness) -> Self::Instrumented {
+        harness.spy_step::<Self, T>(self)
+    }
+}
+
+impl<T, U, In> Instrument for FreeBind<T, U, In>
+where
+    In: Monad<U, MonadT = T> + Functor<T, FuncT = T, FunctorOut = In> + Send + 'static,
+    T: Send + 'static,
+    U: Send + 'static,
+{
+    type Instrumented = Spied<Self>;
+    fn instrument(self, harness: &Harness) -> Self::Instrumented {
+        harness.spy_step::<Self, T>(self)
+    }
+}
+
+impl<Eff> Instrument for Spied<Eff>
+where
+    Eff: FreeEffect,
+    Eff::In: 'static,
+    Eff::Out: 'static,
+{
+    type Instrumented = Self;
+    fn instrument(self, _harness: &Harness) -> Self::Instrumented {
+        self
+    }
+}
+
+impl<CurrEff, NestEff> Instrument for EffectList<CurrEff, NestEff>
+where
+    CurrEff: Instrument<In = NestEff::Out>,
+    NestEff: Instrument,
+{
+    type Instrumented = EffectList<CurrEff::Instrumented, NestEff::Instrumented>;
+    fn instrument(self, harness: &Harness) -> Self::Instrumented {
+        let (curr_effect, next_effect) = self.into_pair();
+        EffectList::from_pair(
+            curr_effect.instrument(harness),
+            next_effect.instrument(harness),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[cfg(feature = "cfuture")]
+    use crate::prelude::*;
+
+    #[cfg(feature = "cfuture")]
+    fn load_user(id: u32) -> CFuture<String> {
+        CFuture::new(async move { panic!("tried to reach the database for user {}", id) })
+    }
+
+    #[cfg(feature = "cfuture")]
+    fn user_pipeline() -> Free<
+        CFuture<u32>,
+        u32,
+        impl Instrument<In = CFuture<u32>, Out = CFuture<String>, InU = u32, OutU = String>,
+    > {
+        Free::<CFuture<u32>, u32>::new()
+            .map_labeled("normalise id", |id: u32| id % 100)
+            .bind_labeled("load user", load_user)
+            .map_labeled("greet", |name: String| format!("hello {}", name))
+    }
+
+    #[cfg(feature = "cfuture")]
+    #[tokio::test]
+    async fn test_stub_future_bind() {
+        let harness = Harness::new();
+        harness.replace_bind::<CFuture<u32>, String>("load user", |id| {
+            CFuture::lazy(format!("user{}", id))
+        });
+        harness.capture_inputs::<CFuture<u32>>("normalise id");
+        let free = harness.instrument(user_pipeline());
+
+        assert_eq!(free.fold_map(CFuture::lazy(142)).await, "hello user42");
+        harness.assert_order(&["normalise id", "load user", "greet"]);
+        assert_eq!(
+            harness
+                .calls()
+                .iter()
+                .map(|c| c.stubbed)
+                .collect::<Vec<_>>(),
+            vec![false, true, false]
+        );
+
+        let inputs = harness.inputs::<CFuture<u32>>("normalise id");
+        assert_eq!(inputs.len(), 1);
+        assert_eq!(inputs[0].clone().await, 142);
+    }
+
+    #[test]
+    fn test_records_calls_without_stubs() {
+        let free = Free::<Vec<&str>, ()>::new()
+            .map_labeled("length", |s: &str| s.len())
+            .bind(|n| if n > 2 { vec![n] } else { vec![] })
+            .map_labeled("double", |n: usize| n * 2);
+        let harness = Harness::new();
+        harness.capture_inputs::<Vec<usize>>("double");
+        let free = harness.instrument(free);
+
+        assert_eq!(free.fold_map(vec!["ox", "fox"]), vec![6]);
+        assert_eq!(free.fold_map(vec!["horse"]), vec![10]);
+        assert_eq!(harness.call_count("length"), 2);
+        assert_eq!(harness.calls()[1].name(), "bind");
+        assert_eq!(
+            harness.inputs::<Vec<usize>>("double"),
+            vec![vec![3], vec![5]]
+        );
+        harness.assert_order(&["length", "double", "length", "double"]);
+
+        harness.reset();
+        harness.assert_not_called("length");
+    }
+
+    #[test]
+    fn test_fused_steps_recorded_once() {
+        let free = Free::<Vec<&str>, ()>::new()
+            .map_labeled("length", |s: &str| s.len())
+            .map_labeled("double", |n: usize| n * 2)
+            .bind(|n| if n > 4 { vec![n] } else { vec![] })
+            .map_labeled("halve", |n: usize| n / 2)
+            .fuse();
+        let harness = Harness::new();
+        let free = harness.instrument(free);
+
+        assert_eq!(free.fold_map(vec!["ox", "fox"]), vec![3]);
+        harness.assert_order(&["length + double", "halve"]);
+    }
+
+    #[test]
+    fn test_short_circuited_steps_not_recorded() {
+        let free = Free::<Option<u32>, ()>::new()
+            .bind_labeled("check", |n| if n > 10 { Some(n) } else { None })
+            .map_labeled("after", |n: u32| n * 2);
+        let harness = Harness::new();
+        harness.capture_inputs::<Option<u32>>("after");
+        let free = harness.instrument(free);
+
+        assert_eq!(free.fold_map(Some(1)), None);
+        assert_eq!(free.fold_map(None), None);
+        harness.assert_order(&["check"]);
+        assert!(harness.inputs::<Option<u32>>("after").is_empty());
+
+        let free = harness.instrument(
+            Free::<Result<u32, String>, ()>::new().map_labeled("parse", |n: u32| n + 1),
+        );
+        assert_eq!(
+            free.fold_map(Err("bad".to_string())),
+            Err("bad".to_string())
+        );
+        harness.assert_not_called("parse");
+        assert_eq!(free.fold_map(Ok(1)), Ok(2));
+        harness.assert_called("parse");
+    }
+
+    #[cfg(feature = "cfuture")]
+    #[tokio::test]
+    async fn test_unpolled_future_not_recorded() {
+        let harness = Harness::new();
+        harness.replace_bind::<CFuture<u32>, String>("load user", |id| {
+            CFuture::lazy(format!("user{}", id))
+        });
+        let free = harness.instrument(user_pipeline());
+
+        let out = free.fold_map(CFuture::new(async { 7 }));
+        drop(out);
+        assert!(harness.calls().is_empty());
+
+        let out = free.fold_map(CFuture::new(async { 7 }));
+        assert!(harness.calls().is_empty());
+        assert_eq!(out.await, "hello user7");
+        harness.assert_order(&["normalise id", "load user", "greet"]);
+    }
+
+    #[test]
+    fn test_replace_custom_spied_effect() {
+        let harness = Harness::new();
+        let step = harness.spy(FreeMap::<u32, u32, Option<u32>>::new(|n| n + 1).with_label("inc"));
+        harness.replace("inc", |m: Option<u32>| m.map(|n| n + 100));
+
+        assert_eq!(step.fold(Some(1)), Some(101));
+        harness.clear_stubs();
+        assert_eq!(step.fold(Some(1)), Some(2));
+        assert_eq!(harness.calls().len(), 2);
+        harness.assert_called("inc");
+    }
+
+    #[test]
+    #[should_panic(expected = "stub for step 'inc' must be a function")]
+    fn test_mismatched_stub_panics() {
+        let harness = Harness::new();
+        let step = harness.spy(FreeMap::<u32, u32, Option<u32>>::new(|n| n + 1).with_label("inc"));
+        harness.replace("inc", |m: Vec<u32>| m);
+        step.fold(Some(1));
+    }
+}
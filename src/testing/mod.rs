@@ -0,0 +1,3 @@
+#[cfg(feature = "cfuture")]
+pub mod executor;
+pub mod harness;
@@ -113,7 +113,7 @@ pub trait ApplicativeFunctor<Func: Fn(Self::AppFuncT) -> U, U = ()>: Applicative
 /// use rust_effects::prelude::seq;
 /// assert_eq!(seq(Some(3), Some(|a| a + 3)), Some(6));
 /// ```
-pub fn seq<'a, A, M, U>(m: A, func: A::AppFuncFn) -> A::AppFuncOut
+pub fn seq<A, M, U>(m: A, func: A::AppFuncFn) -> A::AppFuncOut
 where
     A: ApplicativeFunctor<M, U>,
     M: Fn(A::AppFuncT) -> U,
@@ -0,0 +1,516 @@
+use std::{sync::Arc, vec::IntoIter};
+
+use crate::prelude::typeclasses::*;
+
+/// An instruction which can be lifted into a `FreeApplicative`.
+///
+/// All the instructions of one kind produce the same `Output` type when interpreted (e.g.
+/// the raw text of a form field); typed values are obtained by mapping over the lifted
+/// instruction.  This is a limitation: the output type can't depend on the instruction,
+/// so instructions whose results have different types need an `Output` enum with a
+/// variant per type, and the function mapped over each lifted instruction has to pick
+/// its variant, failing at run time if an interpreter replied with another one.
+pub trait Instruction {
+    type Output;
+}
+
+type Run<O, A> = Box<dyn Fn(&mut IntoIter<O>) -> A + Send>;
+
+/// Function type used to accumulate results while folding into another Applicative.
+/// It is `Clone`, as Applicatives such as `CFuture` clone the functions they hold.
+pub type Accumulate<O> = Box<dyn AccumulateFn<O>>;
+
+/// The functions boxed in `Accumulate`, which can be cloned through the box.
+pub trait AccumulateFn<O>: Fn(O) -> Outputs<O> + Send + Sync {
+    fn clone_box(&self) -> Accumulate<O>;
+}
+
+impl<O, F> AccumulateFn<O> for F
+where
+    F: Fn(O) -> Outputs<O> + Clone + Send + Sync + 'static,
+{
+    fn clone_box(&self) -> Accumulate<O> {
+        Box::new(self.clone())
+    }
+}
+
+impl<O: 'static> Clone for Accumulate<O> {
+    fn clone(&self) -> Self {
+        (**self).clone_box()
+    }
+}
+
+/// The instruction outputs accumulated by `FreeApplicative::fold_map`.
+///
+/// `Accumulate` functions may be called several times on the same accumulator (by the
+/// `Vec` Applicative, for instance), so it is a persistent list, newest output first,
+/// which is extended without copying the outputs before it.
+pub struct Outputs<O>(Option<Arc<(Outputs<O>, O)>>);
+
+impl<O> Clone for Outputs<O> {
+    fn clone(&self) -> Self {
+        Outputs(self.0.clone())
+    }
+}
+
+impl<O> Outputs<O> {
+    fn new() -> Outputs<O> {
+        Outputs(None)
+    }
+
+    fn push(&self, out: O) -> Outputs<O> {
+        Outputs(Some(Arc::new((self.clone(), out))))
+    }
+
+    /// The outputs in program order, only cloning the ones shared with another list.
+    fn into_vec(mut self) -> Vec<O>
+    where
+        O: Clone,
+    {
+        let mut outputs = vec![];
+        while let Some(node) = self.0.take() {
+            let (rest, out) = Arc::try_unwrap(node).unwrap_or_else(|node| (*node).clone());
+            outputs.push(out);
+            self = rest;
+        }
+        outputs.reverse();
+        outputs
+    }
+}
+
+impl<O> Drop for Outputs<O> {
+    /// Unlink the list one node at a time, so dropping a long list doesn't recurse.
+    fn drop(&mut self) {
+        let mut next = self.0.take();
+        while let Some(node) = next {
+            next = Arc::try_unwrap(node)
+                .ok()
+                .and_then(|(mut rest, _)| rest.0.take());
+        }
+    }
+}
+
+/// The free Applicative over instructions of type `F`, producing an `A`.
+///
+/// Programs are built only from `lift`ed instructions, `pure`, `fmap` and `seq`, so unlike
+/// a monadic program the whole set of instructions is known before anything runs.  This
+/// makes it possible to inspect a program (list the fields a form needs, count the
+/// operations, generate help text) with `analyze`, and then run it by interpreting each
+/// instruction into some other Applicative with `fold_map`.
+///
+/// Instructions are kept in program order: in `seq(m, func)`, the instructions of `func`
+/// come before the ones of `m`, which matches the argument order of a lifted function.
+///
+/// ```rust
+/// use rust_effects::prelude::*;
+/// use rust_effects::typeclasses::free_applicative::{FreeApplicative, Instruction};
+///
+/// struct Field(&'static str);
+/// impl Instruction for Field {
+///     type Output = String;
+/// }
+///
+/// let name = FreeApplicative::lift(Field("name"));
+/// let age = fmap(FreeApplicative::lift(Field("age")), |s: String| s.parse::<u32>().ok());
+/// let person = seq(age, fmap(name, |n: String| move |a: Option<u32>| (n.clone(), a)));
+///
+/// assert_eq!(person.analyze(|f| vec![f.0]), vec!["name", "age"]);
+/// let answer = person.fold_map(|f: Field| if f.0 == "name" { Some("Ann".to_string()) } else { Some("33".to_string()) });
+/// assert_eq!(answer, Some(("Ann".to_string(), Some(33))));
+/// ```
+pub struct FreeApplicative<F: Instruction, A> {
+    ops: Vec<F>,
+    run: Run<F::Output, A>,
+}
+
+impl<F, A> FreeApplicative<F, A>
+where
+    F: Instruction,
+    F::Output: 'static,
+    A: 'static,
+{
+    /// A program with no instructions producing `a`.
+    pub fn pure(a: A) -> FreeApplicative<F, A>
+    where
+        A: Clone + Send,
+    {
+        FreeApplicative {
+            ops: vec![],
+            run: Box::new(move |_| a.clone()),
+        }
+    }
+
+    /// The instructions of the program, in order.
+    pub fn instructions(&self) -> &[F] {
+        &self.ops
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Statically analyze the program by mapping each instruction into a Monoid and
+    /// combining the results in program order.  Nothing is run.
+    pub fn analyze<M: Monoid>(&self, func: impl Fn(&F) -> M) -> M {
+        self.ops
+            .iter()
+            .fold(M::empty(), |acc, op| M::combine(acc, func(op)))
+    }
+
+    /// Run the program, giving each instruction its output directly.
+    pub fn interpret(self, func: impl FnMut(F) -> F::Output) -> A {
+        let outputs: Vec<F::Output> = self.ops.into_iter().map(func).collect();
+        (self.run)(&mut outputs.into_iter())
+    }
+
+    /// Run the program by interpreting each instruction into the Applicative `G`.
+    ///
+    /// The results of the instructions are accumulated with `seq`, using boxed
+    /// functions (`Accumulate`), so `G` must accept those as its function type.  Each
+    /// instruction costs one `seq` and one `fmap` of `G`, whatever the number of
+    /// instructions before it.
+    pub fn fold_map<G, GV, GA>(self, mut func: impl FnMut(F) -> G) -> GA
+    where
+        F::Output: Clone + Send + Sync,
+        G: ApplicativeFunctor<
+                Accumulate<F::Output>,
+                Outputs<F::Output>,
+                AppFuncT = F::Output,
+                AppFuncOut = GV,
+            >,
+        G::AppFuncFn: 'static,
+        GV: Applicative<A, AppT = Outputs<F::Output>, FunctorOut = GA>
+            + Functor<Accumulate<F::Output>, FuncT = Outputs<F::Output>, FunctorOut = G::AppFuncFn>,
+    {
+        let run = self.run;
+        let collected = self
+            .ops
+            .into_iter()
+            .fold(GV::pure(Outputs::new()), |acc, op| {
+                let push = fmap(acc, |outputs: Outputs<F::Output>| {
+                    Box::new(move |out| outputs.push(out)) as Accumulate<F::Output>
+                });
+                G::seq(func(op), push)
+            });
+        fmap(collected, move |outputs: Outputs<F::Output>| {
+            run(&mut outputs.into_vec().into_iter())
+        })
+    }
+}
+
+impl<F> FreeApplicative<F, F::Output>
+where
+    F: Instruction,
+    F::Output: 'static,
+{
+    /// Lift a single instruction into a program producing its output.
+    pub fn lift(op: F) -> FreeApplicative<F, F::Output> {
+        FreeApplicative {
+            ops: vec![op],
+            run: Box::new(|outputs| {
+                outputs
+                    .next()
+                    .expect("FreeApplicative ran out of instruction outputs")
+            }),
+        }
+    }
+}
+
+impl<F, T, U> Functor<U> for FreeApplicative<F, T>
+where
+    F: Instruction,
+    F::Output: 'static,
+    T: 'static,
+    U: 'static,
+{
+    type FuncT = T;
+    type FunctorOut = FreeApplicative<F, U>;
+    fn fmap(m: Self, func: impl Fn(T) -> U + Send + 'static) -> Self::FunctorOut {
+        let run = m.run;
+        FreeApplicative {
+            ops: m.ops,
+            run: Box::new(move |outputs| func(run(outputs))),
+        }
+    }
+}
+
+impl<F, T, U> Applicative<U> for FreeApplicative<F, T>
+where
+    F: Instruction,
+    F::Output: 'static,
+    T: Clone + Send + 'static,
+    U: 'static,
+{
+    type AppT = T;
+    fn pure(a: T) -> Self {
+        FreeApplicative::pure(a)
+    }
+}
+
+impl<F, Func, T, U> ApplicativeFunctor<Func, U> for FreeApplicative<F, T>
+where
+    F: Instruction,
+    F::Output: 'static,
+    Func: Fn(T) -> U + 'static,
+    T: Clone + Send + 'static,
+    U: Clone + Send + 'static,
+{
+    type AppFuncT = T;
+    type AppFuncOut = FreeApplicative<F, U>;
+    type AppFuncFn = FreeApplicative<F, Func>;
+    fn seq(m: Self, func: Self::AppFuncFn) -> Self::AppFuncOut {
+        let mut ops = func.ops;
+        ops.extend(m.ops);
+        let (func_run, m_run) = (func.run, m.run);
+        FreeApplicative {
+            ops,
+            run: Box::new(move |outputs| {
+                let f = func_run(outputs);
+                f(m_run(outputs))
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::eff::{
+        Eff,
+        handler::{Handlers, Reply},
+    };
+    use std::{
+        collections::HashMap,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum Field {
+        Text {
+            name: &'static str,
+            help: &'static str,
+        },
+        Number {
+            name: &'static str,
+            help: &'static str,
+        },
+        Flag {
+            name: &'static str,
+        },
+    }
+
+    impl Field {
+        fn name(&self) -> &'static str {
+            match self {
+                Field::Text { name, .. } | Field::Number { name, .. } | Field::Flag { name } => {
+                    name
+                }
+            }
+        }
+    }
+
+    impl Instruction for Field {
+        type Output = String;
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Config {
+        host: String,
+        port: u32,
+        verbose: bool,
+    }
+
+    fn text(name: &'static str, help: &'static str) -> FreeApplicative<Field, String> {
+        FreeApplicative::lift(Field::Text { name, help })
+    }
+
+    fn number(name: &'static str, help: &'static str) -> FreeApplicative<Field, u32> {
+        fmap(
+            FreeApplicative::lift(Field::Number { name, help }),
+            |s: String| s.parse().unwrap_or(0),
+        )
+    }
+
+    fn flag(name: &'static str) -> FreeApplicative<Field, bool> {
+        fmap(FreeApplicative::lift(Field::Flag { name }), |s: String| {
+            s == "true"
+        })
+    }
+
+    fn config() -> FreeApplicative<Field, Config> {
+        let make = |host: String| {
+            move |port: u32| {
+                let host = host.clone();
+                move |verbose: bool| Config {
+                    host: host.clone(),
+                    port,
+                    verbose,
+                }
+            }
+        };
+        seq(
+            flag("verbose"),
+            seq(
+                number("port", "port to listen on"),
+                fmap(text("host", "host name"), make),
+            ),
+        )
+    }
+
+    fn args() -> HashMap<&'static str, String> {
+        HashMap::from([
+            ("host", "localhost".to_string()),
+            ("port", "8080".to_string()),
+            ("verbose", "true".to_string()),
+        ])
+    }
+
+    #[test]
+    fn test_analyze() {
+        let program = config();
+        assert_eq!(program.len(), 3);
+        assert_eq!(program.analyze(|_| 1u32), 3);
+        assert_eq!(
+            program.analyze(|f| vec![f.name()]),
+            vec!["host", "port", "verbose"]
+        );
+        let help = program.analyze(|f| match f {
+            Field::Text { name, help } => format!("--{} <text>  {}\n", name, help),
+            Field::Number { name, help } => format!("--{} <n>  {}\n", name, help),
+            Field::Flag { name } => format!("--{}\n", name),
+        });
+        assert_eq!(
+            help,
+            "--host <text>  host name\n--port <n>  port to listen on\n--verbose\n"
+        );
+    }
+
+    #[test]
+    fn test_pure_program() {
+        let program = pure::<FreeApplicative<Field, _>>(3u32);
+        assert!(program.is_empty());
+        assert_eq!(program.analyze(|_| 1u32), 0);
+        assert_eq!(program.interpret(|_| unreachable!()), 3);
+    }
+
+    #[test]
+    fn test_interpret() {
+        let args = args();
+        let cfg = config().interpret(|f| args[f.name()].clone());
+        assert_eq!(
+            cfg,
+            Config {
+                host: "localhost".to_string(),
+                port: 8080,
+                verbose: true
+            }
+        );
+    }
+
+    #[test]
+    fn test_fold_map_option() {
+        let args = args();
+        let cfg = config().fold_map(|f| args.get(f.name()).cloned());
+        assert_eq!(cfg.map(|c| c.port), Some(8080));
+
+        let cfg = config().fold_map(|f| match f {
+            Field::Flag { .. } => None,
+            f => args.get(f.name()).cloned(),
+        });
+        assert_eq!(cfg, None);
+    }
+
+    #[test]
+    fn test_fold_map_result() {
+        let args = HashMap::from([("host", "localhost".to_string())]);
+        let cfg: Result<Config, String> = config().fold_map(|f| {
+            args.get(f.name())
+                .cloned()
+                .ok_or(format!("missing {}", f.name()))
+        });
+        assert_eq!(cfg, Err("missing port".to_string()));
+    }
+
+    #[test]
+    fn test_fold_map_vec() {
+        let program = seq(
+            text("b", ""),
+            fmap(text("a", ""), |a: String| move |b: String| a.clone() + &b),
+        );
+        let all = program.fold_map(|f| match f.name() {
+            "a" => vec!["1".to_string(), "2".to_string()],
+            _ => vec!["x".to_string(), "y".to_string()],
+        });
+        assert_eq!(all, vec!["1x", "1y", "2x", "2y"]);
+    }
+
+    #[test]
+    fn test_fold_map_many_instructions() {
+        let program = (0..10_000).fold(pure::<FreeApplicative<Field, u64>>(0), |acc, _| {
+            let add = fmap(flag("x"), |x: bool| move |n: u64| n + x as u64);
+            seq(acc, add)
+        });
+        assert_eq!(program.len(), 10_000);
+        assert_eq!(program.fold_map(|_| Some("true".to_string())), Some(10_000));
+    }
+
+    static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Debug, PartialEq)]
+    struct Counted(u32);
+
+    impl Clone for Counted {
+        fn clone(&self) -> Self {
+            CLONES.fetch_add(1, Ordering::SeqCst);
+            Counted(self.0)
+        }
+    }
+
+    struct Tick(u32);
+
+    impl Instruction for Tick {
+        type Output = Counted;
+    }
+
+    #[test]
+    fn test_fold_map_does_not_clone_outputs() {
+        let program = (0..100).fold(pure::<FreeApplicative<Tick, u32>>(0), |acc, i| {
+            let add = fmap(FreeApplicative::lift(Tick(i)), |c: Counted| {
+                move |n: u32| n + c.0
+            });
+            seq(acc, add)
+        });
+        assert_eq!(program.fold_map(|t: Tick| Some(Counted(t.0))), Some(4950));
+        assert_eq!(CLONES.load(Ordering::SeqCst), 0);
+    }
+
+    #[cfg(feature = "cfuture")]
+    #[test]
+    fn test_fold_map_cfuture() {
+        use crate::types::cfuture::CFuture;
+
+        let args = args();
+        let cfg = config().fold_map(|f| {
+            let arg = args.get(f.name()).cloned().unwrap_or_default();
+            CFuture::new(async move { arg })
+        });
+        assert_eq!(cfg.wait().port, 8080);
+    }
+
+    struct Ask(&'static str);
+
+    #[test]
+    fn test_fold_map_eff() {
+        let program = config().fold_map(|f| Eff::<String>::perform(Ask(f.name())));
+        let args = args();
+        let mut handlers = Handlers::new().with(move |Ask(name)| Reply::new(args[name].clone()));
+        assert_eq!(
+            handlers.run(program).map(|c| c.host),
+            Ok("localhost".to_string())
+        );
+    }
+}
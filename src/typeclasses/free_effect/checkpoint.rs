@@ -0,0 +1,379 @@
+use std::{
+    any::{Any, TypeId, type_name},
+    collections::HashMap,
+    fmt::{Display, Formatter},
+    fs,
+    path::PathBuf,
+};
+
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::{Value, json};
+
+use crate::typeclasses::free_effect::{
+    dyn_free::{DynFree, TypeMismatch},
+    stepper::{ResumeError, Stepper},
+};
+
+/// Errors raised while saving or resuming a checkpoint.
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(std::io::Error),
+    /// The checkpoint file isn't valid, or its value couldn't be (de)serialised
+    Format(String),
+    /// The checkpoint holds a value whose type wasn't registered
+    UnregisteredType(String),
+    /// The checkpoint was saved by a pipeline whose description differs from the one
+    /// being resumed
+    PipelineChanged {
+        saved: u64,
+        current: u64,
+    },
+    /// The checkpoint's position is past the end of the pipeline being resumed
+    OutOfRange {
+        position: usize,
+        len: usize,
+    },
+    /// The checkpoint doesn't fit the pipeline being resumed
+    Mismatch(TypeMismatch),
+}
+
+impl Display for CheckpointError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckpointError::Io(e) => write!(f, "checkpoint I/O error: {}", e),
+            CheckpointError::Format(msg) => write!(f, "invalid checkpoint: {}", msg),
+            CheckpointError::UnregisteredType(name) => {
+                write!(f, "checkpoint type {} is not registered", name)
+            }
+            CheckpointError::PipelineChanged { saved, current } => write!(
+                f,
+                "checkpoint was saved by another pipeline (fingerprint {:016x}, expected {:016x})",
+                saved, current
+            ),
+            CheckpointError::OutOfRange { position, len } => write!(
+                f,
+                "checkpoint is at step {} of a pipeline of {} steps",
+                position, len
+            ),
+            CheckpointError::Mismatch(e) => write!(
+                f,
+                "checkpoint does not fit the pipeline at step {}: expected {}, found {}",
+                e.position, e.expected, e.found
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {}
+
+impl From<std::io::Error> for CheckpointError {
+    fn from(e: std::io::Error) -> Self {
+        CheckpointError::Io(e)
+    }
+}
+
+impl From<ResumeError> for CheckpointError {
+    fn from(e: ResumeError) -> Self {
+        match e {
+            ResumeError::OutOfRange { position, len } => {
+                CheckpointError::OutOfRange { position, len }
+            }
+            ResumeError::Mismatch(e) => CheckpointError::Mismatch(e),
+        }
+    }
+}
+
+impl From<serde_json::Error> for CheckpointError {
+    fn from(e: serde_json::Error) -> Self {
+        CheckpointError::Format(e.to_string())
+    }
+}
+
+struct Codec {
+    name: &'static str,
+    save: fn(&(dyn Any + Send)) -> Result<Value, serde_json::Error>,
+    load: fn(Value) -> Result<Box<dyn Any + Send>, serde_json::Error>,
+}
+
+fn save_as<T: Serialize + 'static>(value: &(dyn Any + Send)) -> Result<Value, serde_json::Error> {
+    serde_json::to_value(
+        value
+            .downcast_ref::<T>()
+            .expect("checkpoint codec used with the wrong type"),
+    )
+}
+
+fn load_as<T: DeserializeOwned + Send + 'static>(
+    value: Value,
+) -> Result<Box<dyn Any + Send>, serde_json::Error> {
+    Ok(Box::new(serde_json::from_value::<T>(value)?))
+}
+
+/// Persists the intermediate monads of a stepped pipeline to a local file.
+///
+/// Only the monad types registered with `register` are saved; stages producing other
+/// types are run without updating the checkpoint, so a resumed run restarts from the
+/// last stage whose output could be saved.  The file is written to a temporary file
+/// and renamed into place, so a crash while saving leaves the previous checkpoint.
+/// A fingerprint of the pipeline's steps and of their monad types is saved along, so a
+/// checkpoint isn't resumed into a pipeline whose steps changed since.
+///
+/// ```rust
+/// use rust_effects::typeclasses::free_effect::{
+///     checkpoint::Checkpoint, dyn_free::DynFree, free::Free,
+/// };
+///
+/// let free = DynFree::from_split(
+///     Free::<Vec<String>, ()>::new()
+///         .map(|s: String| s.len())
+///         .map(|n: usize| n * 2),
+/// );
+/// let path = std::env::temp_dir().join("rust-effects-doc-checkpoint.json");
+/// let checkpoint = Checkpoint::new(&path).register::<Vec<usize>>();
+///
+/// let out = checkpoint.run(&free, || vec!["fox".to_string()]).unwrap();
+/// assert_eq!(out, vec![6]);
+/// assert!(!path.exists());
+/// ```
+pub struct Checkpoint {
+    path: PathBuf,
+    codecs: HashMap<TypeId, Codec>,
+}
+
+impl Checkpoint {
+    pub fn new(path: impl Into<PathBuf>) -> Checkpoint {
+        Checkpoint {
+            path: path.into(),
+            codecs: HashMap::new(),
+        }
+    }
+
+    /// Allow intermediate monads of type `T` to be saved.
+    pub fn register<T>(mut self) -> Self
+    where
+        T: Serialize + DeserializeOwned + Send + 'static,
+    {
+        self.codecs.insert(
+            TypeId::of::<T>(),
+            Codec {
+                name: type_name::<T>(),
+                save: save_as::<T>,
+                load: load_as::<T>,
+            },
+        );
+        self
+    }
+
+    /// Save the stepper's current position and intermediate monad.  Returns `false`
+    /// without touching the file if the monad's type isn't registered.
+    pub fn save<In, Out>(&self, stepper: &Stepper<In, Out>) -> Result<bool, CheckpointError>
+    where
+        In: Send + 'static,
+        Out: Send + 'static,
+    {
+        let Some(codec) = self.codecs.get(&stepper.current_type().0) else {
+            return Ok(false);
+        };
+        let contents = json!({
+            "pipeline": stepper.fingerprint(),
+            "position": stepper.position(),
+            "type": codec.name,
+            "value": (codec.save)(stepper.current_any())?,
+        });
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_vec(&contents)?)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(true)
+    }
+
+    /// Resume from the saved checkpoint, or start from `start` if there isn't one.
+    pub fn resume<'a, In, Out>(
+        &self,
+        free: &'a DynFree<In, Out>,
+        start: impl FnOnce() -> In,
+    ) -> Result<Stepper<'a, In, Out>, CheckpointError>
+    where
+        In: Send + 'static,
+        Out: Send + 'static,
+    {
+        if !self.path.exists() {
+            return Ok(free.stepper(start()));
+        }
+        let contents: Value = serde_json::from_slice(&fs::read(&self.path)?)?;
+        let saved = contents["pipeline"]
+            .as_u64()
+            .ok_or_else(|| CheckpointError::Format("missing pipeline".to_string()))?;
+        let current = free.fingerprint();
+        if saved != current {
+            return Err(CheckpointError::PipelineChanged { saved, current });
+        }
+        let position = contents["position"]
+            .as_u64()
+            .ok_or_else(|| CheckpointError::Format("missing position".to_string()))?;
+        let name = contents["type"]
+            .as_str()
+            .ok_or_else(|| CheckpointError::Format("missing type".to_string()))?;
+        let codec = self
+            .codecs
+            .values()
+            .find(|codec| codec.name == name)
+            .ok_or_else(|| CheckpointError::UnregisteredType(name.to_string()))?;
+        let value = (codec.load)(contents["value"].clone())?;
+        Ok(Stepper::resume(free, position as usize, value, codec.name)?)
+    }
+
+    /// Run the pipeline to the end, resuming from the saved checkpoint if there is one
+    /// and saving after every step.  The checkpoint is removed once the run completes.
+    pub fn run<In, Out>(
+        &self,
+        free: &DynFree<In, Out>,
+        start: impl FnOnce() -> In,
+    ) -> Result<Out, CheckpointError>
+    where
+        In: Send + 'static,
+        Out: Send + 'static,
+    {
+        let mut stepper = self.resume(free, start)?;
+        while stepper.step() {
+            self.save(&stepper)?;
+        }
+        self.clear()?;
+        Ok(stepper.finish())
+    }
+
+    /// Remove the saved checkpoint, if any.
+    pub fn clear(&self) -> Result<(), CheckpointError> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::typeclasses::free_effect::free::Free;
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rust-effects-{}-{}.json", name, std::process::id()))
+    }
+
+    fn input() -> Vec<String> {
+        vec!["fox".to_string(), "horse".to_string()]
+    }
+
+    #[test]
+    fn test_resume_after_crash() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let free = DynFree::from_split(
+            Free::<Vec<String>, ()>::new()
+                .map(move |s: String| {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    s.len()
+                })
+                .map(|n: usize| n * 10)
+                .map(|n: usize| n.to_string()),
+        );
+        let checkpoint = Checkpoint::new(temp_path("resume")).register::<Vec<usize>>();
+        checkpoint.clear().unwrap();
+
+        // Run two stages, then "crash" by dropping the stepper.
+        let mut stepper = checkpoint.resume(&free, input).unwrap();
+        stepper.step();
+        assert!(checkpoint.save(&stepper).unwrap());
+        stepper.step();
+        assert!(checkpoint.save(&stepper).unwrap());
+        drop(stepper);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let stepper = checkpoint
+            .resume(&free, || panic!("should resume from the checkpoint"))
+            .unwrap();
+        assert_eq!(stepper.position(), 2);
+        assert_eq!(stepper.current::<Vec<usize>>(), Some(&vec![30, 50]));
+
+        let out = checkpoint
+            .run(&free, || panic!("should resume from the checkpoint"))
+            .unwrap();
+        assert_eq!(out, vec!["30", "50"]);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert!(!temp_path("resume").exists());
+    }
+
+    #[test]
+    fn test_unregistered_types_are_skipped() {
+        let free = DynFree::from_split(
+            Free::<Vec<String>, ()>::new()
+                .map(|s: String| s.len())
+                .map(|n: usize| n.to_string()),
+        );
+        let checkpoint = Checkpoint::new(temp_path("skipped")).register::<Vec<usize>>();
+        checkpoint.clear().unwrap();
+
+        let mut stepper = checkpoint.resume(&free, input).unwrap();
+        stepper.step();
+        assert!(checkpoint.save(&stepper).unwrap());
+        stepper.step();
+        assert!(!checkpoint.save(&stepper).unwrap());
+
+        let stepper = checkpoint.resume(&free, input).unwrap();
+        assert_eq!(stepper.position(), 1);
+        checkpoint.clear().unwrap();
+    }
+
+    #[test]
+    fn test_checkpoint_from_other_pipeline() {
+        let free = DynFree::from_split(Free::<Vec<String>, ()>::new().map(|s: String| s.len()));
+        let path = temp_path("mismatch");
+        let write = |position: usize, pipeline: u64| {
+            let contents = json!({
+                "pipeline": pipeline,
+                "position": position,
+                "type": type_name::<Vec<String>>(),
+                "value": [],
+            });
+            fs::write(&path, contents.to_string()).unwrap();
+        };
+        let fingerprint = free.fingerprint();
+
+        write(1, fingerprint);
+        let checkpoint = Checkpoint::new(&path).register::<Vec<String>>();
+        let err = checkpoint.resume(&free, input).err();
+        assert!(matches!(err, Some(CheckpointError::Mismatch(_))));
+
+        write(2, fingerprint);
+        let err = checkpoint.resume(&free, input).err();
+        assert!(matches!(
+            err,
+            Some(CheckpointError::OutOfRange {
+                position: 2,
+                len: 1
+            })
+        ));
+
+        let changed = DynFree::from_split(
+            Free::<Vec<String>, ()>::new().map_labeled("length", |s: String| s.len()),
+        );
+        write(1, fingerprint);
+        let err = checkpoint.resume(&changed, input).err();
+        assert!(matches!(err, Some(CheckpointError::PipelineChanged { .. })));
+
+        // Same description, but the step now returns another type
+        let retyped =
+            DynFree::from_split(Free::<Vec<String>, ()>::new().map(|s: String| s.len() as u32));
+        assert_eq!(retyped.describe(), free.describe());
+        let err = checkpoint.resume(&retyped, input).err();
+        assert!(matches!(err, Some(CheckpointError::PipelineChanged { .. })));
+
+        let checkpoint = Checkpoint::new(&path);
+        let err = checkpoint.resume(&free, input).err();
+        assert!(matches!(err, Some(CheckpointError::UnregisteredType(_))));
+        checkpoint.clear().unwrap();
+    }
+}
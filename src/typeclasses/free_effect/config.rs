@@ -0,0 +1,399 @@
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter},
+};
+
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_json::Value;
+
+use crate::typeclasses::free_effect::{
+    FreeEffect,
+    dyn_free::{DynFree, ErasedStep},
+};
+
+/// Errors raised while loading a pipeline from a spec.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConfigError {
+    /// The spec text could not be parsed
+    Parse(String),
+    /// The spec names a step which isn't in the registry
+    UnknownStep(String),
+    /// A step's constructor rejected its parameters
+    InvalidParams { step: String, message: String },
+    /// A step's input monad doesn't match the previous step's output monad.  `step` is
+    /// `None` when the last step's output doesn't match the pipeline's output, and
+    /// `previous` is `None` when the first step doesn't accept the pipeline's input.
+    TypeMismatch {
+        step: Option<String>,
+        previous: Option<String>,
+        expected: &'static str,
+        found: &'static str,
+    },
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Parse(msg) => write!(f, "could not parse pipeline spec: {}", msg),
+            ConfigError::UnknownStep(name) => write!(f, "unknown step '{}'", name),
+            ConfigError::InvalidParams { step, message } => {
+                write!(f, "invalid parameters for step '{}': {}", step, message)
+            }
+            ConfigError::TypeMismatch {
+                step,
+                previous,
+                expected,
+                found,
+            } => {
+                let from = previous
+                    .as_ref()
+                    .map_or("the pipeline input".to_string(), |p| {
+                        format!("step '{}'", p)
+                    });
+                match step {
+                    Some(step) => write!(
+                        f,
+                        "step '{}' takes {} but {} produces {}",
+                        step, found, from, expected
+                    ),
+                    None => write!(
+                        f,
+                        "the pipeline must produce {} but {} produces {}",
+                        expected, from, found
+                    ),
+                }
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+fn enabled_default() -> bool {
+    true
+}
+
+/// One step of a pipeline spec.  Steps are enabled unless `enabled = false` is given.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct StepSpec {
+    pub name: String,
+    #[serde(default = "enabled_default")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub params: Value,
+}
+
+/// A pipeline description, listing its steps in execution order.
+///
+/// In JSON:
+/// ```text
+/// { "steps": [ { "name": "trim" }, { "name": "min_len", "params": { "min": 3 } } ] }
+/// ```
+///
+/// In TOML:
+/// ```text
+/// [[steps]]
+/// name = "trim"
+///
+/// [[steps]]
+/// name = "min_len"
+/// params = { min = 3 }
+/// ```
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct PipelineSpec {
+    pub steps: Vec<StepSpec>,
+}
+
+impl PipelineSpec {
+    pub fn from_json(text: &str) -> Result<PipelineSpec, ConfigError> {
+        serde_json::from_str(text).map_err(|e| ConfigError::Parse(e.to_string()))
+    }
+
+    pub fn from_toml(text: &str) -> Result<PipelineSpec, ConfigError> {
+        toml::from_str(text).map_err(|e| ConfigError::Parse(e.to_string()))
+    }
+}
+
+/// The parameters given to a step in a pipeline spec.
+pub struct Params<'a> {
+    step: &'a str,
+    value: &'a Value,
+}
+
+impl Params<'_> {
+    /// Read a required parameter.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Result<T, ConfigError> {
+        match self.value.get(key) {
+            Some(v) => self.parse(key, v),
+            None => Err(self.error(format!("missing parameter '{}'", key))),
+        }
+    }
+
+    /// Read an optional parameter, falling back to `default` when it isn't given.
+    pub fn get_or<T: DeserializeOwned>(&self, key: &str, default: T) -> Result<T, ConfigError> {
+        match self.value.get(key) {
+            Some(v) => self.parse(key, v),
+            None => Ok(default),
+        }
+    }
+
+    /// Raise an `InvalidParams` error for this step.
+    pub fn error(&self, message: impl Into<String>) -> ConfigError {
+        ConfigError::InvalidParams {
+            step: self.step.to_string(),
+            message: message.into(),
+        }
+    }
+
+    fn parse<T: DeserializeOwned>(&self, key: &str, value: &Value) -> Result<T, ConfigError> {
+        T::deserialize(value).map_err(|e| self.error(format!("parameter '{}': {}", key, e)))
+    }
+}
+
+type Constructor = Box<dyn Fn(&Params) -> Result<ErasedStep, ConfigError> + Send + Sync>;
+
+/// A registry of named step constructors used to build pipelines from a spec.
+///
+/// Each constructor receives the step's parameters and returns a `FreeEffect`.  A spec
+/// can then reorder, repeat, or disable the registered steps without recompiling, and
+/// the monad types of adjacent steps are checked when the pipeline is loaded.
+///
+/// ```rust
+/// use rust_effects::typeclasses::free_effect::{config::StepRegistry, free_map::FreeMap};
+///
+/// let mut registry = StepRegistry::new();
+/// registry.register("trim", |_| {
+///     Ok(FreeMap::<String, String, Vec<String>>::new(|s| s.trim().to_string()))
+/// });
+/// registry.register("min_len", |params| {
+///     let min: usize = params.get("min")?;
+///     Ok(FreeMap::<String, Option<String>, Vec<String>>::new(move |s| {
+///         if s.len() >= min { Some(s) } else { None }
+///     }))
+/// });
+///
+/// let spec = r#"{ "steps": [ { "name": "trim" }, { "name": "trim", "enabled": false } ] }"#;
+/// let free = registry.load_json::<Vec<String>, Vec<String>>(spec).unwrap();
+/// assert_eq!(free.fold_map(vec![" fox ".to_string()]), vec!["fox"]);
+///
+/// let spec = r#"{ "steps": [ { "name": "min_len", "params": { "min": 3 } } ] }"#;
+/// let err = registry.load_json::<Vec<String>, Vec<String>>(spec).err().unwrap();
+/// assert!(err.to_string().contains("the pipeline must produce"));
+/// ```
+#[derive(Default)]
+pub struct StepRegistry {
+    steps: HashMap<String, Constructor>,
+}
+
+impl StepRegistry {
+    pub fn new() -> StepRegistry {
+        StepRegistry {
+            steps: HashMap::new(),
+        }
+    }
+
+    /// Register a step constructor under `name`, replacing any previous one.
+    pub fn register<Eff>(
+        &mut self,
+        name: impl Into<String>,
+        ctor: impl Fn(&Params) -> Result<Eff, ConfigError> + Send + Sync + 'static,
+    ) -> &mut Self
+    where
+        Eff: FreeEffect + Send + 'static,
+        Eff::In: 'static,
+        Eff::Out: Send + 'static,
+    {
+        self.steps.insert(
+            name.into(),
+            Box::new(move |params| ctor(params).map(ErasedStep::new)),
+        );
+        self
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.steps.contains_key(name)
+    }
+
+    /// Names of all registered steps, sorted.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names = self.steps.keys().map(String::as_str).collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// Build a pipeline from a spec, skipping disabled steps.
+    pub fn build<In, Out>(&self, spec: &PipelineSpec) -> Result<DynFree<In, Out>, ConfigError>
+    where
+        In: Send + 'static,
+        Out: Send + 'static,
+    {
+        let enabled = spec.steps.iter().filter(|s| s.enabled).collect::<Vec<_>>();
+        let steps = enabled
+            .iter()
+            .map(|s| {
+                let ctor = self
+                    .steps
+                    .get(&s.name)
+                    .ok_or_else(|| ConfigError::UnknownStep(s.name.clone()))?;
+                let step = ctor(&Params {
+                    step: &s.name,
+                    value: &s.params,
+                })?;
+                Ok(step.with_label(&s.name))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        DynFree::try_from_steps(steps).map_err(|e| ConfigError::TypeMismatch {
+            step: enabled.get(e.position).map(|s| s.name.clone()),
+            previous: e
+                .position
+                .checked_sub(1)
+                .map(|prev| enabled[prev].name.clone()),
+            expected: e.expected,
+            found: e.found,
+        })
+    }
+
+    pub fn load_json<In, Out>(&self, text: &str) -> Result<DynFree<In, Out>, ConfigError>
+    where
+        In: Send + 'static,
+        Out: Send + 'static,
+    {
+        self.build(&PipelineSpec::from_json(text)?)
+    }
+
+    pub fn load_toml<In, Out>(&self, text: &str) -> Result<DynFree<In, Out>, ConfigError>
+    where
+        In: Send + 'static,
+        Out: Send + 'static,
+    {
+        self.build(&PipelineSpec::from_toml(text)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::typeclasses::free_effect::{free_bind::FreeBind, free_map::FreeMap};
+
+    fn registry() -> StepRegistry {
+        let mut registry = StepRegistry::new();
+        registry
+            .register("upper", |_| {
+                Ok(FreeMap::<String, String, Vec<String>>::new(|s| {
+                    s.to_uppercase()
+                }))
+            })
+            .register("min_len", |params| {
+                let min: usize = params.get("min")?;
+                Ok(FreeBind::<String, String, Vec<String>>::new(move |s| {
+                    if s.len() >= min { vec![s] } else { vec![] }
+                }))
+            })
+            .register("length", |_| {
+                Ok(FreeMap::<String, usize, Vec<String>>::new(|s| s.len()))
+            })
+            .register("add", |params| {
+                let n: usize = params.get_or("n", 1)?;
+                Ok(FreeMap::<usize, usize, Vec<usize>>::new(move |a| a + n))
+            });
+        registry
+    }
+
+    fn input() -> Vec<String> {
+        vec!["ox".to_string(), "fox".to_string(), "horse".to_string()]
+    }
+
+    #[test]
+    fn test_load_json() {
+        let spec = r#"{ "steps": [
+            { "name": "min_len", "params": { "min": 3 } },
+            { "name": "upper" },
+            { "name": "length" },
+            { "name": "add", "params": { "n": 10 } }
+        ] }"#;
+        let free = registry()
+            .load_json::<Vec<String>, Vec<usize>>(spec)
+            .unwrap();
+        assert_eq!(free.fold_map(input()), vec![13, 15]);
+    }
+
+    #[test]
+    fn test_load_toml_with_disabled_step() {
+        let spec = r#"
+            [[steps]]
+            name = "min_len"
+            enabled = false
+            params = { min = 4 }
+
+            [[steps]]
+            name = "length"
+
+            [[steps]]
+            name = "add"
+        "#;
+        let free = registry()
+            .load_toml::<Vec<String>, Vec<usize>>(spec)
+            .unwrap();
+        assert_eq!(free.fold_map(input()), vec![3, 4, 6]);
+        assert_eq!(free.describe().to_text(), "0: map [length]\n1: map [add]\n");
+    }
+
+    #[test]
+    fn test_unknown_step() {
+        let spec = r#"{ "steps": [ { "name": "reverse" } ] }"#;
+        let err = registry().load_json::<Vec<String>, Vec<String>>(spec).err();
+        assert_eq!(err, Some(ConfigError::UnknownStep("reverse".to_string())));
+    }
+
+    #[test]
+    fn test_invalid_params() {
+        let spec = r#"{ "steps": [ { "name": "min_len" } ] }"#;
+        let err = registry().load_json::<Vec<String>, Vec<String>>(spec).err();
+        assert_eq!(
+            err,
+            Some(ConfigError::InvalidParams {
+                step: "min_len".to_string(),
+                message: "missing parameter 'min'".to_string()
+            })
+        );
+
+        let spec = r#"{ "steps": [ { "name": "min_len", "params": { "min": "three" } } ] }"#;
+        let err = registry().load_json::<Vec<String>, Vec<String>>(spec).err();
+        assert!(matches!(err, Some(ConfigError::InvalidParams { .. })));
+    }
+
+    #[test]
+    fn test_mismatched_steps() {
+        let spec = r#"{ "steps": [ { "name": "length" }, { "name": "upper" } ] }"#;
+        let err = registry()
+            .load_json::<Vec<String>, Vec<String>>(spec)
+            .err()
+            .unwrap();
+        assert!(matches!(
+            &err,
+            ConfigError::TypeMismatch { step: Some(step), previous: Some(prev), .. }
+                if step == "upper" && prev == "length"
+        ));
+        assert!(err.to_string().starts_with("step 'upper' takes"));
+
+        let spec = r#"{ "steps": [ { "name": "add" } ] }"#;
+        let err = registry().load_json::<Vec<String>, Vec<usize>>(spec).err();
+        assert!(matches!(
+            err,
+            Some(ConfigError::TypeMismatch {
+                step: Some(_),
+                previous: None,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let err = registry()
+            .load_json::<Vec<String>, Vec<String>>("{ steps: ")
+            .err();
+        assert!(matches!(err, Some(ConfigError::Parse(_))));
+    }
+}
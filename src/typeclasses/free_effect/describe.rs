@@ -0,0 +1,130 @@
+use std::fmt::{Display, Formatter};
+
+/// Static information about a single step of a `Free` pipeline.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StepInfo {
+    pub kind: &'static str,
+    pub label: Option<String>,
+}
+
+impl StepInfo {
+    pub fn new(kind: &'static str, label: Option<&str>) -> StepInfo {
+        StepInfo {
+            kind,
+            label: label.map(str::to_string),
+        }
+    }
+
+    /// The label if one was given, otherwise the kind of the step
+    pub fn name(&self) -> &str {
+        self.label.as_deref().unwrap_or(self.kind)
+    }
+}
+
+/// The structure of a `Free` pipeline, as returned by `Free::describe`.
+///
+/// Steps are listed in execution order, starting with the first effect applied
+/// to the monad passed to `fold_map`.  The description can be rendered as plain
+/// text (also available through `Display`) or as a Graphviz DOT digraph:
+///
+/// ```rust
+/// use rust_effects::typeclasses::free_effect::free::Free;
+///
+/// let free = Free::<Option<String>, ()>::new()
+///     .map_labeled("length", |s| s.len())
+///     .bind(|n| if n > 2 { Some(n) } else { None });
+/// let desc = free.describe();
+/// assert_eq!(desc.to_text(), "0: identity\n1: map [length]\n2: bind\n");
+/// assert!(desc.to_dot().contains("s1 -> s2;"));
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Description {
+    pub steps: Vec<StepInfo>,
+}
+
+impl Description {
+    pub fn new(steps: Vec<StepInfo>) -> Description {
+        Description { steps }
+    }
+
+    pub fn to_text(&self) -> String {
+        self.steps
+            .iter()
+            .enumerate()
+            .map(|(i, step)| match &step.label {
+                Some(label) => format!("{}: {} [{}]\n", i, step.kind, label),
+                None => format!("{}: {}\n", i, step.kind),
+            })
+            .collect()
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph free {\n    rankdir=LR;\n");
+        for (i, step) in self.steps.iter().enumerate() {
+            let text = match &step.label {
+                Some(label) => format!("{}\\n{}", step.kind, escape(label)),
+                None => step.kind.to_string(),
+            };
+            out += &format!("    s{} [label=\"{}\"];\n", i, text);
+        }
+        for i in 1..self.steps.len() {
+            out += &format!("    s{} -> s{};\n", i - 1, i);
+        }
+        out += "}\n";
+        out
+    }
+
+    /// A hash of the text description, stable across runs and builds, to check that a
+    /// pipeline didn't change (e.g. before resuming it from a checkpoint).
+    pub fn fingerprint(&self) -> u64 {
+        fnv1a(self.to_text().as_bytes())
+    }
+}
+
+impl Display for Description {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_text())
+    }
+}
+
+/// FNV-1a, as std's hashers may change between releases
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dot_output() {
+        let desc = Description::new(vec![
+            StepInfo::new("identity", None),
+            StepInfo::new("map", Some("say \"hi\"")),
+        ]);
+        let expected = concat!(
+            "digraph free {\n",
+            "    rankdir=LR;\n",
+            "    s0 [label=\"identity\"];\n",
+            "    s1 [label=\"map\\nsay \\\"hi\\\"\"];\n",
+            "    s0 -> s1;\n",
+            "}\n"
+        );
+        assert_eq!(desc.to_dot(), expected);
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let desc = Description::new(vec![StepInfo::new("map", Some("length"))]);
+        let same = Description::new(vec![StepInfo::new("map", Some("length"))]);
+        let other = Description::new(vec![StepInfo::new("map", Some("size"))]);
+        assert_eq!(desc.fingerprint(), same.fingerprint());
+        assert_ne!(desc.fingerprint(), other.fingerprint());
+    }
+}
@@ -0,0 +1,594 @@
+use std::{
+    any::{Any, TypeId, type_name},
+    marker::PhantomData,
+};
+
+use crate::typeclasses::{
+    free_effect::{
+        FreeEffect,
+        describe::{Description, StepInfo, fnv1a},
+        effect_list::EffectList,
+        free::Free,
+        free_bind::FreeBind,
+        free_map::FreeMap,
+        fuse::{FusedMap, MapChain},
+        identity::Identity,
+    },
+    monad::Monad,
+};
+
+trait DynStep: Send {
+    fn run(&self, source: Box<dyn Any + Send>) -> Box<dyn Any + Send>;
+    fn describe(&self, steps: &mut Vec<StepInfo>);
+    fn set_label(&mut self, label: String);
+    fn in_type(&self) -> (TypeId, &'static str);
+    fn out_type(&self) -> (TypeId, &'static str);
+}
+
+struct EffectStep<Eff> {
+    effect: Eff,
+    label: Option<String>,
+}
+
+impl<Eff> EffectStep<Eff> {
+    fn new(effect: Eff) -> EffectStep<Eff> {
+        EffectStep {
+            effect,
+            label: None,
+        }
+    }
+}
+
+impl<Eff> DynStep for EffectStep<Eff>
+where
+    Eff: FreeEffect + Send,
+    Eff::In: 'static,
+    Eff::Out: Send + 'static,
+{
+    fn run(&self, source: Box<dyn Any + Send>) -> Box<dyn Any + Send> {
+        let source = source
+            .downcast::<Eff::In>()
+            .expect("DynFree step received an unexpected monad type");
+        Box::new(self.effect.fold(*source))
+    }
+    fn describe(&self, steps: &mut Vec<StepInfo>) {
+        let start = steps.len();
+        self.effect.describe(steps);
+        if let Some(label) = &self.label {
+            steps[start..]
+                .iter_mut()
+                .filter(|step| step.label.is_none())
+                .for_each(|step| step.label = Some(label.clone()));
+        }
+    }
+    fn set_label(&mut self, label: String) {
+        self.label = Some(label);
+    }
+    fn in_type(&self) -> (TypeId, &'static str) {
+        (TypeId::of::<Eff::In>(), type_name::<Eff::In>())
+    }
+    fn out_type(&self) -> (TypeId, &'static str) {
+        (TypeId::of::<Eff::Out>(), type_name::<Eff::Out>())
+    }
+}
+
+/// A single boxed pipeline step whose input and output monads are only known at runtime.
+///
+/// Erased steps are used to assemble a `DynFree` when the steps are chosen at runtime
+/// (see `DynFree::try_from_steps`), in which case the monad types of adjacent steps are
+/// checked when the pipeline is built rather than at compile time.
+pub struct ErasedStep {
+    step: Box<dyn DynStep>,
+}
+
+impl ErasedStep {
+    pub fn new<Eff>(effect: Eff) -> ErasedStep
+    where
+        Eff: FreeEffect + Send + 'static,
+        Eff::In: 'static,
+        Eff::Out: Send + 'static,
+    {
+        ErasedStep {
+            step: Box::new(EffectStep::new(effect)),
+        }
+    }
+
+    /// Label used when describing the step, if the effect doesn't have its own label.
+    pub fn with_label(mut self, label: impl Into<String>) -> ErasedStep {
+        self.step.set_label(label.into());
+        self
+    }
+
+    pub fn in_name(&self) -> &'static str {
+        self.step.in_type().1
+    }
+
+    pub fn out_name(&self) -> &'static str {
+        self.step.out_type().1
+    }
+
+    pub(crate) fn run(&self, source: Box<dyn Any + Send>) -> Box<dyn Any + Send> {
+        self.step.run(source)
+    }
+
+    pub(crate) fn describe(&self, steps: &mut Vec<StepInfo>) {
+        self.step.describe(steps)
+    }
+
+    pub(crate) fn in_type(&self) -> (TypeId, &'static str) {
+        self.step.in_type()
+    }
+
+    pub(crate) fn out_type(&self) -> (TypeId, &'static str) {
+        self.step.out_type()
+    }
+}
+
+/// Mismatch between the monad types of two adjacent erased steps.
+///
+/// `position` is the index of the step whose input didn't match.  A position equal to
+/// the number of steps means the last step's output didn't match the pipeline's output.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypeMismatch {
+    pub position: usize,
+    pub expected: &'static str,
+    pub found: &'static str,
+}
+
+/// An effect which can be split into one erased step per stage.
+///
+/// Converting a `Free` with `DynFree::from` keeps its whole `EffectList` as a single
+/// step.  Effects implementing `SplitEffect` can instead be converted with
+/// `DynFree::from_split`, keeping each stage as a separate step so the intermediate
+/// monads can be inspected (see `Stepper`).
+pub trait SplitEffect: FreeEffect {
+    fn split_into(self, steps: &mut Vec<ErasedStep>);
+}
+
+impl<M, U> SplitEffect for Identity<M, U>
+where
+    U: Send,
+    M: Monad<U>,
+{
+    fn split_into(self, _steps: &mut Vec<ErasedStep>) {}
+}
+
+impl<T, U, In> SplitEffect for FreeMap<T, U, In>
+where
+    T: Send + 'static,
+    U: Send + 'static,
+    In: Monad<U, MonadT = T> + Send + 'static,
+    In::MonadOut: 'static,
+{
+    fn split_into(self, steps: &mut Vec<ErasedStep>) {
+        steps.push(ErasedStep::new(self));
+    }
+}
+
+impl<T, U, In, F> SplitEffect for FusedMap<T, U, In, F>
+where
+    T: Send + 'static,
+    U: Send + 'static,
+    In: Monad<U, MonadT = T> + Send + 'static,
+    In::MonadOut: 'static,
+    F: MapChain<T, U>,
+{
+    fn split_into(self, steps: &mut Vec<ErasedStep>) {
+        steps.push(ErasedStep::new(self));
+    }
+}
+
+impl<T, U, In> SplitEffect for FreeBind<T, U, In>
+where
+    In: Monad<U, MonadT = T> + Send + 'static,
+    T: Send + 'static,
+    U: Send + 'static,
+{
+    fn split_into(self, steps: &mut Vec<ErasedStep>) {
+        steps.push(ErasedStep::new(self));
+    }
+}
+
+impl<CurrEff, NestEff> SplitEffect for EffectList<CurrEff, NestEff>
+where
+    CurrEff: SplitEffect<In = NestEff::Out>,
+    NestEff: SplitEffect,
+{
+    fn split_into(self, steps: &mut Vec<ErasedStep>) {
+        let (curr_effect, next_effect) = self.into_pair();
+        next_effect.split_into(steps);
+        curr_effect.split_into(steps);
+    }
+}
+
+impl<In, Out> SplitEffect for DynFree<In, Out>
+where
+    In: Monad + Send + 'static,
+    Out: Monad + Send + 'static,
+{
+    fn split_into(self, steps: &mut Vec<ErasedStep>) {
+        steps.extend(self.steps);
+    }
+}
+
+/// A `Free` pipeline with its `EffectList` type erased.
+///
+/// A `Free` pipeline's type grows with every step added, so two pipelines with the
+/// same input and output monads generally have different types.  `DynFree` only keeps
+/// track of the input monad `In` and the output monad `Out`, boxing each step, so
+/// pipelines can be stored in collections, returned from trait objects and built up
+/// in loops at runtime.  The price is one boxed call and a downcast per step.
+///
+/// Any statically typed `Free` can be converted with `DynFree::from`, and a `DynFree`
+/// is itself a `FreeEffect`, so it can be used as a step of a `Free` again.
+///
+/// ```rust
+/// use rust_effects::typeclasses::free_effect::dyn_free::DynFree;
+///
+/// let mut free = DynFree::<Vec<u32>, Vec<u32>>::new();
+/// for i in 1..=3 {
+///     free.push_map(move |a| a * i);
+/// }
+/// let free = free.map(|a: u32| a.to_string());
+/// assert_eq!(free.fold_map(vec![1, 2]), vec!["6", "12"]);
+/// ```
+pub struct DynFree<In, Out> {
+    steps: Vec<ErasedStep>,
+    _ph: PhantomData<fn(In) -> Out>,
+}
+
+impl<M: Send + 'static> DynFree<M, M> {
+    pub fn new() -> DynFree<M, M> {
+        DynFree {
+            steps: vec![],
+            _ph: PhantomData,
+        }
+    }
+}
+
+impl<M: Send + 'static> Default for DynFree<M, M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<In, Out> DynFree<In, Out>
+where
+    In: Send + 'static,
+    Out: Send + 'static,
+{
+    pub fn fold_map(&self, start_monad: In) -> Out {
+        let out = self
+            .steps
+            .iter()
+            .fold(Box::new(start_monad) as Box<dyn Any + Send>, |m, step| {
+                step.run(m)
+            });
+        *out.downcast::<Out>()
+            .expect("DynFree pipeline produced an unexpected monad type")
+    }
+
+    /// Convert a `Free` keeping each of its stages as a separate step.
+    pub fn from_split<U, Eff>(free: Free<In, U, Eff>) -> DynFree<In, Out>
+    where
+        Eff: SplitEffect<In = In, Out = Out>,
+        U: Send,
+        In: Monad<U>,
+    {
+        let mut steps = vec![];
+        free.start_effect.split_into(&mut steps);
+        DynFree {
+            steps,
+            _ph: PhantomData,
+        }
+    }
+
+    pub(crate) fn steps(&self) -> &[ErasedStep] {
+        &self.steps
+    }
+
+    pub fn describe(&self) -> Description {
+        let mut steps = vec![];
+        for step in &self.steps {
+            step.describe(&mut steps);
+        }
+        Description::new(steps)
+    }
+
+    /// A hash of the description and of the monad types each step takes and returns,
+    /// stable across runs and builds, to check that a pipeline didn't change (e.g.
+    /// before resuming it from a checkpoint).  Unlike `Description::fingerprint`, it
+    /// tells apart unlabelled steps whose functions have different types.
+    pub fn fingerprint(&self) -> u64 {
+        let mut text = self.describe().to_text();
+        for step in &self.steps {
+            text += &format!("{} -> {}\n", step.in_type().1, step.out_type().1);
+        }
+        fnv1a(text.as_bytes())
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Build a pipeline from steps chosen at runtime, checking that each step's input
+    /// monad is the previous step's output monad (or `In` for the first step), and that
+    /// the last step's output monad is `Out`.
+    pub fn try_from_steps(steps: Vec<ErasedStep>) -> Result<DynFree<In, Out>, TypeMismatch> {
+        let mut curr = (TypeId::of::<In>(), type_name::<In>());
+        for (position, step) in steps.iter().enumerate() {
+            let (in_type, in_name) = step.in_type();
+            if in_type != curr.0 {
+                return Err(TypeMismatch {
+                    position,
+                    expected: curr.1,
+                    found: in_name,
+                });
+            }
+            curr = step.out_type();
+        }
+        if TypeId::of::<Out>() != curr.0 {
+            return Err(TypeMismatch {
+                position: steps.len(),
+                expected: type_name::<Out>(),
+                found: curr.1,
+            });
+        }
+        Ok(DynFree {
+            steps,
+            _ph: PhantomData,
+        })
+    }
+
+    /// Add a step which may change the output monad's type.
+    pub fn push<Eff>(mut self, effect: Eff) -> DynFree<In, Eff::Out>
+    where
+        Eff: FreeEffect<In = Out> + Send + 'static,
+        Eff::Out: Send + 'static,
+    {
+        self.steps.push(ErasedStep::new(effect));
+        DynFree {
+            steps: self.steps,
+            _ph: PhantomData,
+        }
+    }
+
+    /// Append all of the steps of another pipeline starting from this one's output.
+    pub fn append<Out2>(mut self, other: DynFree<Out, Out2>) -> DynFree<In, Out2> {
+        self.steps.extend(other.steps);
+        DynFree {
+            steps: self.steps,
+            _ph: PhantomData,
+        }
+    }
+
+    pub fn map<V, W>(
+        self,
+        func: impl Fn(V) -> W + Send + Clone + 'static,
+    ) -> DynFree<In, Out::MonadOut>
+    where
+        V: Send + 'static,
+        W: Send + 'static,
+        Out: Monad<W, MonadT = V>,
+        Out::MonadOut: 'static,
+    {
+        self.push(FreeMap::<V, W, Out>::new(func))
+    }
+
+    pub fn map_labeled<V, W>(
+        self,
+        label: impl Into<String>,
+        func: impl Fn(V) -> W + Send + Clone + 'static,
+    ) -> DynFree<In, Out::MonadOut>
+    where
+        V: Send + 'static,
+        W: Send + 'static,
+        Out: Monad<W, MonadT = V>,
+        Out::MonadOut: 'static,
+    {
+        self.push(FreeMap::<V, W, Out>::new(func).with_label(label))
+    }
+
+    pub fn bind<V, W, MOut>(
+        self,
+        func: impl Fn(V) -> MOut + Send + Clone + 'static,
+    ) -> DynFree<In, MOut>
+    where
+        V: Send + 'static,
+        W: Send + 'static,
+        Out: Monad<W, MonadT = V, MonadOut = MOut>,
+        MOut: Monad<MonadT = W> + Send + 'static,
+    {
+        self.push(FreeBind::<V, W, Out>::new(func))
+    }
+
+    pub fn bind_labeled<V, W, MOut>(
+        self,
+        label: impl Into<String>,
+        func: impl Fn(V) -> MOut + Send + Clone + 'static,
+    ) -> DynFree<In, MOut>
+    where
+        V: Send + 'static,
+        W: Send + 'static,
+        Out: Monad<W, MonadT = V, MonadOut = MOut>,
+        MOut: Monad<MonadT = W> + Send + 'static,
+    {
+        self.push(FreeBind::<V, W, Out>::new(func).with_label(label))
+    }
+
+    /// Add a map step in place.  As the output type can't change, the mapping function
+    /// must return the same type it is given.
+    pub fn push_map<V>(&mut self, func: impl Fn(V) -> V + Send + Clone + 'static)
+    where
+        V: Send + 'static,
+        Out: Monad<V, MonadT = V, MonadOut = Out>,
+    {
+        self.steps
+            .push(ErasedStep::new(FreeMap::<V, V, Out>::new(func)));
+    }
+
+    /// Add a bind step in place.  As the output type can't change, the bound function
+    /// must return the same monad type it is bound to.
+    pub fn push_bind<V>(&mut self, func: impl Fn(V) -> Out + Send + Clone + 'static)
+    where
+        V: Send + 'static,
+        Out: Monad<V, MonadT = V, MonadOut = Out>,
+    {
+        self.steps
+            .push(ErasedStep::new(FreeBind::<V, V, Out>::new(func)));
+    }
+}
+
+impl<M, U, Eff> From<Free<M, U, Eff>> for DynFree<Eff::In, Eff::Out>
+where
+    Eff: FreeEffect<In = M> + Send + 'static,
+    Eff::Out: Send + 'static,
+    U: Send,
+    M: Monad<U> + Send + 'static,
+{
+    fn from(free: Free<M, U, Eff>) -> Self {
+        DynFree::new().push(free.start_effect)
+    }
+}
+
+impl<In, Out> FreeEffect for DynFree<In, Out>
+where
+    In: Monad + Send + 'static,
+    Out: Monad + Send + 'static,
+{
+    type InU = ();
+    type OutU = ();
+    type In = In;
+    type Out = Out;
+    fn fold(&self, source: Self::In) -> Self::Out {
+        self.fold_map(source)
+    }
+    fn kind(&self) -> &'static str {
+        "dyn"
+    }
+    fn describe(&self, steps: &mut Vec<StepInfo>) {
+        for step in &self.steps {
+            step.step.describe(steps);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_empty_pipeline_is_identity() {
+        let free = DynFree::<Option<u32>, _>::new();
+        assert!(free.is_empty());
+        assert_eq!(free.fold_map(Some(3)), Some(3));
+    }
+
+    #[test]
+    fn test_store_pipelines_in_vec() {
+        let pipelines: Vec<DynFree<Option<String>, Option<usize>>> = vec![
+            DynFree::new().map(|s: String| s.len()),
+            DynFree::new()
+                .map(|s: String| s.len())
+                .bind(|n| if n > 3 { Some(n) } else { None }),
+        ];
+        let outs = pipelines
+            .iter()
+            .map(|p| p.fold_map(Some("dog".to_string())))
+            .collect::<Vec<_>>();
+        assert_eq!(outs, vec![Some(3), None]);
+    }
+
+    #[test]
+    fn test_build_in_loop() {
+        let mut free = DynFree::<Vec<u32>, _>::new();
+        for limit in [10, 5] {
+            free.push_bind(move |a| if a < limit { vec![a] } else { vec![] });
+            free.push_map(|a| a + 2);
+        }
+        assert_eq!(free.len(), 4);
+        assert_eq!(free.fold_map(vec![1, 2, 3, 7, 12]), vec![5, 6]);
+    }
+
+    #[test]
+    fn test_from_static_free() {
+        let free = Free::<Option<String>, ()>::new()
+            .map_labeled("length", |s| s.len())
+            .bind(|n| if n % 2 == 1 { Some(n) } else { None });
+        let free = DynFree::from(free).map_labeled("double", |n: usize| n * 2);
+
+        assert_eq!(free.fold_map(Some("dog".to_string())), Some(6));
+        assert_eq!(free.fold_map(Some("crow".to_string())), None);
+        assert_eq!(
+            free.describe()
+                .steps
+                .iter()
+                .map(|s| s.name())
+                .collect::<Vec<_>>(),
+            vec!["identity", "length", "bind", "double"]
+        );
+    }
+
+    #[test]
+    fn test_dyn_free_as_static_step() {
+        let inner = DynFree::<Option<u32>, _>::new().map(|n: u32| n + 1);
+        let free = Free::<Option<u32>, (), _>::new_effect(inner).map(|n: u32| n * 3);
+        assert_eq!(free.fold_map(Some(1)), Some(6));
+    }
+
+    #[test]
+    fn test_append() {
+        let first = DynFree::<Vec<&str>, _>::new().map(|s: &str| s.len());
+        let second = DynFree::<Vec<usize>, _>::new().map(|n: usize| n * 10);
+        let free = first.append(second);
+        assert_eq!(free.fold_map(vec!["a", "abc"]), vec![10, 30]);
+    }
+
+    #[test]
+    fn test_try_from_steps() {
+        let steps = vec![
+            ErasedStep::new(FreeMap::<&str, usize, Vec<&str>>::new(str::len)),
+            ErasedStep::new(FreeMap::<usize, usize, Vec<usize>>::new(|n| n + 1)),
+        ];
+        let free = DynFree::<Vec<&str>, Vec<usize>>::try_from_steps(steps).unwrap();
+        assert_eq!(free.fold_map(vec!["ox", "fox"]), vec![3, 4]);
+
+        let steps = vec![
+            ErasedStep::new(FreeMap::<usize, usize, Vec<usize>>::new(|n| n + 1)),
+            ErasedStep::new(FreeMap::<&str, usize, Vec<&str>>::new(str::len)),
+        ];
+        let err = DynFree::<Vec<usize>, Vec<usize>>::try_from_steps(steps).err();
+        assert_eq!(
+            err,
+            Some(TypeMismatch {
+                position: 1,
+                expected: type_name::<Vec<usize>>(),
+                found: type_name::<Vec<&str>>(),
+            })
+        );
+
+        let err = DynFree::<Vec<usize>, Vec<String>>::try_from_steps(vec![]).err();
+        assert_eq!(err.map(|e| e.position), Some(0));
+    }
+
+    #[test]
+    fn test_from_split() {
+        let free = Free::<Option<String>, ()>::new()
+            .map(|s| s.len())
+            .bind(|n| if n > 2 { Some(n) } else { None })
+            .map_labeled("double", |n: usize| n * 2);
+        let free = DynFree::from_split(free);
+
+        assert_eq!(free.len(), 3);
+        assert_eq!(free.fold_map(Some("dog".to_string())), Some(6));
+        assert_eq!(free.fold_map(Some("ox".to_string())), None);
+        assert_eq!(
+            free.describe().to_text(),
+            "0: map\n1: bind\n2: map [double]\n"
+        );
+    }
+}
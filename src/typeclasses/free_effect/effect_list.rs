@@ -1,4 +1,8 @@
-use crate::typeclasses::free_effect::FreeEffect;
+use crate::typeclasses::free_effect::{
+    FreeEffect,
+    describe::StepInfo,
+    trace::{Trace, TracedEffect},
+};
 
 pub struct EffectList<CurrEff, NestEff>
 where
@@ -20,6 +24,10 @@ where
             next_effect: effect1,
         }
     }
+    pub fn into_pair(self) -> (CurrEff, NestEff) {
+        (self.curr_effect, self.next_effect)
+    }
+    #[allow(clippy::should_implement_trait)]
     pub fn add<NewF: FreeEffect<In = CurrEff::Out>>(
         self,
         effect: NewF,
@@ -42,7 +50,24 @@ where
     type Out = CurrEff::Out;
     fn fold(&self, source: Self::In) -> Self::Out {
         let folded_monad = self.next_effect.fold(source);
-        let out = self.curr_effect.fold(folded_monad);
-        out
+        self.curr_effect.fold(folded_monad)
+    }
+    fn kind(&self) -> &'static str {
+        "list"
+    }
+    fn describe(&self, steps: &mut Vec<StepInfo>) {
+        self.next_effect.describe(steps);
+        self.curr_effect.describe(steps);
+    }
+}
+
+impl<CurrEff, NestEff> TracedEffect for EffectList<CurrEff, NestEff>
+where
+    CurrEff: TracedEffect<In = NestEff::Out>,
+    NestEff: TracedEffect,
+{
+    fn fold_traced(&self, source: Self::In, trace: &mut Trace) -> Self::Out {
+        let folded_monad = self.next_effect.fold_traced(source, trace);
+        self.curr_effect.fold_traced(folded_monad, trace)
     }
 }
@@ -5,7 +5,14 @@ use crate::typeclasses::{
     monad::Monad,
 };
 
-use super::{FreeEffect, effect_list::EffectList, identity::Identity};
+use super::{
+    FreeEffect,
+    describe::Description,
+    effect_list::EffectList,
+    fuse::Fuse,
+    identity::Identity,
+    trace::{Trace, TracedEffect},
+};
 
 pub struct Free<M, U = (), Eff = Identity<M, U>>
 where
@@ -52,6 +59,38 @@ where
         self.start_effect.fold(start_monad)
     }
 
+    /// Same as `fold_map`, but also returns a `Trace` recording each step's timing and
+    /// whether the monad was left empty after it.
+    pub fn fold_map_traced(&self, start_monad: Eff::In) -> (Eff::Out, Trace)
+    where
+        Eff: TracedEffect,
+    {
+        let mut trace = Trace::new();
+        let out = self.start_effect.fold_traced(start_monad, &mut trace);
+        (out, trace)
+    }
+
+    /// Fuse each run of consecutive map steps, and a bind following them, into a single
+    /// step, see `Fuse`.
+    pub fn fuse(self) -> Free<M, U, Eff::Fused>
+    where
+        Eff: Fuse,
+    {
+        Free::<M, U, _> {
+            start_effect: self.start_effect.fuse(),
+            _ph1: PhantomData,
+            _ph2: PhantomData,
+        }
+    }
+
+    /// Describe the structure of the pipeline without running it.
+    pub fn describe(&self) -> Description {
+        let mut steps = vec![];
+        self.start_effect.describe(&mut steps);
+        Description::new(steps)
+    }
+
+    #[allow(clippy::should_implement_trait)]
     pub fn add<NewEff>(self, effect: NewEff) -> Free<M, U, EffectList<NewEff, Eff>>
     where
         NewEff: FreeEffect<In = Eff::Out>,
@@ -63,6 +102,9 @@ where
         }
     }
 
+    /// Add a step mapping `func` over the monad.  Consecutive maps are not fused; see
+    /// `fuse`.
+    #[allow(clippy::type_complexity)]
     pub fn map<V, W>(
         self,
         func: impl Fn(V) -> W + Send + Clone + 'static,
@@ -75,6 +117,9 @@ where
         self.add(FreeMap::<V, W, Eff::Out>::new(func))
     }
 
+    /// Add a step binding the monad to `func`.  A bind following a map is not fused
+    /// with it; see `fuse`.
+    #[allow(clippy::type_complexity)]
     pub fn bind<V, W, MOut>(
         self,
         func: impl Fn(V) -> MOut + Send + Clone + 'static,
@@ -87,6 +132,35 @@ where
     {
         self.add(FreeBind::<V, W, Eff::Out>::new(func))
     }
+
+    #[allow(clippy::type_complexity)]
+    pub fn map_labeled<V, W>(
+        self,
+        label: impl Into<String>,
+        func: impl Fn(V) -> W + Send + Clone + 'static,
+    ) -> Free<M, U, EffectList<FreeMap<V, W, Eff::Out>, Eff>>
+    where
+        V: Send + 'static,
+        W: Send + 'static,
+        Eff::Out: Monad<W, MonadT = V> + Send,
+    {
+        self.add(FreeMap::<V, W, Eff::Out>::new(func).with_label(label))
+    }
+
+    #[allow(clippy::type_complexity)]
+    pub fn bind_labeled<V, W, MOut>(
+        self,
+        label: impl Into<String>,
+        func: impl Fn(V) -> MOut + Send + Clone + 'static,
+    ) -> Free<M, U, EffectList<FreeBind<V, W, Eff::Out>, Eff>>
+    where
+        V: Send + 'static,
+        W: Send + 'static,
+        Eff::Out: Monad<W, MonadT = V, MonadOut = MOut> + Send + 'static,
+        MOut: Monad<MonadT = W> + Send + 'static,
+    {
+        self.add(FreeBind::<V, W, Eff::Out>::new(func).with_label(label))
+    }
 }
 
 #[cfg(test)]
@@ -100,7 +174,7 @@ mod test {
     fn test_new_with_identity() {
         let input = Some(34u32);
         let free = Free::<Option<_>>::new();
-        let out = free.fold_map(input.clone());
+        let out = free.fold_map(input);
         assert_eq!(out, input)
     }
 
@@ -109,7 +183,7 @@ mod test {
         let input = Some(34u32);
         let ident = Identity::<Option<_>, u32>::new();
         let free = Free::<Option<_>, u32, _>::new_effect(ident);
-        let out = free.fold_map(input.clone());
+        let out = free.fold_map(input);
         assert_eq!(out, input)
     }
 
@@ -121,7 +195,7 @@ mod test {
             Identity::<Option<_>, u32>::new(),
         );
         let free = Free::<Option<_>, u32, _>::new_effect(list);
-        let out = free.fold_map(input.clone());
+        let out = free.fold_map(input);
         assert_eq!(out, input)
     }
 
@@ -131,7 +205,7 @@ mod test {
         let free = Free::<Option<_>>::new();
         let new_effect = Identity::<Option<_>, u32>::new();
         let free = free.add(new_effect);
-        let out = free.fold_map(input.clone());
+        let out = free.fold_map(input);
         assert_eq!(out, input)
     }
 
@@ -146,7 +220,7 @@ mod test {
 
         let new_effect = Identity::<Option<_>, u32>::new();
         let free = free.add(new_effect);
-        let out = free.fold_map(input.clone());
+        let out = free.fold_map(input);
         assert_eq!(out, input)
     }
 
@@ -155,7 +229,7 @@ mod test {
         let input = Some(34u32);
         let mapping = FreeMap::<u32, u32, Option<_>>::new(|t| t);
         let free = Free::<Option<_>, u32, _>::new_effect(mapping);
-        let out = free.fold_map(input.clone());
+        let out = free.fold_map(input);
         assert_eq!(out, input)
     }
 
@@ -216,9 +290,9 @@ mod test {
     #[test]
     fn test_new_with_effect_bind() {
         let input = Some(34u32);
-        let mapping = FreeBind::<u32, u32, Option<_>>::new(|t| Some(t));
+        let mapping = FreeBind::<u32, u32, Option<_>>::new(Some);
         let free = Free::<Option<_>, u32, _>::new_effect(mapping);
-        let out = free.fold_map(input.clone());
+        let out = free.fold_map(input);
         assert_eq!(out, input)
     }
 
@@ -279,4 +353,27 @@ mod test {
         let out = free.fold_map(input.clone());
         assert_eq!(out, Some("3".to_string()))
     }
+
+    #[test]
+    fn test_describe_labels() {
+        let free = Free::<Option<String>, ()>::new()
+            .map_labeled("length", |t| t.len())
+            .bind(|t| Some(t.to_string()));
+        let desc = free.describe();
+        assert_eq!(
+            desc.steps.iter().map(|s| s.name()).collect::<Vec<_>>(),
+            vec!["identity", "length", "bind"]
+        );
+        assert_eq!(desc.to_string(), "0: identity\n1: map [length]\n2: bind\n");
+    }
+
+    #[test]
+    fn test_traced_bind_to_none() {
+        let free = Free::<Option<String>, ()>::new()
+            .bind_labeled("drop", |_t| None::<u32>)
+            .map(|t| t + 1);
+        let (out, trace) = free.fold_map_traced(Some("dog".to_string()));
+        assert!(out.is_none());
+        assert_eq!(trace.first_empty().map(|s| s.name()), Some("drop"));
+    }
 }
@@ -1,4 +1,10 @@
-use crate::typeclasses::{free_effect::FreeEffect, monad::Monad};
+use crate::typeclasses::{
+    free_effect::{
+        FreeEffect,
+        trace::{Trace, Traceable, TracedEffect},
+    },
+    monad::Monad,
+};
 
 trait CloneableFn<T, U, In>: Fn(T) -> In::MonadOut + Send
 where
@@ -44,6 +50,7 @@ where
     U: 'static + Send,
 {
     func: Box<dyn CloneableFn<T, U, In>>,
+    label: Option<String>,
 }
 
 impl<T, U, In> FreeBind<T, U, In>
@@ -55,8 +62,19 @@ where
     pub fn new(func: impl Fn(T) -> In::MonadOut + Send + Clone + 'static) -> FreeBind<T, U, In> {
         FreeBind {
             func: Box::new(func),
+            label: None,
         }
     }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    /// The step's function and label, for fusing it with a map before it.
+    pub(crate) fn into_parts(self) -> (impl Fn(T) -> In::MonadOut + Send + Clone, Option<String>) {
+        (self.func, self.label)
+    }
 }
 
 impl<T, U, In> FreeEffect for FreeBind<T, U, In>
@@ -72,4 +90,22 @@ where
     fn fold(&self, source: Self::In) -> Self::Out {
         Self::In::bind(source, self.func.clone())
     }
+    fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+    fn kind(&self) -> &'static str {
+        "bind"
+    }
+}
+
+impl<T, U, In> TracedEffect for FreeBind<T, U, In>
+where
+    In: Monad<U, MonadT = T> + Send + 'static,
+    In::MonadOut: Traceable,
+    T: Send + 'static,
+    U: Send + 'static,
+{
+    fn fold_traced(&self, source: Self::In, trace: &mut Trace) -> Self::Out {
+        trace.record(self, || self.fold(source))
+    }
 }
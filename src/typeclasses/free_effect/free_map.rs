@@ -1,6 +1,12 @@
 use std::marker::PhantomData;
 
-use crate::typeclasses::{free_effect::FreeEffect, monad::Monad};
+use crate::typeclasses::{
+    free_effect::{
+        FreeEffect,
+        trace::{Trace, Traceable, TracedEffect},
+    },
+    monad::Monad,
+};
 
 trait CloneableFn<T, U>: Fn(T) -> U + Send
 where
@@ -41,6 +47,7 @@ where
     In: Monad<U> + Send,
 {
     func: Box<dyn CloneableFn<T, U>>,
+    label: Option<String>,
     _ph: PhantomData<In>,
 }
 
@@ -53,9 +60,35 @@ where
     pub fn new(func: impl Fn(T) -> U + Send + Clone + 'static) -> Self {
         FreeMap {
             func: Box::new(func),
+            label: None,
             _ph: PhantomData,
         }
     }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    /// The step's function and label, for fusing it with the steps around it.
+    pub(crate) fn into_parts(self) -> (MapFn<T, U>, Option<String>) {
+        (MapFn(self.func), self.label)
+    }
+}
+
+/// The function of a map step, taken out of it to be fused with others (see `Fuse`).
+pub struct MapFn<T: Send, U: Send>(Box<dyn CloneableFn<T, U>>);
+
+impl<T: Send, U: Send> MapFn<T, U> {
+    pub(crate) fn call(&self, t: T) -> U {
+        (self.0)(t)
+    }
+}
+
+impl<T: Send + 'static, U: Send + 'static> Clone for MapFn<T, U> {
+    fn clone(&self) -> Self {
+        MapFn(self.0.clone())
+    }
 }
 
 impl<T, U, In> FreeEffect for FreeMap<T, U, In>
@@ -71,4 +104,22 @@ where
     fn fold(&self, source: Self::In) -> Self::Out {
         Self::In::fmap(source, self.func.clone())
     }
+    fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+    fn kind(&self) -> &'static str {
+        "map"
+    }
+}
+
+impl<T, U, In> TracedEffect for FreeMap<T, U, In>
+where
+    T: Send + 'static,
+    U: Send + 'static,
+    In: Monad<U, MonadT = T> + Send,
+    In::MonadOut: Traceable,
+{
+    fn fold_traced(&self, source: Self::In, trace: &mut Trace) -> Self::Out {
+        trace.record(self, || self.fold(source))
+    }
 }
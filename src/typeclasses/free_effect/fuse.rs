@@ -0,0 +1,368 @@
+use std::marker::PhantomData;
+
+use crate::typeclasses::monad::Monad;
+
+use super::{
+    FreeEffect,
+    effect_list::EffectList,
+    free_bind::FreeBind,
+    free_map::{FreeMap, MapFn},
+    identity::Identity,
+    trace::{Trace, Traceable, TracedEffect},
+};
+
+/// Fusion of consecutive steps, as done by `Free::fuse`.
+///
+/// Each `map` or `bind` step of a pipeline makes one full `fmap`/`bind` call over the
+/// monad (a full pass and a new allocation for a `Vec`), and clones its boxed function
+/// on every `fold_map`.  Fusing composes each run of consecutive maps, and a bind
+/// following them, into a single step, so the run is executed by a single call, with a
+/// single clone.  By the functor and monad laws the output is the same.
+///
+/// Fusion is a separate pass rather than something `map` does, as it changes what
+/// `describe`, tracing and the harness see: a fused run of steps is a single step,
+/// labelled with the labels of its parts joined by " + ".  It is implemented for
+/// pipelines made of map and bind steps.
+///
+/// ```rust
+/// use rust_effects::typeclasses::free_effect::free::Free;
+///
+/// let free = Free::<Vec<u32>, ()>::new()
+///     .map_labeled("inc", |a| a + 1)
+///     .map_labeled("double", |a| a * 2)
+///     .bind(|a| vec![a, a])
+///     .fuse();
+/// assert_eq!(free.fold_map(vec![1, 2]), vec![4, 4, 6, 6]);
+/// assert_eq!(free.describe().to_text(), "0: identity\n1: bind [inc + double]\n");
+/// ```
+pub trait Fuse: FreeEffect {
+    type Fused: FreeEffect<In = Self::In, Out = Self::Out>;
+    fn fuse(self) -> Self::Fused;
+}
+
+/// A fused effect to which the step `Next` can be added, fusing it into the last step
+/// when both can be run by a single call.
+pub trait Append<Next: FreeEffect<In = Self::Out>>: FreeEffect {
+    type Appended: FreeEffect<In = Self::In, Out = Next::Out>;
+    fn append(self, next: Next) -> Self::Appended;
+}
+
+/// The composed functions of a run of fused map steps.
+///
+/// Composing them statically, rather than boxing each composition, keeps a fused run
+/// of `n` maps at `n` calls through a box per value.
+pub trait MapChain<T, U>: Clone + Send + 'static {
+    fn call(&self, t: T) -> U;
+}
+
+impl<T, U> MapChain<T, U> for MapFn<T, U>
+where
+    T: Send + 'static,
+    U: Send + 'static,
+{
+    fn call(&self, t: T) -> U {
+        MapFn::call(self, t)
+    }
+}
+
+/// `first` then `second`, through values of type `U`.
+pub struct Then<A, B, U> {
+    first: A,
+    second: B,
+    _ph: PhantomData<fn(U)>,
+}
+
+impl<A: Clone, B: Clone, U> Clone for Then<A, B, U> {
+    fn clone(&self) -> Self {
+        Then {
+            first: self.first.clone(),
+            second: self.second.clone(),
+            _ph: PhantomData,
+        }
+    }
+}
+
+impl<T, U, W, A, B> MapChain<T, W> for Then<A, B, U>
+where
+    A: MapChain<T, U>,
+    B: MapChain<U, W>,
+    U: 'static,
+{
+    fn call(&self, t: T) -> W {
+        self.second.call(self.first.call(t))
+    }
+}
+
+/// A run of map steps fused into a single `fmap`, as built by `Free::fuse`.
+pub struct FusedMap<T, U, In, F> {
+    chain: F,
+    label: Option<String>,
+    _ph: PhantomData<fn(In, T) -> U>,
+}
+
+impl<T, U, In, F> FusedMap<T, U, In, F> {
+    fn new(chain: F, label: Option<String>) -> Self {
+        FusedMap {
+            chain,
+            label,
+            _ph: PhantomData,
+        }
+    }
+}
+
+impl<T, U, In, F> FreeEffect for FusedMap<T, U, In, F>
+where
+    T: Send + 'static,
+    U: Send + 'static,
+    In: Monad<U, MonadT = T> + Send,
+    F: MapChain<T, U>,
+{
+    type InU = U;
+    type OutU = U;
+    type In = In;
+    type Out = In::MonadOut;
+    fn fold(&self, source: Self::In) -> Self::Out {
+        let chain = self.chain.clone();
+        Self::In::fmap(source, move |t| chain.call(t))
+    }
+    fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+    fn kind(&self) -> &'static str {
+        "map"
+    }
+}
+
+impl<T, U, In, F> TracedEffect for FusedMap<T, U, In, F>
+where
+    T: Send + 'static,
+    U: Send + 'static,
+    In: Monad<U, MonadT = T> + Send,
+    In::MonadOut: Traceable,
+    F: MapChain<T, U>,
+{
+    fn fold_traced(&self, source: Self::In, trace: &mut Trace) -> Self::Out {
+        trace.record(self, || self.fold(source))
+    }
+}
+
+impl<M, U> Fuse for Identity<M, U>
+where
+    U: Send,
+    M: Monad<U>,
+{
+    type Fused = Self;
+    fn fuse(self) -> Self::Fused {
+        self
+    }
+}
+
+impl<CurrEff, NestEff> Fuse for EffectList<CurrEff, NestEff>
+where
+    CurrEff: FreeEffect<In = NestEff::Out>,
+    NestEff: Fuse,
+    NestEff::Fused: Append<CurrEff>,
+{
+    type Fused = <NestEff::Fused as Append<CurrEff>>::Appended;
+    fn fuse(self) -> Self::Fused {
+        let (curr_effect, next_effect) = self.into_pair();
+        next_effect.fuse().append(curr_effect)
+    }
+}
+
+/// Start a run of fused maps with `map`.
+fn start_run<T, U, In>(map: FreeMap<T, U, In>) -> FusedMap<T, U, In, MapFn<T, U>>
+where
+    T: Send,
+    U: Send,
+    In: Monad<U> + Send,
+{
+    let (func, label) = map.into_parts();
+    FusedMap::new(func, label)
+}
+
+impl<M, U, T, V> Append<FreeMap<T, V, M>> for Identity<M, U>
+where
+    U: Send,
+    M: Monad<U> + Monad<V, MonadT = T> + Send,
+    T: Send + 'static,
+    V: Send + 'static,
+{
+    type Appended = EffectList<FusedMap<T, V, M, MapFn<T, V>>, Self>;
+    fn append(self, next: FreeMap<T, V, M>) -> Self::Appended {
+        EffectList::from_pair(start_run(next), self)
+    }
+}
+
+impl<M, U, T, V> Append<FreeBind<T, V, M>> for Identity<M, U>
+where
+    U: Send,
+    M: Monad<U> + Monad<V, MonadT = T> + Send + 'static,
+    T: Send + 'static,
+    V: Send + 'static,
+{
+    type Appended = EffectList<FreeBind<T, V, M>, Self>;
+    fn append(self, next: FreeBind<T, V, M>) -> Self::Appended {
+        EffectList::from_pair(next, self)
+    }
+}
+
+impl<T, U, In, NestEff, V, W> Append<FreeMap<V, W, In::MonadOut>>
+    for EffectList<FreeBind<T, U, In>, NestEff>
+where
+    In: Monad<U, MonadT = T> + Send + 'static,
+    In::MonadOut: Monad<W, MonadT = V> + Send,
+    T: Send + 'static,
+    U: Send + 'static,
+    V: Send + 'static,
+    W: Send + 'static,
+    NestEff: FreeEffect<Out = In>,
+{
+    type Appended = EffectList<FusedMap<V, W, In::MonadOut, MapFn<V, W>>, Self>;
+    fn append(self, next: FreeMap<V, W, In::MonadOut>) -> Self::Appended {
+        EffectList::from_pair(start_run(next), self)
+    }
+}
+
+impl<T, U, In, NestEff, V, W> Append<FreeBind<V, W, In::MonadOut>>
+    for EffectList<FreeBind<T, U, In>, NestEff>
+where
+    In: Monad<U, MonadT = T> + Send + 'static,
+    In::MonadOut: Monad<W, MonadT = V> + Send + 'static,
+    T: Send + 'static,
+    U: Send + 'static,
+    V: Send + 'static,
+    W: Send + 'static,
+    NestEff: FreeEffect<Out = In>,
+{
+    type Appended = EffectList<FreeBind<V, W, In::MonadOut>, Self>;
+    fn append(self, next: FreeBind<V, W, In::MonadOut>) -> Self::Appended {
+        EffectList::from_pair(next, self)
+    }
+}
+
+/// A map after a run of maps: its function joins the run.
+impl<T, U, W, In, Mid, F, NestEff> Append<FreeMap<U, W, Mid>>
+    for EffectList<FusedMap<T, U, In, F>, NestEff>
+where
+    T: Send + 'static,
+    U: Send + 'static,
+    W: Send + 'static,
+    In: Monad<U, MonadT = T, MonadOut = Mid> + Send,
+    In: Monad<W, MonadT = T, MonadOut = <Mid as Monad<W>>::MonadOut>,
+    Mid: Monad<W, MonadT = U> + Send,
+    F: MapChain<T, U>,
+    NestEff: FreeEffect<Out = In>,
+{
+    type Appended = EffectList<FusedMap<T, W, In, Then<F, MapFn<U, W>, U>>, NestEff>;
+    fn append(self, next: FreeMap<U, W, Mid>) -> Self::Appended {
+        let (run, rest) = self.into_pair();
+        let (func, label) = next.into_parts();
+        let chain = Then {
+            first: run.chain,
+            second: func,
+            _ph: PhantomData,
+        };
+        EffectList::from_pair(FusedMap::new(chain, fuse_labels(run.label, label)), rest)
+    }
+}
+
+/// A bind after a run of maps: the maps run inside the `bind` call.
+impl<T, U, W, In, Mid, F, NestEff> Append<FreeBind<U, W, Mid>>
+    for EffectList<FusedMap<T, U, In, F>, NestEff>
+where
+    T: Send + 'static,
+    U: Send + 'static,
+    W: Send + 'static,
+    In: Monad<U, MonadT = T, MonadOut = Mid> + Send + 'static,
+    In: Monad<W, MonadT = T, MonadOut = <Mid as Monad<W>>::MonadOut>,
+    Mid: Monad<W, MonadT = U> + Send + 'static,
+    F: MapChain<T, U>,
+    NestEff: FreeEffect<Out = In>,
+{
+    type Appended = EffectList<FreeBind<T, W, In>, NestEff>;
+    fn append(self, next: FreeBind<U, W, Mid>) -> Self::Appended {
+        let (run, rest) = self.into_pair();
+        let (func, label) = next.into_parts();
+        let chain = run.chain;
+        let fused = FreeBind::new(move |t| func(chain.call(t)));
+        let fused = match fuse_labels(run.label, label) {
+            Some(label) => fused.with_label(label),
+            None => fused,
+        };
+        EffectList::from_pair(fused, rest)
+    }
+}
+
+fn fuse_labels(first: Option<String>, second: Option<String>) -> Option<String> {
+    match (first, second) {
+        (Some(first), Some(second)) => Some(format!("{} + {}", first, second)),
+        (first, second) => first.or(second),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::typeclasses::free_effect::free::Free;
+
+    #[test]
+    fn test_fuse_maps() {
+        let input = vec!["fox".to_string(), "horse".to_string()];
+
+        let free = Free::<Vec<String>, ()>::new()
+            .map_labeled("length", |t| t.len())
+            .map(|t| t * 2)
+            .map(|t| t.to_string())
+            .fuse();
+        assert_eq!(free.fold_map(input.clone()), vec!["6", "10"]);
+
+        let desc = free.describe();
+        assert_eq!(
+            desc.steps.iter().map(|s| s.name()).collect::<Vec<_>>(),
+            vec!["identity", "length"]
+        );
+    }
+
+    #[test]
+    fn test_fused_labels() {
+        let free = Free::<Option<String>, ()>::new()
+            .map_labeled("length", |t| t.len())
+            .map(|t| t * 2)
+            .map_labeled("halve", |t| t / 2)
+            .bind_labeled("only long", |t| if t > 3 { Some(t) } else { None })
+            .fuse();
+        assert_eq!(free.fold_map(Some("horse".to_string())), Some(5));
+        assert_eq!(
+            free.describe().to_text(),
+            "0: identity\n1: bind [length + halve + only long]\n"
+        );
+
+        let unlabeled = Free::<Option<String>, ()>::new()
+            .map(|t| t.len())
+            .map_labeled("double", |t| t * 2)
+            .fuse();
+        assert_eq!(unlabeled.describe().steps[1].name(), "double");
+    }
+
+    #[test]
+    fn test_fuse_matches_unfused() {
+        let only_long = |t: usize| if t > 3 { Some(t) } else { None };
+
+        let unfused = || {
+            Free::<Option<String>, ()>::new()
+                .map(|t| t.len())
+                .bind(only_long)
+                .map(|t| t + 1)
+                .map(|t| t * 2)
+                .bind(only_long)
+        };
+        let fused = unfused().fuse();
+
+        for input in [Some("dog".to_string()), Some("horse".to_string()), None] {
+            assert_eq!(fused.fold_map(input.clone()), unfused().fold_map(input));
+        }
+        // A map following a bind starts a new run
+        assert_eq!(unfused().describe().steps.len(), 6);
+        assert_eq!(fused.describe().steps.len(), 3);
+    }
+}
@@ -2,7 +2,10 @@ use std::marker::PhantomData;
 
 use crate::typeclasses::monad::Monad;
 
-use super::FreeEffect;
+use super::{
+    FreeEffect,
+    trace::{Trace, Traceable, TracedEffect},
+};
 
 pub struct Identity<M, U>
 where
@@ -25,6 +28,16 @@ where
     }
 }
 
+impl<M, U> Default for Identity<M, U>
+where
+    U: Send,
+    M: Monad<U>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<M, U> FreeEffect for Identity<M, U>
 where
     U: Send,
@@ -37,6 +50,19 @@ where
     fn fold(&self, source: Self::In) -> Self::Out {
         source
     }
+    fn kind(&self) -> &'static str {
+        "identity"
+    }
+}
+
+impl<M, U> TracedEffect for Identity<M, U>
+where
+    U: Send,
+    M: Monad<U> + Traceable,
+{
+    fn fold_traced(&self, source: Self::In, trace: &mut Trace) -> Self::Out {
+        trace.record(self, || self.fold(source))
+    }
 }
 
 #[cfg(test)]
@@ -1,10 +1,20 @@
+#[cfg(feature = "checkpoint")]
+pub mod checkpoint;
+#[cfg(feature = "config")]
+pub mod config;
+pub mod describe;
+pub mod dyn_free;
 pub mod effect_list;
 pub mod free;
 pub mod free_bind;
 pub mod free_map;
+pub mod fuse;
 pub mod identity;
+pub mod stepper;
+pub mod trace;
 
 use crate::typeclasses::monad::Monad;
+use describe::StepInfo;
 
 pub trait FreeEffect {
     /// Output type to the effect's input monad
@@ -17,6 +27,18 @@ pub trait FreeEffect {
     type Out: Monad<Self::OutU>;
     /// Effect's Conversion function from input to output monad
     fn fold(&self, source: Self::In) -> Self::Out;
+    /// Optional human-readable label used when tracing or describing a pipeline
+    fn label(&self) -> Option<&str> {
+        None
+    }
+    /// Short name for the kind of step (e.g. "map" or "bind")
+    fn kind(&self) -> &'static str {
+        "effect"
+    }
+    /// Append the step(s) this effect is made of, in execution order
+    fn describe(&self, steps: &mut Vec<StepInfo>) {
+        steps.push(StepInfo::new(self.kind(), self.label()));
+    }
 }
 
 #[cfg(test)]
@@ -28,7 +50,7 @@ mod test {
     #[test]
     fn test_vec_free_monad() {
         let only_evens = |a: usize| {
-            if a % 2 == 0 {
+            if a.is_multiple_of(2) {
                 pure![Vec](a)
             } else {
                 empty::<Vec<_>>()
@@ -54,9 +76,14 @@ mod test {
     }
 
     fn only_evens<M: Monad<MonadT = u32> + Monoid>(a: M::AppT) -> M {
-        if a % 2 == 0 { M::pure(a) } else { M::empty() }
+        if a.is_multiple_of(2) {
+            M::pure(a)
+        } else {
+            M::empty()
+        }
     }
 
+    #[allow(opaque_hidden_inferred_bound)]
     fn free_function<M, A>() -> Free<M, u32, impl FreeEffect<In = M, Out = A>>
     where
         M: Monad<u32, MonadT = String> + Send + 'static,
@@ -66,8 +93,7 @@ mod test {
     {
         let free = Free::<M, u32>::new();
         let free = free.map(|a: String| a.len() as u32);
-        let free = free.bind(only_evens);
-        free
+        free.bind(only_evens)
     }
 
     #[test]
@@ -0,0 +1,292 @@
+use std::{
+    any::{Any, TypeId, type_name},
+    fmt::{Display, Formatter},
+};
+
+use crate::typeclasses::free_effect::{
+    describe::Description,
+    dyn_free::{DynFree, TypeMismatch},
+};
+
+/// Errors raised by `Stepper::resume`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ResumeError {
+    /// The position is past the end of the pipeline, which has `len` steps
+    OutOfRange { position: usize, len: usize },
+    /// The intermediate monad isn't of the type output by the steps before `position`
+    Mismatch(TypeMismatch),
+}
+
+impl Display for ResumeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResumeError::OutOfRange { position, len } => write!(
+                f,
+                "cannot resume at step {} of a pipeline of {} steps",
+                position, len
+            ),
+            ResumeError::Mismatch(e) => write!(
+                f,
+                "cannot resume at step {}: expected {}, found {}",
+                e.position, e.expected, e.found
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ResumeError {}
+
+/// A cursor running a `DynFree` pipeline one step at a time.
+///
+/// After each step the intermediate monad can be inspected with `current`, and the
+/// stepper can be abandoned and later recreated with `Stepper::resume` from the saved
+/// position and intermediate value (see also `Checkpoint` with the `checkpoint`
+/// feature).  To step through a statically typed `Free`, convert it first with
+/// `DynFree::from_split` so each of its stages becomes a separate step.
+///
+/// ```rust
+/// use rust_effects::typeclasses::free_effect::{dyn_free::DynFree, free::Free};
+///
+/// let free = Free::<Vec<&str>, ()>::new()
+///     .map(|s: &str| s.len())
+///     .bind(|n| if n > 2 { vec![n] } else { vec![] });
+/// let free = DynFree::from_split(free);
+///
+/// let mut stepper = free.stepper(vec!["ox", "fox"]);
+/// assert!(stepper.step());
+/// assert_eq!(stepper.current::<Vec<usize>>(), Some(&vec![2, 3]));
+/// assert!(stepper.step());
+/// assert!(stepper.is_done());
+/// assert_eq!(stepper.finish(), vec![3]);
+/// ```
+pub struct Stepper<'a, In, Out> {
+    free: &'a DynFree<In, Out>,
+    position: usize,
+    current: Box<dyn Any + Send>,
+    current_type: (TypeId, &'static str),
+}
+
+impl<In, Out> DynFree<In, Out>
+where
+    In: Send + 'static,
+    Out: Send + 'static,
+{
+    /// Start stepping through the pipeline with the given input monad.
+    pub fn stepper(&self, start_monad: In) -> Stepper<'_, In, Out> {
+        Stepper {
+            free: self,
+            position: 0,
+            current: Box::new(start_monad),
+            current_type: (TypeId::of::<In>(), type_name::<In>()),
+        }
+    }
+}
+
+impl<'a, In, Out> Stepper<'a, In, Out>
+where
+    In: Send + 'static,
+    Out: Send + 'static,
+{
+    /// Resume stepping from `position` (the number of steps already run) with the
+    /// intermediate monad produced by those steps, whose type is named `current_type`
+    /// (as given by `type_name`) to report a mismatch.
+    pub fn resume(
+        free: &'a DynFree<In, Out>,
+        position: usize,
+        current: Box<dyn Any + Send>,
+        current_type: &'static str,
+    ) -> Result<Stepper<'a, In, Out>, ResumeError> {
+        let expected = Self::type_at(free, position).ok_or(ResumeError::OutOfRange {
+            position,
+            len: free.len(),
+        })?;
+        if Any::type_id(&*current) != expected.0 {
+            return Err(ResumeError::Mismatch(TypeMismatch {
+                position,
+                expected: expected.1,
+                found: current_type,
+            }));
+        }
+        Ok(Stepper {
+            free,
+            position,
+            current,
+            current_type: expected,
+        })
+    }
+
+    fn type_at(free: &DynFree<In, Out>, position: usize) -> Option<(TypeId, &'static str)> {
+        match position {
+            0 => Some((TypeId::of::<In>(), type_name::<In>())),
+            p => free.steps().get(p - 1).map(|step| step.out_type()),
+        }
+    }
+
+    /// The number of steps run so far.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// The total number of steps in the pipeline.
+    pub fn len(&self) -> usize {
+        self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.free.is_empty()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.position == self.free.len()
+    }
+
+    /// The fingerprint of the pipeline, see `DynFree::fingerprint`.
+    pub fn fingerprint(&self) -> u64 {
+        self.free.fingerprint()
+    }
+
+    /// The intermediate monad after the last step run, if it has type `T`.
+    pub fn current<T: 'static>(&self) -> Option<&T> {
+        self.current.downcast_ref::<T>()
+    }
+
+    pub fn current_any(&self) -> &(dyn Any + Send) {
+        &*self.current
+    }
+
+    pub fn current_type(&self) -> (TypeId, &'static str) {
+        self.current_type
+    }
+
+    /// Describe the steps which haven't been run yet.  A step may be described by
+    /// several entries, e.g. a whole `Free` converted with `DynFree::from`.
+    pub fn remaining(&self) -> Description {
+        let mut steps = vec![];
+        for step in &self.free.steps()[self.position..] {
+            step.describe(&mut steps);
+        }
+        Description::new(steps)
+    }
+
+    /// Run the next step.  Returns `false` if the pipeline was already finished.
+    pub fn step(&mut self) -> bool {
+        let Some(step) = self.free.steps().get(self.position) else {
+            return false;
+        };
+        let source = std::mem::replace(&mut self.current, Box::new(()));
+        self.current = step.run(source);
+        self.current_type = step.out_type();
+        self.position += 1;
+        true
+    }
+
+    /// Run all of the remaining steps and return the pipeline's output.
+    pub fn finish(mut self) -> Out {
+        while self.step() {}
+        *self
+            .current
+            .downcast::<Out>()
+            .expect("DynFree pipeline produced an unexpected monad type")
+    }
+
+    /// Stop stepping, returning the position, the intermediate monad and the name of
+    /// its type so they can be given to `Stepper::resume` later.
+    pub fn suspend(self) -> (usize, Box<dyn Any + Send>, &'static str) {
+        (self.position, self.current, self.current_type.1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::typeclasses::free_effect::{dyn_free::ErasedStep, free::Free, free_map::FreeMap};
+
+    fn pipeline() -> DynFree<Option<String>, Option<String>> {
+        DynFree::from_split(
+            Free::<Option<String>, ()>::new()
+                .map(|s| s.len())
+                .bind(|n| if n % 2 == 1 { Some(n) } else { None })
+                .map(|n: usize| n.to_string()),
+        )
+    }
+
+    #[test]
+    fn test_step_through() {
+        let free = pipeline();
+        let mut stepper = free.stepper(Some("dog".to_string()));
+        assert_eq!(stepper.len(), 3);
+        assert_eq!(
+            stepper.current::<Option<String>>(),
+            Some(&Some("dog".to_string()))
+        );
+
+        assert!(stepper.step());
+        assert_eq!(stepper.position(), 1);
+        assert_eq!(stepper.current::<Option<usize>>(), Some(&Some(3)));
+        assert_eq!(stepper.current::<Option<String>>(), None);
+        assert_eq!(stepper.remaining().to_text(), "0: bind\n1: map\n");
+
+        assert!(stepper.step());
+        assert!(stepper.step());
+        assert!(stepper.is_done());
+        assert!(!stepper.step());
+        assert_eq!(stepper.finish(), Some("3".to_string()));
+    }
+
+    #[test]
+    fn test_suspend_and_resume() {
+        let free = pipeline();
+        let mut stepper = free.stepper(Some("horse".to_string()));
+        stepper.step();
+        let (position, value, name) = stepper.suspend();
+
+        let stepper = Stepper::resume(&free, position, value, name).unwrap();
+        assert_eq!(stepper.finish(), Some("5".to_string()));
+    }
+
+    #[test]
+    fn test_resume_wrong_type() {
+        let free = pipeline();
+        let name = type_name::<Option<String>>();
+        let err = Stepper::resume(&free, 1, Box::new(Some("dog".to_string())), name).err();
+        assert_eq!(
+            err,
+            Some(ResumeError::Mismatch(TypeMismatch {
+                position: 1,
+                expected: type_name::<Option<usize>>(),
+                found: name,
+            }))
+        );
+
+        let err = Stepper::resume(&free, 4, Box::new(Some("dog".to_string())), name).err();
+        assert_eq!(
+            err,
+            Some(ResumeError::OutOfRange {
+                position: 4,
+                len: 3
+            })
+        );
+    }
+
+    #[test]
+    fn test_remaining_with_multi_info_steps() {
+        let whole = Free::<Option<String>, ()>::new()
+            .map_labeled("length", |s| s.len())
+            .map_labeled("double", |n| n * 2);
+        let free = DynFree::<Option<String>, Option<usize>>::try_from_steps(vec![
+            ErasedStep::new(DynFree::from(whole)),
+            ErasedStep::new(FreeMap::<usize, usize, Option<usize>>::new(|n| n + 1))
+                .with_label("inc"),
+        ])
+        .unwrap();
+        assert_eq!(free.describe().steps.len(), 4);
+
+        let mut stepper = free.stepper(Some("dog".to_string()));
+        assert_eq!(stepper.remaining(), free.describe());
+        stepper.step();
+        assert_eq!(stepper.remaining().to_text(), "0: map [inc]\n");
+        stepper.step();
+        assert!(stepper.remaining().steps.is_empty());
+        assert_eq!(stepper.finish(), Some(7));
+    }
+}
@@ -0,0 +1,123 @@
+use std::time::{Duration, Instant};
+
+use crate::typeclasses::free_effect::FreeEffect;
+
+/// Inspection of a monad's state while tracing a `Free` pipeline.
+///
+/// `is_empty_state` should report whether the monad is in the state its `Monoid::empty`
+/// would produce (`None` for `Option`, an empty `Vec`, `Err` for `Result`), which is the
+/// state in which any further `map` or `bind` steps will be skipped.
+///
+/// This is a separate trait because `Monoid` can only build the empty value, not tell
+/// whether a value is empty, and comparing with `PartialEq` would rule out monads like
+/// `CFuture`.  Monads which cannot tell without running keep the default, `None`, so
+/// their pipelines can still be traced for timings.
+pub trait Traceable {
+    fn is_empty_state(&self) -> Option<bool> {
+        None
+    }
+}
+
+/// An effect which can record its execution into a `Trace`.
+///
+/// All of the provided effects (`Identity`, `FreeMap`, `FreeBind` and `EffectList`)
+/// implement this trait whenever their output monad is `Traceable`.  Custom effects can
+/// implement it by wrapping their `fold` with `Trace::record`.
+pub trait TracedEffect: FreeEffect {
+    fn fold_traced(&self, source: Self::In, trace: &mut Trace) -> Self::Out;
+}
+
+/// Execution record for a single step of a `Free` pipeline.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StepTrace {
+    pub index: usize,
+    pub kind: &'static str,
+    pub label: Option<String>,
+    pub elapsed: Duration,
+    pub empty: Option<bool>,
+}
+
+impl StepTrace {
+    /// The label if one was given, otherwise the kind of the step
+    pub fn name(&self) -> &str {
+        self.label.as_deref().unwrap_or(self.kind)
+    }
+}
+
+/// Execution record of a `Free` pipeline, as returned by `Free::fold_map_traced`.
+///
+/// Note that the elapsed time of a step only covers the step's `fold`.  For lazy monads
+/// like `CFuture`, that is the time to build the future, not to run it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Trace {
+    pub steps: Vec<StepTrace>,
+}
+
+impl Trace {
+    pub fn new() -> Trace {
+        Trace { steps: vec![] }
+    }
+
+    /// Run an effect's fold and record the time taken and the state of the result.
+    pub fn record<E>(&mut self, effect: &E, run: impl FnOnce() -> E::Out) -> E::Out
+    where
+        E: FreeEffect + ?Sized,
+        E::Out: Traceable,
+    {
+        let start = Instant::now();
+        let out = run();
+        let elapsed = start.elapsed();
+        self.steps.push(StepTrace {
+            index: self.steps.len(),
+            kind: effect.kind(),
+            label: effect.label().map(str::to_string),
+            elapsed,
+            empty: out.is_empty_state(),
+        });
+        out
+    }
+
+    /// The first step which turned a non-empty monad into an empty one.  If the
+    /// pipeline was started with an empty monad, this will be the first step.
+    pub fn first_empty(&self) -> Option<&StepTrace> {
+        self.steps.iter().find(|step| step.empty == Some(true))
+    }
+
+    pub fn total_elapsed(&self) -> Duration {
+        self.steps.iter().map(|step| step.elapsed).sum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::typeclasses::free_effect::free::Free;
+
+    #[test]
+    fn test_trace_finds_emptying_step() {
+        let free = Free::<Vec<String>, ()>::new()
+            .map_labeled("length", |s| s.len())
+            .bind_labeled("long only", |n| if n > 5 { vec![n] } else { vec![] })
+            .map(|n| n * 2);
+        let (out, trace) = free.fold_map_traced(vec!["fox".to_string(), "ox".to_string()]);
+
+        assert!(out.is_empty());
+        assert_eq!(trace.steps.len(), 4);
+        assert_eq!(
+            trace.steps.iter().map(StepTrace::name).collect::<Vec<_>>(),
+            vec!["identity", "length", "long only", "map"]
+        );
+        assert_eq!(trace.first_empty().map(StepTrace::name), Some("long only"));
+        assert_eq!(trace.first_empty().map(|step| step.index), Some(2));
+    }
+
+    #[test]
+    fn test_trace_non_empty() {
+        let free = Free::<Option<u32>, ()>::new().map(|n| n + 1);
+        let (out, trace) = free.fold_map_traced(Some(3));
+
+        assert_eq!(out, Some(4));
+        assert!(trace.first_empty().is_none());
+        assert!(trace.steps.iter().all(|step| step.empty == Some(false)));
+    }
+}
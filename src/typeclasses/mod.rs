@@ -1,5 +1,6 @@
 pub mod applicative;
 pub mod applicative_functor;
+pub mod free_applicative;
 pub mod free_effect;
 pub mod functor;
 pub mod monad;
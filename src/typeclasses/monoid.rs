@@ -75,9 +75,7 @@ monoid_num_impl! { f64 }
 monoid_num_impl! { usize }
 
 impl Monoid for () {
-    fn empty() -> Self {
-        ()
-    }
+    fn empty() -> Self {}
 }
 
 impl Monoid for String {
@@ -64,9 +64,7 @@ sg_num_impl! { f64 }
 sg_num_impl! { usize }
 
 impl Semigroup for () {
-    fn combine(_a: Self, _b: Self) -> Self {
-        ()
-    }
+    fn combine(_a: Self, _b: Self) -> Self {}
 }
 
 impl Semigroup for String {
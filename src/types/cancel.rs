@@ -0,0 +1,245 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Formatter},
+    pin::Pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    task::{Context, Poll, Waker},
+};
+
+/// Error reported by a `CFuture` which was cancelled before it completed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl Display for Cancelled {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "future was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+type Finalizer = Box<dyn FnOnce() + Send>;
+
+/// Wakers and finalizers waiting on a token, by registration id, so that those no
+/// longer needed can be removed before the token is cancelled.
+#[derive(Default)]
+struct Listeners {
+    next_id: u64,
+    wakers: BTreeMap<u64, Waker>,
+    finalizers: BTreeMap<u64, Finalizer>,
+}
+
+impl Listeners {
+    fn register(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+}
+
+#[derive(Default)]
+struct TokenState {
+    cancelled: AtomicBool,
+    listeners: Mutex<Listeners>,
+}
+
+/// A handle used to cooperatively cancel `CFuture`s.
+///
+/// Clones of a token all refer to the same cancellation state: cancelling any of them
+/// cancels every future the token was attached to.  The token doesn't depend on any
+/// particular runtime.
+#[derive(Clone, Default)]
+pub struct CancelToken {
+    state: Arc<TokenState>,
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    /// Cancel the token, waking up everything waiting on it and running its finalizers.
+    /// Cancelling an already cancelled token does nothing.
+    pub fn cancel(&self) {
+        if self.state.cancelled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let listeners = std::mem::take(&mut *self.state.listeners.lock().unwrap());
+        listeners.wakers.into_values().for_each(Waker::wake);
+        listeners.finalizers.into_values().for_each(|f| f());
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// A future which resolves once the token is cancelled.
+    pub fn cancelled(&self) -> WaitCancelled {
+        WaitCancelled {
+            token: self.clone(),
+            id: None,
+        }
+    }
+
+    /// Run `func` when the token is cancelled, or right away if it already is.  The
+    /// returned registration can remove `func` once it is no longer needed.
+    pub fn on_cancel(&self, func: impl FnOnce() + Send + 'static) -> Registration {
+        let mut listeners = self.state.listeners.lock().unwrap();
+        let id = listeners.register();
+        if self.is_cancelled() {
+            drop(listeners);
+            func();
+        } else {
+            listeners.finalizers.insert(id, Box::new(func));
+        }
+        Registration {
+            token: self.clone(),
+            id,
+        }
+    }
+}
+
+#[cfg(test)]
+impl CancelToken {
+    /// The number of wakers and finalizers registered on the token.
+    pub(crate) fn listener_count(&self) -> (usize, usize) {
+        let listeners = self.state.listeners.lock().unwrap();
+        (listeners.wakers.len(), listeners.finalizers.len())
+    }
+}
+
+/// A finalizer added with `CancelToken::on_cancel`.  Dropping the registration leaves
+/// the finalizer in place.
+pub struct Registration {
+    token: CancelToken,
+    id: u64,
+}
+
+impl Registration {
+    /// Remove the finalizer, if it didn't run yet, so that cancelling the token later
+    /// won't run it.
+    pub fn remove(&self) {
+        let mut listeners = self.token.state.listeners.lock().unwrap();
+        let finalizer = listeners.finalizers.remove(&self.id);
+        // Dropped outside the lock, in case the finalizer owns a token
+        drop(listeners);
+        drop(finalizer);
+    }
+}
+
+/// Future returned by `CancelToken::cancelled`.  Its waker is removed from the token
+/// when it completes or is dropped.
+pub struct WaitCancelled {
+    token: CancelToken,
+    id: Option<u64>,
+}
+
+impl Future for WaitCancelled {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.token.is_cancelled() {
+            self.id = None;
+            return Poll::Ready(());
+        }
+        let this = &mut *self;
+        let mut listeners = this.token.state.listeners.lock().unwrap();
+        // Checked again under the lock, as `cancel` takes the wakers under it
+        if this.token.is_cancelled() {
+            this.id = None;
+            return Poll::Ready(());
+        }
+        let id = *this.id.get_or_insert_with(|| listeners.register());
+        match listeners.wakers.get_mut(&id) {
+            Some(waker) if waker.will_wake(cx.waker()) => {}
+            Some(waker) => waker.clone_from(cx.waker()),
+            None => {
+                listeners.wakers.insert(id, cx.waker().clone());
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for WaitCancelled {
+    fn drop(&mut self) {
+        if let Some(id) = self.id.take() {
+            self.token
+                .state
+                .listeners
+                .lock()
+                .unwrap()
+                .wakers
+                .remove(&id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::AtomicU32;
+
+    #[test]
+    fn test_cancel_token() {
+        let token = CancelToken::new();
+        let other = token.clone();
+        let count = Arc::new(AtomicU32::new(0));
+        let c = count.clone();
+        token.on_cancel(move || {
+            c.fetch_add(1, Ordering::SeqCst);
+        });
+        assert!(!other.is_cancelled());
+        other.cancel();
+        other.cancel();
+        assert!(token.is_cancelled());
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        let c = count.clone();
+        token.on_cancel(move || {
+            c.fetch_add(1, Ordering::SeqCst);
+        });
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_wait_cancelled() {
+        let token = CancelToken::new();
+        let waiter = tokio::spawn(token.cancelled());
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+        token.cancel();
+        waiter.await.unwrap();
+        token.cancelled().await;
+    }
+
+    #[test]
+    fn test_listeners_removed() {
+        let token = CancelToken::new();
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        for _ in 0..10 {
+            let mut wait = token.cancelled();
+            assert!(Pin::new(&mut wait).poll(&mut cx).is_pending());
+            assert!(Pin::new(&mut wait).poll(&mut cx).is_pending());
+            assert_eq!(token.listener_count(), (1, 0));
+        }
+        assert_eq!(token.listener_count(), (0, 0));
+
+        let count = Arc::new(AtomicU32::new(0));
+        let c = count.clone();
+        let removed = token.on_cancel(move || {
+            c.fetch_add(1, Ordering::SeqCst);
+        });
+        let c = count.clone();
+        let _kept = token.on_cancel(move || {
+            c.fetch_add(10, Ordering::SeqCst);
+        });
+        assert_eq!(token.listener_count(), (0, 2));
+        removed.remove();
+        assert_eq!(token.listener_count(), (0, 1));
+        token.cancel();
+        assert_eq!(count.load(Ordering::SeqCst), 10);
+    }
+}
@@ -1,24 +1,597 @@
 use crate::prelude::typeclasses::*;
-use futures::future::{BoxFuture, Shared, lazy};
-use futures_util::FutureExt;
+use crate::typeclasses::free_effect::trace::Traceable;
+use crate::types::cancel::{CancelToken, Cancelled};
+use crate::types::clock::Clock;
+use crate::types::executor::block_on;
+use crate::types::schedule::{Schedule, Step};
+#[cfg(feature = "tokio")]
+use crate::types::{clock::SystemClock, io::panic_message};
+use futures::future::{BoxFuture, FutureExt, Shared, join, join_all, select, select_all};
+use std::{
+    fmt::{Display, Formatter},
+    sync::{
+        Arc, Mutex, OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
 
+/// Defines `CFuture::par_mapN`, which polls N futures concurrently and combines
+/// their results with `func`.
+macro_rules! par_map_impl {
+    ($name:ident, $($arg:ident: $t:ident),+) => {
+        #[allow(clippy::too_many_arguments)]
+        pub fn $name<$($t),+>(
+            $($arg: CFuture<$t>,)+
+            func: impl FnOnce($($t),+) -> A + Send + 'static,
+        ) -> CFuture<A>
+        where
+            $($t: Clone + Send + Sync + 'static,)+
+        {
+            let token = linked_token([$($arg.token()),+]);
+            CFuture::from_outcome(async move {
+                let ($($arg,)+) = futures::join!($(branch($arg)),+);
+                Ok(func($($arg?),+))
+            }, Some(token))
+        }
+    };
+}
+
+/// The result of `CFuture::race`: which of the two futures finished first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Either<L, R> {
+    Left(L),
+    Right(R),
+}
+
+/// Error returned by `CFuture::timeout` when the deadline passed first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Elapsed(pub Duration);
+
+impl Display for Elapsed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "deadline of {:?} elapsed", self.0)
+    }
+}
+
+impl std::error::Error for Elapsed {}
+
+#[cfg(feature = "tokio")]
+/// Error returned by spawned `CFuture`s when the task didn't complete.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TaskError {
+    /// The task panicked, with the panic message if it was a string
+    Panicked(String),
+    /// The task was cancelled, through its `Fiber` or by the runtime (e.g. during shutdown)
+    Cancelled,
+}
+
+#[cfg(feature = "tokio")]
+impl From<tokio::task::JoinError> for TaskError {
+    fn from(err: tokio::task::JoinError) -> TaskError {
+        if !err.is_panic() {
+            return TaskError::Cancelled;
+        }
+        TaskError::Panicked(panic_message(err.into_panic()))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Display for TaskError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TaskError::Panicked(msg) => write!(f, "task panicked: {}", msg),
+            TaskError::Cancelled => write!(f, "task was cancelled"),
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl std::error::Error for TaskError {}
+
+type Outcome<A> = Result<A, Cancelled>;
+
+#[derive(Clone)]
+enum Repr<A> {
+    /// An already known value, cloned for each poll
+    Ready(A),
+    /// A function of known values, run when first polled
+    Deferred(Arc<dyn Force<A>>),
+    Pending(Shared<BoxFuture<'static, Outcome<A>>>),
+}
+
+/// A computation over values which are already known, such as a function mapped over
+/// a ready value.  It runs once, when one of the clones sharing it is first polled, and
+/// the future it made is then shared by all of them.
+struct Deferred<A, F> {
+    thunk: Mutex<Option<F>>,
+    forced: OnceLock<CFuture<A>>,
+}
+
+trait Force<A>: Send + Sync {
+    /// Run the computation if it didn't run yet, returning the future it made.
+    fn force(&self) -> CFuture<A>;
+    /// Like `force`, for a computation no other future shares.
+    fn force_unshared(&mut self) -> CFuture<A>;
+    fn forced(&self) -> Option<&CFuture<A>>;
+}
+
+impl<A, F> Force<A> for Deferred<A, F>
+where
+    A: Clone + Send + Sync,
+    F: FnOnce() -> CFuture<A> + Send,
+{
+    fn force(&self) -> CFuture<A> {
+        self.forced
+            .get_or_init(|| {
+                let thunk = self.thunk.lock().unwrap_or_else(|e| e.into_inner()).take();
+                thunk.expect("deferred CFuture computation panicked")()
+            })
+            .clone()
+    }
+    fn force_unshared(&mut self) -> CFuture<A> {
+        match self
+            .thunk
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+        {
+            Some(thunk) => thunk(),
+            None => self.force(),
+        }
+    }
+    fn forced(&self) -> Option<&CFuture<A>> {
+        self.forced.get()
+    }
+}
+
+/// A shared, cloneable future.
+///
+/// Values which are already known (from `lazy`/`pure`) are kept inline, without boxing
+/// or sharing a future.  `fmap`, `bind`, `seq` and `combine` over known values don't
+/// build a future either: the function is kept aside, and only runs when the result is
+/// first polled (once for all of its clones), so chains over ready values stay cheap
+/// while still running nothing until awaited.  All other futures are boxed and shared,
+/// so clones poll the same underlying future.
+///
+/// # Cancellation
+///
+/// A pending future can be tied to a `CancelToken` with `cancellable`.  Once the token is
+/// cancelled the future stops being polled, and so do the futures derived from it with
+/// `fmap`, `bind`, `seq` and `combine` (as well as the `par_*` and `race` combinators):
+/// their continuations never run and they resolve as cancelled.  The futures made by
+/// `par_*`, `race` and `select_all` have a token of their own, so they can be cancelled
+/// even if none of the futures they poll can; cancelling it also cancels the tokens of
+/// those futures.
+///
+/// Cancellation belongs to the token, not to a handle.  All clones of a `CFuture` share
+/// one underlying computation, so cancelling through any of them (`cancel`, or the token
+/// itself) cancels it for every clone and everything derived from it, while merely
+/// dropping a clone never cancels anything.  Futures which already completed, including
+/// ready values, are not affected by cancellation.
+///
+/// Awaiting a cancelled `CFuture` directly panics, since there is no value to return; use
+/// `outcome` to observe cancellation as a `Result` instead.
 #[derive(Clone)]
 pub struct CFuture<A> {
-    inner: Shared<BoxFuture<'static, A>>,
+    inner: Repr<A>,
+    token: Option<CancelToken>,
 }
 
 impl<A: Clone + Sync + Send + 'static> CFuture<A> {
     pub fn lazy(val: A) -> CFuture<A> {
-        CFuture::new(lazy(|_| val))
+        CFuture {
+            inner: Repr::Ready(val),
+            token: None,
+        }
+    }
+
+    /// A future resolving to the future made by `thunk`, which only runs when polled.
+    fn defer(thunk: impl FnOnce() -> CFuture<A> + Send + 'static) -> CFuture<A> {
+        CFuture {
+            inner: Repr::Deferred(Arc::new(Deferred {
+                thunk: Mutex::new(Some(thunk)),
+                forced: OnceLock::new(),
+            })),
+            token: None,
+        }
+    }
+
+    /// Run the deferred computations this future is made of, if any, returning a ready
+    /// or pending future.
+    fn forced(mut self) -> CFuture<A> {
+        while let Repr::Deferred(deferred) = &mut self.inner {
+            self = force(deferred);
+        }
+        self
+    }
+
+    fn is_pending(&self) -> bool {
+        matches!(self.inner, Repr::Pending(_))
     }
 
     pub fn new(fut: impl Future<Output = A> + Send + 'static) -> CFuture<A> {
+        CFuture::from_outcome(fut.map(Ok), None)
+    }
+
+    /// Build a pending future, stopping it when `token` is cancelled.
+    pub(crate) fn from_outcome(
+        fut: impl Future<Output = Outcome<A>> + Send + 'static,
+        token: Option<CancelToken>,
+    ) -> CFuture<A> {
+        let fut = match &token {
+            Some(token) => select(fut.boxed(), token.cancelled())
+                .map(|res| match res {
+                    futures::future::Either::Left((res, _)) => res,
+                    futures::future::Either::Right(_) => Err(Cancelled),
+                })
+                .boxed(),
+            None => fut.boxed(),
+        };
         CFuture {
-            inner: fut.boxed().shared(),
+            inner: Repr::Pending(fut.shared()),
+            token,
+        }
+    }
+
+    pub(crate) async fn into_outcome(self) -> Outcome<A> {
+        match self.forced().inner {
+            Repr::Ready(a) => Ok(a),
+            Repr::Pending(fut) => fut.await,
+            Repr::Deferred(_) => unreachable!("forced CFuture is still deferred"),
+        }
+    }
+
+    /// Block the current thread until the value is available, using the built-in
+    /// executor (see `executor::block_on`).
+    pub fn wait(self) -> A {
+        block_on(self)
+    }
+
+    /// Whether the value is already known, in which case awaiting won't suspend.
+    pub fn is_ready(&self) -> bool {
+        matches!(self.inner, Repr::Ready(_))
+    }
+
+    fn into_ready(self) -> Result<A, CFuture<A>> {
+        match self.inner {
+            Repr::Ready(a) => Ok(a),
+            inner => Err(CFuture {
+                inner,
+                token: self.token,
+            }),
+        }
+    }
+
+    /// A new future tied to `token`: once the token is cancelled, it and the futures
+    /// derived from it stop and resolve as cancelled.  The future this is called on is
+    /// left as it was.  Ready values are returned as is.
+    pub fn cancellable(&self, token: &CancelToken) -> CFuture<A> {
+        if self.is_ready() {
+            return self.clone();
+        }
+        CFuture::from_outcome(self.clone().into_outcome(), Some(token.clone()))
+    }
+
+    /// The token this future was tied to, directly or through the future it was derived
+    /// from.
+    pub fn token(&self) -> Option<&CancelToken> {
+        self.token.as_ref()
+    }
+
+    /// Cancel this future's token, if it has one.  See the type documentation for how
+    /// this affects clones.
+    pub fn cancel(&self) {
+        if let Some(token) = &self.token {
+            token.cancel();
+        }
+    }
+
+    /// Whether this future resolved, or will resolve, as cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        match &self.inner {
+            Repr::Ready(_) => false,
+            Repr::Deferred(deferred) => deferred.forced().is_some_and(CFuture::is_cancelled),
+            Repr::Pending(fut) => match fut.peek() {
+                Some(res) => res.is_err(),
+                None => self.token.as_ref().is_some_and(CancelToken::is_cancelled),
+            },
+        }
+    }
+
+    /// Resolve to the value, or to `Err(Cancelled)` if the future was cancelled.
+    pub fn outcome(&self) -> CFuture<Outcome<A>> {
+        match &self.inner {
+            Repr::Ready(a) => CFuture::lazy(Ok(a.clone())),
+            _ => CFuture::new(self.clone().into_outcome()),
+        }
+    }
+
+    /// Run `func` if this future is cancelled before completing.  With a token, `func`
+    /// runs as soon as the token is cancelled, even if nobody is polling the future.
+    pub fn on_cancel(&self, func: impl FnOnce() + Send + 'static) -> CFuture<A> {
+        if self.is_ready() {
+            return self.clone();
+        }
+        let done = Arc::new(AtomicBool::new(false));
+        let func = Arc::new(Mutex::new(Some(func)));
+        let run_once = {
+            let (done, func) = (done.clone(), func.clone());
+            move || {
+                if !done.load(Ordering::SeqCst)
+                    && let Some(func) = func.lock().unwrap().take()
+                {
+                    func()
+                }
+            }
+        };
+        let registration = self
+            .token
+            .as_ref()
+            .map(|token| token.on_cancel(run_once.clone()));
+        let fut = self.clone().into_outcome();
+        CFuture::from_outcome(
+            async move {
+                let res = fut.await;
+                match res {
+                    Ok(_) => {
+                        done.store(true, Ordering::SeqCst);
+                        if let Some(registration) = registration {
+                            registration.remove();
+                        }
+                    }
+                    Err(_) => run_once(),
+                }
+                res
+            },
+            self.token.clone(),
+        )
+    }
+
+    /// Like `seq`, but polls the function and the value concurrently.
+    pub fn par_seq<T, F>(m: CFuture<T>, func: CFuture<F>) -> CFuture<A>
+    where
+        T: Clone + Send + Sync + 'static,
+        F: Fn(T) -> A + Clone + Send + Sync + 'static,
+    {
+        let token = linked_token([m.token(), func.token()]);
+        CFuture::from_outcome(
+            async move {
+                let (f, t) = join(branch(func), branch(m)).await;
+                Ok(f?(t?))
+            },
+            Some(token),
+        )
+    }
+
+    par_map_impl! { par_map2, a: T1, b: T2 }
+    par_map_impl! { par_map3, a: T1, b: T2, c: T3 }
+    par_map_impl! { par_map4, a: T1, b: T2, c: T3, d: T4 }
+    par_map_impl! { par_map5, a: T1, b: T2, c: T3, d: T4, e: T5 }
+    par_map_impl! { par_map6, a: T1, b: T2, c: T3, d: T4, e: T5, f: T6 }
+    par_map_impl! { par_map7, a: T1, b: T2, c: T3, d: T4, e: T5, f: T6, g: T7 }
+    par_map_impl! { par_map8, a: T1, b: T2, c: T3, d: T4, e: T5, f: T6, g: T7, h: T8 }
+
+    /// Like `combine`, but polls both futures concurrently.
+    pub fn par_combine(a: CFuture<A>, b: CFuture<A>) -> CFuture<A>
+    where
+        A: Semigroup,
+    {
+        CFuture::par_map2(a, b, A::combine)
+    }
+
+    /// Like `combine_m`, but polls both futures concurrently.
+    pub fn par_combine_m(a: CFuture<A>, b: CFuture<A>) -> CFuture<A>
+    where
+        A: Semigroup,
+    {
+        CFuture::par_map2(a, b, A::combine_m)
+    }
+
+    /// Poll both futures, resolving to whichever finishes first.  The loser is dropped
+    /// from the race, though clones of it elsewhere can still be awaited.
+    pub fn race<B>(a: CFuture<A>, b: CFuture<B>) -> CFuture<Either<A, B>>
+    where
+        B: Clone + Send + Sync + 'static,
+    {
+        let token = linked_token([a.token(), b.token()]);
+        CFuture::from_outcome(
+            async move {
+                match select(branch(a), branch(b)).await {
+                    futures::future::Either::Left((a, _)) => a.map(Either::Left),
+                    futures::future::Either::Right((b, _)) => b.map(Either::Right),
+                }
+            },
+            Some(token),
+        )
+    }
+
+    /// Resolve to the first of the futures to finish along with its index, or `None`
+    /// when there are no futures.
+    pub fn select_all(futures: Vec<CFuture<A>>) -> CFuture<Option<(usize, A)>> {
+        if futures.is_empty() {
+            return CFuture::lazy(None);
+        }
+        let token = linked_token(futures.iter().map(CFuture::token));
+        CFuture::from_outcome(
+            async move {
+                let (a, idx, _) = select_all(futures.into_iter().map(branch)).await;
+                a.map(|a| Some((idx, a)))
+            },
+            Some(token),
+        )
+    }
+
+    /// Resolve to `Err(Elapsed)` if this future doesn't finish within `duration`, as
+    /// measured by `clock`.
+    pub fn timeout_on(
+        &self,
+        clock: &(impl Clock + ?Sized),
+        duration: Duration,
+    ) -> CFuture<Result<A, Elapsed>> {
+        let race = CFuture::race(self.clone(), clock.sleep(duration));
+        fmap(race, move |res| match res {
+            Either::Left(a) => Ok(a),
+            Either::Right(()) => Err(Elapsed(duration)),
+        })
+    }
+
+    /// Resolve to `alt` if this future doesn't finish within `duration`, as measured by
+    /// `clock`.
+    pub fn with_fallback_after_on(
+        &self,
+        clock: &(impl Clock + ?Sized),
+        duration: Duration,
+        alt: CFuture<A>,
+    ) -> CFuture<A> {
+        let race = CFuture::race(self.clone(), clock.sleep(duration));
+        bind(race, move |res| match res {
+            Either::Left(a) => CFuture::lazy(a),
+            Either::Right(()) => alt.clone(),
+        })
+    }
+}
+
+impl<A: Clone + Sync + Send + 'static> CFuture<Vec<A>> {
+    /// Map every item into a future and poll them all concurrently, keeping the order
+    /// of the input in the result.
+    pub fn par_traverse<T>(items: Vec<T>, func: impl Fn(T) -> CFuture<A>) -> CFuture<Vec<A>> {
+        CFuture::par_sequence(items.into_iter().map(func).collect())
+    }
+
+    /// Poll all the futures concurrently, keeping their order in the result.
+    pub fn par_sequence(futures: Vec<CFuture<A>>) -> CFuture<Vec<A>> {
+        let token = linked_token(futures.iter().map(CFuture::token));
+        CFuture::from_outcome(
+            async move {
+                let all = join_all(futures.into_iter().map(branch)).await;
+                all.into_iter().collect()
+            },
+            Some(token),
+        )
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<A: Clone + Sync + Send + 'static> CFuture<A> {
+    /// Start running `fut` on the tokio runtime right away, rather than when the result
+    /// is first polled, so that spawned futures run in parallel.  A panic in the task
+    /// resolves to `TaskError::Panicked` instead of propagating to whoever awaits it.
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn spawn(fut: impl Future<Output = A> + Send + 'static) -> CFuture<Result<A, TaskError>> {
+        let handle = tokio::spawn(fut);
+        CFuture::new(handle.map(|res| res.map_err(TaskError::from)))
+    }
+
+    /// Run blocking or CPU-heavy `func` on tokio's blocking thread pool, starting right
+    /// away.  Panics are reported as with `spawn`.
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn blocking(func: impl FnOnce() -> A + Send + 'static) -> CFuture<Result<A, TaskError>> {
+        let handle = tokio::task::spawn_blocking(func);
+        CFuture::new(handle.map(|res| res.map_err(TaskError::from)))
+    }
+
+    /// Resolve to `Err(Elapsed)` if this future doesn't finish within `duration`.
+    pub fn timeout(&self, duration: Duration) -> CFuture<Result<A, Elapsed>> {
+        self.timeout_on(&SystemClock::new(), duration)
+    }
+
+    /// Resolve to `alt` if this future doesn't finish within `duration`.
+    pub fn with_fallback_after(&self, duration: Duration, alt: CFuture<A>) -> CFuture<A> {
+        self.with_fallback_after_on(&SystemClock::new(), duration, alt)
+    }
+}
+
+impl<A, E> CFuture<Result<A, E>>
+where
+    A: Clone + Sync + Send + 'static,
+    E: Clone + Sync + Send + 'static,
+{
+    /// Run the future made by `make`, and keep running a new one after it fails for as
+    /// long as `schedule` allows, sleeping on `clock` for the delays it asks for in
+    /// between.  Resolves to the first success, or to the last error once the schedule
+    /// is done.
+    pub fn retry_on(
+        clock: impl Clock + 'static,
+        schedule: Schedule,
+        make: impl Fn() -> CFuture<Result<A, E>> + Send + 'static,
+    ) -> CFuture<Result<A, E>> {
+        CFuture::from_outcome(
+            Self::recur(clock, schedule, make, |res: &Result<A, E>| res.is_err()),
+            None,
+        )
+    }
+
+    /// Run the future made by `make`, and keep running a new one after it succeeds for
+    /// as long as `schedule` allows, sleeping on `clock` for the delays it asks for in
+    /// between.  Resolves to the first error, or to the last success once the schedule
+    /// is done.
+    pub fn repeat_on(
+        clock: impl Clock + 'static,
+        schedule: Schedule,
+        make: impl Fn() -> CFuture<Result<A, E>> + Send + 'static,
+    ) -> CFuture<Result<A, E>> {
+        CFuture::from_outcome(
+            Self::recur(clock, schedule, make, |res: &Result<A, E>| res.is_ok()),
+            None,
+        )
+    }
+
+    async fn recur(
+        clock: impl Clock,
+        schedule: Schedule,
+        make: impl Fn() -> CFuture<Result<A, E>> + Send + 'static,
+        again: impl Fn(&Result<A, E>) -> bool + Send + 'static,
+    ) -> Outcome<Result<A, E>> {
+        let start = clock.monotonic().into_outcome().await?;
+        let mut attempt = 0;
+        loop {
+            let res = make().into_outcome().await?;
+            let step = Step {
+                attempt,
+                elapsed: clock.monotonic().into_outcome().await? - start,
+            };
+            match schedule.next_delay(step) {
+                Some(delay) if again(&res) => clock.sleep(delay).into_outcome().await?,
+                _ => return Ok(res),
+            }
+            attempt += 1;
         }
     }
 }
 
+#[cfg(feature = "tokio")]
+impl<A, E> CFuture<Result<A, E>>
+where
+    A: Clone + Sync + Send + 'static,
+    E: Clone + Sync + Send + 'static,
+{
+    /// Like `retry_on`, sleeping on the system clock.
+    pub fn retry(
+        schedule: Schedule,
+        make: impl Fn() -> CFuture<Result<A, E>> + Send + 'static,
+    ) -> CFuture<Result<A, E>> {
+        CFuture::retry_on(SystemClock::new(), schedule, make)
+    }
+
+    /// Like `repeat_on`, sleeping on the system clock.
+    pub fn repeat(
+        schedule: Schedule,
+        make: impl Fn() -> CFuture<Result<A, E>> + Send + 'static,
+    ) -> CFuture<Result<A, E>> {
+        CFuture::repeat_on(SystemClock::new(), schedule, make)
+    }
+}
+
+fn force<A>(deferred: &mut Arc<dyn Force<A>>) -> CFuture<A> {
+    match Arc::get_mut(deferred) {
+        Some(deferred) => deferred.force_unshared(),
+        None => deferred.force(),
+    }
+}
+
 impl<A> Future for CFuture<A>
 where
     A: Clone + Send + Sync,
@@ -28,10 +601,62 @@ where
         mut self: std::pin::Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Self::Output> {
-        self.inner.poll_unpin(cx)
+        loop {
+            match &mut self.inner {
+                Repr::Ready(a) => return std::task::Poll::Ready(a.clone()),
+                Repr::Deferred(deferred) => {
+                    let forced = force(deferred);
+                    *self = forced;
+                }
+                Repr::Pending(fut) => {
+                    return fut.poll_unpin(cx).map(|res| {
+                        res.expect(
+                            "awaited a cancelled CFuture; use `outcome` to observe cancellation",
+                        )
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Take the values of two futures if both are already known.
+fn both_ready<T, U>(a: CFuture<T>, b: CFuture<U>) -> Result<(T, U), (CFuture<T>, CFuture<U>)> {
+    match (a.inner, b.inner) {
+        (Repr::Ready(a), Repr::Ready(b)) => Ok((a, b)),
+        (a_inner, b_inner) => Err((
+            CFuture {
+                inner: a_inner,
+                token: a.token,
+            },
+            CFuture {
+                inner: b_inner,
+                token: b.token,
+            },
+        )),
+    }
+}
+
+/// A new token for a future polling several futures concurrently, which cancels the
+/// tokens of those futures when it is cancelled.
+fn linked_token<'a>(children: impl IntoIterator<Item = Option<&'a CancelToken>>) -> CancelToken {
+    let token = CancelToken::new();
+    let children: Vec<_> = children.into_iter().flatten().cloned().collect();
+    if !children.is_empty() {
+        token.on_cancel(move || children.iter().for_each(CancelToken::cancel));
     }
+    token
 }
 
+/// The token a future derived from `a` and `b` inherits.
+fn either_token<T, U>(a: &CFuture<T>, b: &CFuture<U>) -> Option<CancelToken> {
+    a.token.clone().or_else(|| b.token.clone())
+}
+
+// The value is never pinned in place: it is either cloned out, or lives in a boxed
+// future.
+impl<A> Unpin for CFuture<A> {}
+
 unsafe impl<A> Send for CFuture<A> where A: Clone + Send + Sync {}
 unsafe impl<A> Sync for CFuture<A> where A: Clone + Send + Sync {}
 
@@ -52,19 +677,50 @@ where
     A: Semigroup + Clone + Send + Sync + 'static,
 {
     fn combine(a: Self, b: Self) -> Self {
-        let f = a
-            .inner
-            .then(move |a_res| b.inner.map(move |b_res| A::combine(a_res, b_res)));
-        CFuture::new(f)
+        combine_with(a, b, A::combine)
     }
     fn combine_m(a: Self, b: Self) -> Self {
-        let f = a
-            .inner
-            .then(move |a_res| b.inner.map(move |b_res| A::combine_m(a_res, b_res)));
-        CFuture::new(f)
+        combine_with(a, b, A::combine_m)
     }
 }
 
+/// Poll `fut` as one of the branches of a concurrent combinator.  Within a
+/// `TestExecutor`, a pending branch runs as a task of its own, so that the executor
+/// explores the orders in which the branches can progress.
+fn branch<A>(fut: CFuture<A>) -> BoxFuture<'static, Outcome<A>>
+where
+    A: Clone + Send + Sync + 'static,
+{
+    let fut = fut.forced();
+    #[cfg(feature = "testing")]
+    if fut.is_pending()
+        && let Some(task) = crate::testing::executor::spawn_branch(fut.clone())
+    {
+        return task;
+    }
+    fut.into_outcome().boxed()
+}
+
+/// Await `a` then `b`, combining their results with `func`.
+fn combine_with<A>(a: CFuture<A>, b: CFuture<A>, func: fn(A, A) -> A) -> CFuture<A>
+where
+    A: Clone + Send + Sync + 'static,
+{
+    if !a.is_pending() && !b.is_pending() {
+        return CFuture::defer(move || match both_ready(a.forced(), b.forced()) {
+            Ok((a, b)) => CFuture::lazy(func(a, b)),
+            Err((a, b)) => combine_with(a, b, func),
+        });
+    }
+    let token = either_token(&a, &b);
+    let f = async move {
+        let a_res = a.into_outcome().await?;
+        let b_res = b.into_outcome().await?;
+        Ok(func(a_res, b_res))
+    };
+    CFuture::from_outcome(f, token)
+}
+
 impl<T, U> Functor<U> for CFuture<T>
 where
     T: Send + Sync + Clone + 'static,
@@ -73,7 +729,13 @@ where
     type FuncT = T;
     type FunctorOut = CFuture<U>;
     fn fmap(m: Self, func: impl FnOnce(T) -> U + Send + 'static) -> Self::FunctorOut {
-        CFuture::new(m.map(func))
+        if !m.is_pending() {
+            return CFuture::defer(move || match m.forced().into_ready() {
+                Ok(t) => CFuture::lazy(func(t)),
+                Err(m) => map_pending(m, func),
+            });
+        }
+        map_pending(m, func)
     }
 }
 
@@ -98,8 +760,19 @@ where
     type AppFuncOut = CFuture<U>;
     type AppFuncFn = CFuture<F>;
     fn seq(m: Self, func: Self::AppFuncFn) -> Self::AppFuncOut {
-        let in_f = func;
-        CFuture::new(async { in_f.await(m.await) })
+        if !m.is_pending() && !func.is_pending() {
+            return CFuture::defer(move || match both_ready(m.forced(), func.forced()) {
+                Ok((t, f)) => CFuture::lazy(f(t)),
+                Err((m, func)) => Self::seq(m, func),
+            });
+        }
+        let token = either_token(&func, &m);
+        let f = async move {
+            let f = func.into_outcome().await?;
+            let t = m.into_outcome().await?;
+            Ok(f(t))
+        };
+        CFuture::from_outcome(f, token)
     }
 }
 
@@ -111,13 +784,52 @@ where
     type MonadT = T;
     type MonadOut = CFuture<U>;
     fn bind(m: Self, func: impl FnOnce(T) -> Self::MonadOut + Send + 'static) -> Self::MonadOut {
-        CFuture::new(m.then(func))
+        if !m.is_pending() {
+            return CFuture::defer(move || match m.forced().into_ready() {
+                Ok(t) => func(t),
+                Err(m) => bind_pending(m, func),
+            });
+        }
+        bind_pending(m, func)
     }
 }
 
+fn map_pending<T, U>(m: CFuture<T>, func: impl FnOnce(T) -> U + Send + 'static) -> CFuture<U>
+where
+    T: Send + Sync + Clone + 'static,
+    U: Send + Sync + Clone + 'static,
+{
+    let token = m.token.clone();
+    CFuture::from_outcome(m.into_outcome().map(|res| res.map(func)), token)
+}
+
+fn bind_pending<T, U>(
+    m: CFuture<T>,
+    func: impl FnOnce(T) -> CFuture<U> + Send + 'static,
+) -> CFuture<U>
+where
+    T: Send + Sync + Clone + 'static,
+    U: Send + Sync + Clone + 'static,
+{
+    let token = m.token.clone();
+    let f = async move {
+        let t = m.into_outcome().await?;
+        func(t).into_outcome().await
+    };
+    CFuture::from_outcome(f, token)
+}
+
+impl<A> Traceable for CFuture<A> {}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::types::cancel::CancelToken;
+    #[cfg(feature = "tokio")]
+    use std::sync::atomic::AtomicU32;
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+    use tokio::time::Instant;
 
     #[tokio::test]
     async fn test_empty_future() {
@@ -171,10 +883,8 @@ mod test {
         assert_eq!(seq(CFuture::lazy(3), func).await, 7);
     }
 
-    fn empty_if_even<'a, M: Monad<u32, MonadT = u32> + Monoid + Applicative<u32>>(
-        input: String,
-    ) -> M {
-        if input.len() % 2 == 0 {
+    fn empty_if_even<M: Monad<u32, MonadT = u32> + Monoid + Applicative<u32>>(input: String) -> M {
+        if input.len().is_multiple_of(2) {
             M::empty()
         } else {
             M::pure(input.len() as u32)
@@ -210,4 +920,513 @@ mod test {
         let new_func = lift_m2::<CFuture<_>, _, _>(add2);
         assert_eq!(new_func(CFuture::lazy(3), CFuture::lazy(4)).await, 7);
     }
+
+    #[test]
+    fn test_wait_future() {
+        let fut = bind(CFuture::new(async { 3u32 }), |a| {
+            fmap(CFuture::new(async { 4 }), move |b| a + b)
+        });
+        assert_eq!(fut.wait(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_ready_fast_path() {
+        let ready = bind(fmap(CFuture::lazy(3), |a| a + 4), |a| {
+            pure::<CFuture<_>>(a * 2)
+        });
+        // Kept as a deferred computation, not a boxed shared future
+        assert!(matches!(ready.inner, Repr::Deferred(_)));
+        let sum = seq(ready.clone(), CFuture::lazy(|a: u32| a + 1));
+        assert!(matches!(sum.inner, Repr::Deferred(_)));
+        let combined = combine(ready.clone(), CFuture::lazy(1));
+        assert!(matches!(combined.inner, Repr::Deferred(_)));
+        assert_eq!(ready.clone().await, 14);
+        assert_eq!(ready.await, 14);
+        assert_eq!(sum.await, 15);
+        assert_eq!(combined.await, 15);
+
+        let pending = fmap(CFuture::new(async { 3 }), |a: u32| a + 4);
+        assert!(!pending.is_ready());
+        assert!(combine(pending.clone(), CFuture::lazy(1)).is_pending());
+        assert_eq!(combine(CFuture::lazy(1), pending).await, 8);
+        let bound = bind(CFuture::lazy(1), |a| {
+            fmap(CFuture::new(async { 2 }), move |b| a + b)
+        });
+        assert_eq!(bound.await, 3);
+    }
+
+    #[test]
+    fn test_clone_after_poll() {
+        let mut fut = CFuture::lazy(3);
+        assert_eq!(block_on(&mut fut), 3);
+        assert_eq!(block_on(&mut fut), 3);
+        assert_eq!(block_on(fut.clone()), 3);
+
+        let mut mapped = fmap(CFuture::lazy(3), |a: u32| a + 1);
+        assert_eq!(block_on(&mut mapped), 4);
+        assert_eq!(block_on(mapped.clone()), 4);
+    }
+
+    #[test]
+    fn test_ready_functions_run_when_polled() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counted = |runs: &Arc<AtomicUsize>| {
+            let runs = runs.clone();
+            move |a: u32| {
+                runs.fetch_add(1, Ordering::SeqCst);
+                a + 1
+            }
+        };
+        let mapped = fmap(CFuture::lazy(1), counted(&runs));
+        let f = counted(&runs);
+        let bound = bind(mapped.clone(), move |a| CFuture::lazy(f(a)));
+        let seqd = seq(CFuture::lazy(1), CFuture::lazy(counted(&runs)));
+        assert_eq!(runs.load(Ordering::SeqCst), 0);
+
+        assert_eq!(bound.wait(), 3);
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        // Clones share the computation
+        assert_eq!(mapped.clone().wait(), 2);
+        assert_eq!(mapped.wait(), 2);
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert_eq!(seqd.wait(), 2);
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+    }
+
+    fn delayed<A: Clone + Send + Sync + 'static>(millis: u64, val: A) -> CFuture<A> {
+        CFuture::new(async move {
+            tokio::time::sleep(Duration::from_millis(millis)).await;
+            val
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_seq_is_sequential() {
+        let start = Instant::now();
+        let func = delayed(100, |x: u32| x + 4);
+        assert_eq!(seq(delayed(100, 3), func).await, 7);
+        assert_eq!(start.elapsed(), Duration::from_millis(200));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_par_seq_future() {
+        let start = Instant::now();
+        let func = delayed(100, |x: u32| x + 4);
+        assert_eq!(CFuture::par_seq(delayed(100, 3), func).await, 7);
+        assert_eq!(start.elapsed(), Duration::from_millis(100));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_par_map_future() {
+        let start = Instant::now();
+        let res = CFuture::par_map2(delayed(50, 1u32), delayed(100, 2u32), add2);
+        assert_eq!(res.await, 3);
+        assert_eq!(start.elapsed(), Duration::from_millis(100));
+
+        let start = Instant::now();
+        let res = CFuture::par_map8(
+            delayed(10, 1u32),
+            delayed(20, 2u32),
+            delayed(30, 3u32),
+            delayed(40, 4u32),
+            delayed(50, 5u32),
+            delayed(60, 6u32),
+            delayed(70, 7u32),
+            delayed(80, "sum".to_string()),
+            |a, b, c, d, e, f, g, h| format!("{} {}", h, a + b + c + d + e + f + g),
+        );
+        assert_eq!(res.await, "sum 28");
+        assert_eq!(start.elapsed(), Duration::from_millis(80));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_par_combine_future() {
+        let start = Instant::now();
+        assert_eq!(
+            CFuture::par_combine(delayed(100, 3), delayed(100, 4)).await,
+            7
+        );
+        assert_eq!(
+            CFuture::par_combine_m(delayed(100, 3), delayed(100, 4)).await,
+            12
+        );
+        assert_eq!(start.elapsed(), Duration::from_millis(200));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_race_future() {
+        let start = Instant::now();
+        let res = CFuture::race(delayed(100, 1u32), delayed(50, "fast".to_string()));
+        assert_eq!(res.await, Either::Right("fast".to_string()));
+        assert_eq!(start.elapsed(), Duration::from_millis(50));
+
+        let res = bind(
+            CFuture::race(delayed(10, 1u32), delayed(50, 2u32)),
+            |winner| match winner {
+                Either::Left(a) | Either::Right(a) => delayed(10, a * 10),
+            },
+        );
+        assert_eq!(res.await, 10);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_select_all_future() {
+        let start = Instant::now();
+        let all = vec![delayed(30, 'a'), delayed(10, 'b'), delayed(20, 'c')];
+        assert_eq!(CFuture::select_all(all).await, Some((1, 'b')));
+        assert_eq!(start.elapsed(), Duration::from_millis(10));
+        assert_eq!(CFuture::<u32>::select_all(vec![]).await, None);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test(start_paused = true)]
+    async fn test_timeout_future() {
+        let start = Instant::now();
+        let slow = delayed(100, 3u32);
+        assert_eq!(
+            slow.timeout(Duration::from_millis(50)).await,
+            Err(Elapsed(Duration::from_millis(50)))
+        );
+        assert_eq!(start.elapsed(), Duration::from_millis(50));
+        // The original future is shared and can still be awaited
+        assert_eq!(slow.timeout(Duration::from_millis(100)).await, Ok(3));
+        assert_eq!(slow.await, 3);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test(start_paused = true)]
+    async fn test_fallback_future() {
+        let start = Instant::now();
+        let res = delayed(100, 3u32).with_fallback_after(Duration::from_millis(20), delayed(10, 0));
+        assert_eq!(fmap(res, |a| a + 1).await, 1);
+        assert_eq!(start.elapsed(), Duration::from_millis(30));
+
+        let res = delayed(10, 3u32).with_fallback_after(Duration::from_millis(20), delayed(10, 0));
+        assert_eq!(res.await, 3);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test(start_paused = true)]
+    async fn test_spawn_runs_in_parallel() {
+        let start = Instant::now();
+        let a = CFuture::spawn(delayed(100, 3u32));
+        let b = CFuture::spawn(delayed(100, 4u32));
+        // Sequential combine, but both tasks are already running
+        let unwrap = |res: Result<u32, TaskError>| res.unwrap();
+        assert_eq!(combine(fmap(a, unwrap), fmap(b, unwrap)).await, 7);
+        assert_eq!(start.elapsed(), Duration::from_millis(100));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_spawn_is_eager() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let res = CFuture::spawn(async move { tx.send(1).unwrap() });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(res.await, Ok(()));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_spawn_panic() {
+        let res: CFuture<Result<u32, _>> = CFuture::spawn(async { panic!("boom") });
+        let other = res.clone();
+        assert_eq!(res.await, Err(TaskError::Panicked("boom".to_string())));
+        assert_eq!(other.await, Err(TaskError::Panicked("boom".to_string())));
+
+        let res = bind(CFuture::spawn(async { 3u32 }), |a| {
+            let a = a.unwrap();
+            CFuture::spawn(async move { a / (a - 3) })
+        });
+        assert!(matches!(res.await, Err(TaskError::Panicked(_))));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_blocking_future() {
+        let res = CFuture::blocking(|| {
+            std::thread::sleep(Duration::from_millis(10));
+            (1..=10u64).product::<u64>()
+        });
+        assert_eq!(res.await, Ok(3628800));
+
+        let res = CFuture::<u32>::blocking(|| panic!("blocking {}", "boom"));
+        assert_eq!(
+            res.await,
+            Err(TaskError::Panicked("blocking boom".to_string()))
+        );
+    }
+
+    fn cancel_after(token: &CancelToken, millis: u64) {
+        let token = token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(millis)).await;
+            token.cancel();
+        });
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancel_propagates() {
+        let token = CancelToken::new();
+        let ran = Arc::new(AtomicBool::new(false));
+        let ran_in = ran.clone();
+        let fut = delayed(100, 3u32).cancellable(&token);
+        let mapped = fmap(fut.clone(), |a| a + 1);
+        let bound = bind(fut.clone(), move |a| {
+            ran_in.store(true, Ordering::SeqCst);
+            delayed(10, a * 2)
+        });
+        let seqd = seq(CFuture::lazy(1u32), fmap(fut.clone(), |a| move |b| a + b));
+        let combined = combine(CFuture::lazy(1u32), fut.clone());
+        let par = CFuture::par_map2(fut.clone(), delayed(10, 1u32), add2);
+        cancel_after(&token, 50);
+
+        let start = Instant::now();
+        assert_eq!(mapped.outcome().await, Err(Cancelled));
+        assert_eq!(start.elapsed(), Duration::from_millis(50));
+        assert_eq!(bound.outcome().await, Err(Cancelled));
+        assert_eq!(seqd.outcome().await, Err(Cancelled));
+        assert_eq!(combined.outcome().await, Err(Cancelled));
+        assert_eq!(par.outcome().await, Err(Cancelled));
+        assert!(!ran.load(Ordering::SeqCst));
+        assert!(fut.is_cancelled() && mapped.is_cancelled() && bound.is_cancelled());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancel_par_cancels_branches() {
+        let token = CancelToken::new();
+        let branch = delayed(100, 3u32).cancellable(&token);
+        let par = CFuture::par_map2(branch.clone(), delayed(100, 1u32), add2);
+        par.cancel();
+        assert!(par.is_cancelled());
+        assert!(token.is_cancelled() && branch.is_cancelled());
+        assert_eq!(par.outcome().await, Err(Cancelled));
+        assert_eq!(branch.outcome().await, Err(Cancelled));
+
+        // Combined futures can be cancelled even if their branches have no token
+        let start = Instant::now();
+        let all = CFuture::par_sequence(vec![delayed(100, 1u32), delayed(200, 2u32)]);
+        let cancel = all.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            cancel.cancel();
+        });
+        assert_eq!(all.outcome().await, Err(Cancelled));
+        assert_eq!(start.elapsed(), Duration::from_millis(50));
+
+        let raced = CFuture::race(delayed(100, 1u32), branch.clone());
+        raced.cancel();
+        assert_eq!(raced.outcome().await, Err(Cancelled));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancel_during_continuation() {
+        let token = CancelToken::new();
+        let fut = bind(delayed(10, 3u32).cancellable(&token), |a| delayed(100, a));
+        cancel_after(&token, 50);
+        let start = Instant::now();
+        assert_eq!(fut.outcome().await, Err(Cancelled));
+        assert_eq!(start.elapsed(), Duration::from_millis(50));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancel_clones() {
+        let token = CancelToken::new();
+        let original = delayed(100, 3u32);
+        let fut = original.cancellable(&token);
+        let clone = fut.clone();
+        // Dropping a handle doesn't cancel anything
+        drop(fut.clone());
+        assert!(!clone.is_cancelled());
+
+        // Cancelling through one handle cancels every clone
+        fut.cancel();
+        assert!(clone.is_cancelled() && fut.is_cancelled());
+        assert_eq!(clone.outcome().await, Err(Cancelled));
+        assert_eq!(fut.outcome().await, Err(Cancelled));
+        // ... but not the future it was made cancellable from
+        assert!(!original.is_cancelled());
+        assert_eq!(original.await, 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancel_after_completion() {
+        let token = CancelToken::new();
+        let fut = delayed(10, 3u32).cancellable(&token);
+        assert_eq!(fut.clone().await, 3);
+        token.cancel();
+        assert!(!fut.is_cancelled());
+        assert_eq!(fut.outcome().await, Ok(3));
+
+        let ready = CFuture::lazy(3u32).cancellable(&token);
+        assert!(!ready.is_cancelled());
+        assert_eq!(ready.await, 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_on_cancel() {
+        let token = CancelToken::new();
+        let count = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let c = count.clone();
+        // Never polled: the finalizer runs when the token is cancelled
+        let _fut = delayed(100, 3u32).cancellable(&token).on_cancel(move || {
+            c.fetch_add(1, Ordering::SeqCst);
+        });
+        let c = count.clone();
+        let done = delayed(10, 3u32).cancellable(&token).on_cancel(move || {
+            c.fetch_add(10, Ordering::SeqCst);
+        });
+        assert_eq!(done.clone().await, 3);
+        token.cancel();
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        // Derived futures inherit the token; registering after cancellation runs at once
+        let c = count.clone();
+        let derived = fmap(delayed(100, 3u32).cancellable(&CancelToken::new()), |a| a);
+        derived.cancel();
+        let derived = derived.on_cancel(move || {
+            c.fetch_add(100, Ordering::SeqCst);
+        });
+        assert_eq!(derived.outcome().await, Err(Cancelled));
+        assert_eq!(count.load(Ordering::SeqCst), 101);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_completed_futures_leave_no_listeners() {
+        let token = CancelToken::new();
+        for i in 0..10u32 {
+            let fut = delayed(10, i).cancellable(&token).on_cancel(|| ());
+            assert_eq!(fut.await, i);
+        }
+        assert_eq!(token.listener_count(), (0, 0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    #[should_panic(expected = "awaited a cancelled CFuture")]
+    async fn test_await_cancelled() {
+        let token = CancelToken::new();
+        token.cancel();
+        delayed(10, 3u32).cancellable(&token).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_par_traverse_future() {
+        let start = Instant::now();
+        let res = CFuture::par_traverse(vec![300u64, 100, 200], |ms| delayed(ms, ms / 100));
+        assert_eq!(res.await, vec![3, 1, 2]);
+        assert_eq!(start.elapsed(), Duration::from_millis(300));
+        assert!(CFuture::<Vec<u32>>::par_sequence(vec![]).await.is_empty());
+    }
+
+    /// Makes a future which fails until its `n`th run, counting runs in `runs`
+    #[cfg(feature = "tokio")]
+    fn flaky(runs: &Arc<AtomicU32>, n: u32) -> impl Fn() -> CFuture<Result<u32, String>> + use<> {
+        let runs = runs.clone();
+        move || {
+            let run = runs.fetch_add(1, Ordering::SeqCst) + 1;
+            let res = if run >= n {
+                Ok(run)
+            } else {
+                Err(format!("run {}", run))
+            };
+            delayed(10, res)
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_future() {
+        use crate::types::schedule::Schedule;
+
+        let start = Instant::now();
+        let runs = Arc::new(AtomicU32::new(0));
+        let schedule = Schedule::exponential(Duration::from_millis(100));
+        assert_eq!(CFuture::retry(schedule, flaky(&runs, 3)).await, Ok(3));
+        // 3 runs of 10ms, with 100ms and 200ms of backoff in between
+        assert_eq!(start.elapsed(), Duration::from_millis(330));
+
+        let start = Instant::now();
+        let runs = Arc::new(AtomicU32::new(0));
+        let schedule = combine(
+            Schedule::spaced(Duration::from_millis(100)),
+            Schedule::recurs(2),
+        );
+        let res = CFuture::retry(schedule, flaky(&runs, 5));
+        assert_eq!(res.await, Err("run 3".to_string()));
+        assert_eq!(start.elapsed(), Duration::from_millis(230));
+
+        let start = Instant::now();
+        let runs = Arc::new(AtomicU32::new(0));
+        let schedule = Schedule::up_to(Duration::from_millis(50));
+        let res = CFuture::retry(schedule, flaky(&runs, 10));
+        assert_eq!(res.await, Err("run 5".to_string()));
+        assert_eq!(start.elapsed(), Duration::from_millis(50));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test(start_paused = true)]
+    async fn test_repeat_future() {
+        use crate::types::schedule::Schedule;
+
+        let start = Instant::now();
+        let runs = Arc::new(AtomicU32::new(0));
+        let schedule = combine_m(
+            Schedule::recurs(2),
+            Schedule::fibonacci(Duration::from_millis(100)),
+        );
+        let schedule = combine(schedule, Schedule::recurs(3));
+        assert_eq!(CFuture::repeat(schedule, flaky(&runs, 1)).await, Ok(4));
+        // No delay while `recurs(2)` continues, then the 3rd Fibonacci delay
+        assert_eq!(start.elapsed(), Duration::from_millis(240));
+
+        let start = Instant::now();
+        let runs = Arc::new(AtomicU32::new(0));
+        let res = CFuture::repeat(Schedule::spaced(Duration::from_millis(100)), move || {
+            let run = runs.fetch_add(1, Ordering::SeqCst) + 1;
+            delayed(10, if run < 3 { Ok(run) } else { Err(run) })
+        });
+        assert_eq!(res.await, Err(3));
+        assert_eq!(start.elapsed(), Duration::from_millis(230));
+    }
+
+    #[tokio::test]
+    async fn test_test_clock_future() {
+        use crate::types::{clock::TestClock, schedule::Schedule};
+
+        let clock = TestClock::new();
+        let slow = clock.sleep(Duration::from_secs(10));
+        let timeout = tokio::spawn(slow.timeout_on(&clock, Duration::from_secs(5)));
+        let fast = clock.sleep(Duration::ZERO);
+        assert_eq!(
+            fast.timeout_on(&clock, Duration::from_secs(5)).await,
+            Ok(())
+        );
+        while clock.sleepers() < 2 {
+            tokio::task::yield_now().await;
+        }
+        clock.advance(Duration::from_secs(5));
+        // The losing sleep is dropped once nothing else holds on to it
+        drop(slow);
+        let elapsed = Elapsed(Duration::from_secs(5));
+        assert_eq!(timeout.await.unwrap(), Err(elapsed));
+        assert_eq!(clock.sleepers(), 0);
+
+        let runs = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let r = runs.clone();
+        let schedule = Schedule::exponential(Duration::from_secs(1));
+        let retry = tokio::spawn(CFuture::retry_on(clock.clone(), schedule, move || {
+            let run = r.fetch_add(1, Ordering::SeqCst) + 1;
+            CFuture::lazy(if run < 3 { Err(run) } else { Ok(run) })
+        }));
+        // Backs off 1s then 2s, only as the clock moves
+        for backoff in [1, 2] {
+            while clock.sleepers() == 0 {
+                tokio::task::yield_now().await;
+            }
+            clock.advance(Duration::from_secs(backoff));
+        }
+        assert_eq!(retry.await.unwrap(), Ok(3));
+        assert_eq!(clock.monotonic().await, Duration::from_secs(8));
+    }
 }
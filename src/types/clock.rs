@@ -0,0 +1,299 @@
+use crate::types::cfuture::CFuture;
+use std::{
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::{Duration, SystemTime},
+};
+
+/// Access to time, as an effect.
+///
+/// Everything in the crate which waits or measures time has a variant taking a `Clock`
+/// (e.g. `CFuture::timeout_on`, or `with_clock` for the resilience wrappers), so that
+/// time-dependent code can run against a `TestClock` in tests.
+pub trait Clock: Send + Sync {
+    /// The current wall-clock time.
+    fn now(&self) -> CFuture<SystemTime>;
+    /// Time elapsed since an arbitrary point fixed for the clock, which never goes back.
+    fn monotonic(&self) -> CFuture<Duration>;
+    /// A future resolving once `duration` passed, counting from when `sleep` is called.
+    fn sleep(&self, duration: Duration) -> CFuture<()>;
+}
+
+/// The real clock, with sleeps driven by the tokio runtime.
+///
+/// Monotonic time is measured with tokio's clock, so it follows tokio's paused time in
+/// tests.
+#[cfg(feature = "tokio")]
+#[derive(Clone, Copy, Debug)]
+pub struct SystemClock {
+    start: tokio::time::Instant,
+}
+
+#[cfg(feature = "tokio")]
+impl Default for SystemClock {
+    fn default() -> Self {
+        SystemClock {
+            start: tokio::time::Instant::now(),
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock::default()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Clock for SystemClock {
+    fn now(&self) -> CFuture<SystemTime> {
+        CFuture::new(async { SystemTime::now() })
+    }
+
+    fn monotonic(&self) -> CFuture<Duration> {
+        let start = self.start;
+        CFuture::new(async move { start.elapsed() })
+    }
+
+    fn sleep(&self, duration: Duration) -> CFuture<()> {
+        CFuture::new(tokio::time::sleep(duration))
+    }
+}
+
+struct TestState {
+    start: SystemTime,
+    elapsed: Duration,
+    /// Deadlines of the sleeps still waiting, with their ids and the tasks to wake
+    sleepers: Vec<(Duration, u64, Waker)>,
+    next_sleep: u64,
+}
+
+/// A clock whose time only moves when `advance` is called.
+///
+/// Sleeps complete as soon as the clock is advanced past their deadline, no matter how
+/// much real time passed, so that tests of time-dependent code are fast and
+/// deterministic.  Clones of a test clock share its time.
+///
+/// ```rust
+/// use rust_effects::prelude::*;
+/// use rust_effects::types::clock::{Clock, TestClock};
+/// use std::time::Duration;
+///
+/// let clock = TestClock::new();
+/// let sleep = clock.sleep(Duration::from_secs(60));
+/// assert_eq!(clock.sleepers(), 0);
+/// clock.advance(Duration::from_secs(60));
+/// sleep.wait();
+/// assert_eq!(clock.monotonic().wait(), Duration::from_secs(60));
+/// ```
+#[derive(Clone)]
+pub struct TestClock {
+    state: Arc<Mutex<TestState>>,
+}
+
+impl Default for TestClock {
+    fn default() -> Self {
+        TestClock::starting_at(SystemTime::UNIX_EPOCH)
+    }
+}
+
+impl TestClock {
+    /// A test clock starting at the Unix epoch.
+    pub fn new() -> TestClock {
+        TestClock::default()
+    }
+
+    /// A test clock whose wall-clock time starts at `start`.
+    pub fn starting_at(start: SystemTime) -> TestClock {
+        TestClock {
+            state: Arc::new(Mutex::new(TestState {
+                start,
+                elapsed: Duration::ZERO,
+                sleepers: vec![],
+                next_sleep: 0,
+            })),
+        }
+    }
+
+    /// Move time forward by `duration`, waking up the sleeps whose deadline passed, in
+    /// the order of their deadlines.
+    pub fn advance(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.elapsed = state.elapsed.saturating_add(duration);
+        let elapsed = state.elapsed;
+        let (mut due, waiting) = std::mem::take(&mut state.sleepers)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(deadline, _, _)| *deadline <= elapsed);
+        state.sleepers = waiting;
+        drop(state);
+        due.sort_by_key(|(deadline, id, _)| (*deadline, *id));
+        due.into_iter().for_each(|(_, _, waker)| waker.wake());
+    }
+
+    /// Move time forward to the earliest deadline of the sleeps waiting, waking it up.
+    /// Returns `false`, leaving time unchanged, when no sleep is waiting.
+    pub fn advance_to_next(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        let next = state
+            .sleepers
+            .iter()
+            .map(|(deadline, _, _)| *deadline)
+            .min();
+        let elapsed = state.elapsed;
+        drop(state);
+        match next {
+            Some(deadline) => {
+                self.advance(deadline.saturating_sub(elapsed));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// How many sleeps are waiting for the clock to be advanced.  Only sleeps which
+    /// are being polled are counted, not the ones just built or already dropped.
+    pub fn sleepers(&self) -> usize {
+        self.state.lock().unwrap().sleepers.len()
+    }
+}
+
+impl Clock for TestClock {
+    fn now(&self) -> CFuture<SystemTime> {
+        let state = self.state.clone();
+        CFuture::new(async move {
+            let state = state.lock().unwrap();
+            state.start + state.elapsed
+        })
+    }
+
+    fn monotonic(&self) -> CFuture<Duration> {
+        let state = self.state.clone();
+        CFuture::new(async move { state.lock().unwrap().elapsed })
+    }
+
+    fn sleep(&self, duration: Duration) -> CFuture<()> {
+        let mut state = self.state.lock().unwrap();
+        let (deadline, id) = (state.elapsed.saturating_add(duration), state.next_sleep);
+        state.next_sleep += 1;
+        CFuture::new(TestSleep {
+            state: self.state.clone(),
+            deadline,
+            id,
+        })
+    }
+}
+
+/// Future returned by `TestClock::sleep`.
+struct TestSleep {
+    state: Arc<Mutex<TestState>>,
+    deadline: Duration,
+    id: u64,
+}
+
+impl Future for TestSleep {
+    type Output = ();
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.elapsed >= self.deadline {
+            return Poll::Ready(());
+        }
+        let waker = cx.waker().clone();
+        match state.sleepers.iter_mut().find(|(_, id, _)| *id == self.id) {
+            Some((_, _, registered)) => *registered = waker,
+            None => state.sleepers.push((self.deadline, self.id, waker)),
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for TestSleep {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.sleepers.retain(|(_, id, _)| *id != self.id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prelude::*;
+
+    #[test]
+    fn test_clock_time() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let clock = TestClock::starting_at(start);
+        let now = clock.now();
+        clock.advance(Duration::from_secs(5));
+        // Read when polled, not when built
+        assert_eq!(now.wait(), start + Duration::from_secs(5));
+        let c = clock.clone();
+        let later = bind(clock.monotonic(), move |a| {
+            fmap(c.monotonic(), move |b| b - a)
+        });
+        assert_eq!(later.wait(), Duration::ZERO);
+        assert_eq!(clock.monotonic().wait(), Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_sleeps_wake_on_advance() {
+        let clock = TestClock::new();
+        let order = Arc::new(Mutex::new(vec![]));
+        let tasks: Vec<_> = [30u64, 10, 20]
+            .into_iter()
+            .map(|secs| {
+                let order = order.clone();
+                let sleep = clock.sleep(Duration::from_secs(secs));
+                tokio::spawn(async move {
+                    sleep.await;
+                    order.lock().unwrap().push(secs);
+                })
+            })
+            .collect();
+        while clock.sleepers() < 3 {
+            tokio::task::yield_now().await;
+        }
+
+        clock.advance(Duration::from_secs(15));
+        while clock.sleepers() > 2 || order.lock().unwrap().is_empty() {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(*order.lock().unwrap(), vec![10]);
+
+        clock.advance(Duration::from_secs(15));
+        futures::future::join_all(tasks).await;
+        order.lock().unwrap().sort();
+        assert_eq!(*order.lock().unwrap(), vec![10, 20, 30]);
+        assert_eq!(clock.sleepers(), 0);
+    }
+
+    #[test]
+    fn test_sleep_counts_from_call() {
+        let clock = TestClock::new();
+        clock.advance(Duration::from_secs(10));
+        let sleep = clock.sleep(Duration::from_secs(10));
+        clock.advance(Duration::from_secs(10));
+        sleep.wait();
+        clock.sleep(Duration::ZERO).wait();
+    }
+
+    #[test]
+    fn test_saturates_at_max() {
+        let clock = TestClock::new();
+        clock.advance(Duration::from_secs(10));
+        let sleep = clock.sleep(Duration::MAX);
+        clock.advance(Duration::MAX);
+        sleep.wait();
+        assert_eq!(clock.monotonic().wait(), Duration::MAX);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test(start_paused = true)]
+    async fn test_system_clock() {
+        let clock = SystemClock::new();
+        clock.sleep(Duration::from_millis(100)).await;
+        assert_eq!(clock.monotonic().await, Duration::from_millis(100));
+        assert!(clock.now().await > SystemTime::UNIX_EPOCH);
+    }
+}
@@ -0,0 +1,445 @@
+use crate::types::cfuture::CFuture;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::{Semaphore as Permits, watch};
+
+/// A shared mutable value, updated atomically.
+///
+/// Like the other primitives in this module, each operation returns a `CFuture` which
+/// performs it when it is first polled (e.g. when the `bind` chain it is part of gets
+/// there), not when it is built.  Clones of a `Ref` refer to the same value.
+///
+/// ```rust
+/// use rust_effects::prelude::*;
+/// use rust_effects::types::concurrent::Ref;
+///
+/// let counter = Ref::new(1u32);
+/// let c = counter.clone();
+/// let res = bind(counter.update(|n| n + 1), move |_| c.modify(|n| (n * 10, n + 1)));
+/// assert_eq!(res.wait(), 20);
+/// assert_eq!(counter.get().wait(), 3);
+/// ```
+#[derive(Clone)]
+pub struct Ref<A> {
+    value: Arc<Mutex<A>>,
+}
+
+impl<A: Clone + Send + Sync + 'static> Ref<A> {
+    pub fn new(value: A) -> Ref<A> {
+        Ref {
+            value: Arc::new(Mutex::new(value)),
+        }
+    }
+
+    pub fn get(&self) -> CFuture<A> {
+        self.modify(|a| (a.clone(), a))
+    }
+
+    pub fn set(&self, value: A) -> CFuture<()> {
+        self.modify(|_| ((), value))
+    }
+
+    pub fn update(&self, func: impl FnOnce(A) -> A + Send + 'static) -> CFuture<()> {
+        self.modify(|a| ((), func(a)))
+    }
+
+    /// Replace the value with the second value returned by `func`, resolving to the
+    /// first.
+    pub fn modify<B: Clone + Send + Sync + 'static>(
+        &self,
+        func: impl FnOnce(A) -> (B, A) + Send + 'static,
+    ) -> CFuture<B> {
+        let value = self.value.clone();
+        CFuture::new(async move {
+            let mut value = value.lock().unwrap();
+            let (b, a) = func(value.clone());
+            *value = a;
+            b
+        })
+    }
+}
+
+/// A value which is completed once, and which can be waited for until then.
+///
+/// ```rust
+/// use rust_effects::prelude::*;
+/// use rust_effects::types::concurrent::Deferred;
+///
+/// let deferred = Deferred::new();
+/// let sum = lift_m2::<CFuture<_>, _, _>(|a: u32, b: u32| a + b)(deferred.get(), deferred.get());
+/// assert!(deferred.complete(3).wait());
+/// assert!(!deferred.complete(4).wait());
+/// assert_eq!(sum.wait(), 6);
+/// ```
+#[derive(Clone)]
+pub struct Deferred<A> {
+    value: Arc<watch::Sender<Option<A>>>,
+}
+
+impl<A: Clone + Send + Sync + 'static> Default for Deferred<A> {
+    fn default() -> Self {
+        Deferred {
+            value: Arc::new(watch::Sender::new(None)),
+        }
+    }
+}
+
+impl<A: Clone + Send + Sync + 'static> Deferred<A> {
+    pub fn new() -> Deferred<A> {
+        Deferred::default()
+    }
+
+    /// Complete with `value`, waking up everything waiting for it.  Resolves to `false`,
+    /// leaving the value unchanged, if it was already completed.
+    pub fn complete(&self, value: A) -> CFuture<bool> {
+        let sender = self.value.clone();
+        CFuture::new(async move {
+            sender.send_if_modified(|current| {
+                let empty = current.is_none();
+                if empty {
+                    *current = Some(value);
+                }
+                empty
+            })
+        })
+    }
+
+    /// Wait for the value to be completed.
+    pub fn get(&self) -> CFuture<A> {
+        let sender = self.value.clone();
+        CFuture::new(async move {
+            let mut receiver = sender.subscribe();
+            // The sender is kept alive by `sender`, so this can't fail
+            let value = receiver.wait_for(Option::is_some).await.unwrap();
+            value.clone().unwrap()
+        })
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.value.borrow().is_some()
+    }
+}
+
+/// Limits how many pieces of work run at the same time.
+///
+/// ```rust
+/// use rust_effects::prelude::*;
+/// use rust_effects::types::concurrent::Semaphore;
+///
+/// let semaphore = Semaphore::new(1);
+/// let res = semaphore.with_permit(|| CFuture::new(async { 3 }));
+/// assert_eq!(res.wait(), 3);
+/// ```
+#[derive(Clone)]
+pub struct Semaphore {
+    permits: Arc<Permits>,
+}
+
+impl Semaphore {
+    /// A semaphore with `permits` permits, up to `Semaphore::MAX_PERMITS` of tokio's
+    /// semaphore (`usize::MAX >> 3`); more are reduced to that.
+    pub fn new(permits: usize) -> Semaphore {
+        Semaphore {
+            permits: Arc::new(Permits::new(permits.min(Permits::MAX_PERMITS))),
+        }
+    }
+
+    /// Wait for a permit, then build the work with `make` and run it, holding the permit
+    /// until it finishes.  Waiters get permits in the order they asked for them.
+    pub fn with_permit<A: Clone + Send + Sync + 'static>(
+        &self,
+        make: impl FnOnce() -> CFuture<A> + Send + 'static,
+    ) -> CFuture<A> {
+        let permits = self.permits.clone();
+        CFuture::from_outcome(
+            async move {
+                // The semaphore is never closed
+                let _permit = permits.acquire().await.unwrap();
+                make().into_outcome().await
+            },
+            None,
+        )
+    }
+
+    /// How many permits are free right now.
+    pub fn available(&self) -> usize {
+        self.permits.available_permits()
+    }
+}
+
+struct Channel<A> {
+    items: Mutex<VecDeque<A>>,
+    /// One permit per item in the queue
+    filled: Permits,
+    /// One permit per free slot, for bounded queues
+    space: Option<Permits>,
+}
+
+/// A FIFO queue shared by any number of producers and consumers.
+///
+/// `take` waits while the queue is empty, and for bounded queues, `offer` waits while
+/// the queue is full.  Clones of a queue refer to the same queue.
+///
+/// ```rust
+/// use rust_effects::prelude::*;
+/// use rust_effects::types::concurrent::Queue;
+///
+/// let queue = Queue::bounded(2);
+/// let q = queue.clone();
+/// let res = bind(queue.offer(3u32), move |_| {
+///     let q = q.clone();
+///     bind(q.offer(4), move |_| lift_m2::<CFuture<_>, _, _>(|a: u32, b: u32| a * 10 + b)(q.take(), q.take()))
+/// });
+/// assert_eq!(res.wait(), 34);
+/// ```
+#[derive(Clone)]
+pub struct Queue<A> {
+    channel: Arc<Channel<A>>,
+}
+
+impl<A: Clone + Send + Sync + 'static> Queue<A> {
+    /// A queue holding at most `capacity` items, and at least one, as no item could
+    /// ever be offered to an empty one.  Capacities above tokio's
+    /// `Semaphore::MAX_PERMITS` (`usize::MAX >> 3`) are reduced to it.
+    pub fn bounded(capacity: usize) -> Queue<A> {
+        let capacity = capacity.clamp(1, Permits::MAX_PERMITS);
+        Queue::with_space(Some(Permits::new(capacity)))
+    }
+
+    pub fn unbounded() -> Queue<A> {
+        Queue::with_space(None)
+    }
+
+    fn with_space(space: Option<Permits>) -> Queue<A> {
+        Queue {
+            channel: Arc::new(Channel {
+                items: Mutex::new(VecDeque::new()),
+                filled: Permits::new(0),
+                space,
+            }),
+        }
+    }
+
+    /// Add `item` at the back of the queue, waiting for room first if it is full.
+    pub fn offer(&self, item: A) -> CFuture<()> {
+        let channel = self.channel.clone();
+        CFuture::new(async move {
+            if let Some(space) = &channel.space {
+                // The semaphores are never closed
+                space.acquire().await.unwrap().forget();
+            }
+            channel.items.lock().unwrap().push_back(item);
+            channel.filled.add_permits(1);
+        })
+    }
+
+    /// Remove the item at the front of the queue, waiting for one if it is empty.
+    pub fn take(&self) -> CFuture<A> {
+        let channel = self.channel.clone();
+        CFuture::new(async move {
+            channel.filled.acquire().await.unwrap().forget();
+            let item = channel.items.lock().unwrap().pop_front();
+            if let Some(space) = &channel.space {
+                space.add_permits(1);
+            }
+            // Every permit of `filled` stands for an item
+            item.unwrap()
+        })
+    }
+
+    /// How many items are in the queue right now.
+    pub fn len(&self) -> usize {
+        self.channel.items.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prelude::*;
+    use crate::types::fiber::Fiber;
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    #[tokio::test]
+    async fn test_ref() {
+        let r = Ref::new(1u32);
+        assert_eq!(r.get().await, 1);
+        r.set(5).await;
+        r.update(|a| a * 2).await;
+        assert_eq!(r.modify(|a| (a.to_string(), a + 1)).await, "10");
+        assert_eq!(r.get().await, 11);
+
+        // Nothing happens until polled
+        let set = r.set(0);
+        assert_eq!(r.get().await, 11);
+        set.await;
+        assert_eq!(r.get().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_ref_updates_are_atomic() {
+        let r = Ref::new(0u32);
+        let tasks = (0..50).map(|_| {
+            let r = r.clone();
+            tokio::spawn(async move {
+                for _ in 0..100 {
+                    r.update(|a| a + 1).await;
+                }
+            })
+        });
+        futures::future::join_all(tasks).await;
+        assert_eq!(r.get().await, 5000);
+    }
+
+    #[tokio::test]
+    async fn test_ref_bind() {
+        let r = Ref::new(vec![1u32]);
+        let r2 = r.clone();
+        let res = bind(
+            r.update(|mut v| {
+                v.push(2);
+                v
+            }),
+            move |_| fmap(r2.get(), |v| v.len()),
+        );
+        assert_eq!(res.await, 2);
+        let sum = lift_m2::<CFuture<_>, _, _>(|a: Vec<u32>, b: Vec<u32>| a.len() + b.len())(
+            r.get(),
+            r.get(),
+        );
+        assert_eq!(sum.await, 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_deferred() {
+        let start = Instant::now();
+        let deferred = Deferred::new();
+        let waiting = Fiber::start(deferred.get()).await;
+        let d = deferred.clone();
+        let completing = Fiber::start(bind(
+            CFuture::new(tokio::time::sleep(Duration::from_millis(100))),
+            move |_| d.complete(3u32),
+        ))
+        .await;
+        assert!(!deferred.is_completed());
+        assert_eq!(waiting.join().await, Ok(3));
+        assert_eq!(start.elapsed(), Duration::from_millis(100));
+        assert_eq!(completing.join().await, Ok(true));
+        assert!(deferred.is_completed());
+        assert!(!deferred.complete(4).await);
+        assert_eq!(deferred.get().await, 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_semaphore() {
+        let semaphore = Semaphore::new(2);
+        let running = Ref::new((0u32, 0u32));
+        let start = Instant::now();
+        let work = (0..5u32).map(|i| {
+            let running = running.clone();
+            semaphore.with_permit(move || {
+                let r = running.clone();
+                let started = running.update(|(now, max)| (now + 1, max.max(now + 1)));
+                bind(started, move |_| {
+                    let r = r.clone();
+                    bind(
+                        CFuture::new(tokio::time::sleep(Duration::from_millis(10))),
+                        move |_| fmap(r.update(|(now, max)| (now - 1, max)), move |_| i),
+                    )
+                })
+            })
+        });
+        let res = CFuture::par_sequence(work.collect()).await;
+        assert_eq!(res, vec![0, 1, 2, 3, 4]);
+        assert_eq!(running.get().await, (0, 2));
+        assert_eq!(start.elapsed(), Duration::from_millis(30));
+        assert_eq!(semaphore.available(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_bounded_queue() {
+        let queue = Queue::bounded(2);
+        queue.offer(1u32).await;
+        queue.offer(2).await;
+        assert_eq!(queue.len(), 2);
+
+        // Full, so the producer waits for a consumer
+        let producer = Fiber::start(queue.offer(3)).await;
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.take().await, 1);
+        assert_eq!(producer.join().await, Ok(()));
+        assert_eq!(queue.take().await, 2);
+        assert_eq!(queue.take().await, 3);
+        assert!(queue.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_capacity_limits() {
+        assert_eq!(Semaphore::new(usize::MAX).available(), Permits::MAX_PERMITS);
+        let queue = Queue::bounded(0);
+        queue.offer(1u32).await;
+        assert_eq!(queue.take().await, 1);
+        let queue = Queue::bounded(usize::MAX);
+        queue.offer(2u32).await;
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unbounded_queue() {
+        let queue = Queue::unbounded();
+        let consumer = Fiber::start(lift_m2::<CFuture<_>, _, _>(|a: u32, b: u32| (a, b))(
+            queue.take(),
+            queue.take(),
+        ))
+        .await;
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        for i in 0..100 {
+            queue.offer(i).await;
+        }
+        assert_eq!(consumer.join().await, Ok((0, 1)));
+        assert_eq!(queue.len(), 98);
+    }
+
+    #[tokio::test]
+    async fn test_queue_producers_and_consumers() {
+        let queue = Queue::bounded(4);
+        let producers: Vec<_> = (0..4u32)
+            .map(|p| {
+                let queue = queue.clone();
+                tokio::spawn(async move {
+                    for i in 0..100 {
+                        queue.offer(p * 100 + i).await;
+                    }
+                })
+            })
+            .collect();
+        let consumers: Vec<_> = (0..4)
+            .map(|_| {
+                let queue = queue.clone();
+                tokio::spawn(async move {
+                    let mut got = vec![];
+                    for _ in 0..100 {
+                        got.push(queue.take().await);
+                    }
+                    got
+                })
+            })
+            .collect();
+        futures::future::join_all(producers).await;
+        let mut all: Vec<u32> = futures::future::join_all(consumers)
+            .await
+            .into_iter()
+            .flat_map(Result::unwrap)
+            .collect();
+        all.sort();
+        assert_eq!(all, (0..400).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,516 @@
+use crate::prelude::typeclasses::*;
+use crate::typeclasses::free_effect::trace::Traceable;
+#[cfg(feature = "tokio")]
+use crate::types::clock::SystemClock;
+use crate::types::{
+    cancel::Cancelled, cfuture::CFuture, clock::Clock, executor::spawn_detached, io::sync_fn,
+};
+use futures::{
+    FutureExt, Stream, StreamExt,
+    future::{BoxFuture, ready},
+    stream::{self, BoxStream},
+};
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+type Build<T> = Arc<dyn Fn() -> BoxStream<'static, T> + Send + Sync>;
+
+/// A description of an asynchronous stream of `T`s.
+///
+/// Like `IO`, a `CStream` runs nothing when built: every call to `stream` (or every
+/// `fold` of it) starts a new run of the underlying `futures::Stream`, so streams can be
+/// cloned and reused freely.  As a monad a stream behaves like a `Vec` whose items
+/// arrive over time: `bind` runs the stream built from each item in turn and
+/// concatenates them, and `combine` concatenates two streams.
+///
+/// ```rust
+/// use rust_effects::prelude::*;
+///
+/// let numbers = CStream::iter(1..=4u32);
+/// let pairs = bind(numbers.clone(), |n| CStream::iter(vec![n; n as usize % 2 + 1]));
+/// assert_eq!(pairs.to_vec().wait(), vec![1, 1, 2, 3, 3, 4]);
+/// assert_eq!(numbers.scan().to_vec().wait(), vec![1, 3, 6, 10]);
+/// assert_eq!(numbers.fold(0, |acc, n| acc * 10 + n).wait(), 1234);
+/// ```
+pub struct CStream<T> {
+    build: Build<T>,
+}
+
+impl<T> Clone for CStream<T> {
+    fn clone(&self) -> Self {
+        CStream {
+            build: self.build.clone(),
+        }
+    }
+}
+
+impl<T: Send + 'static> CStream<T> {
+    /// Build the stream to run with `make` every time it runs.
+    pub fn new<S>(make: impl Fn() -> S + Send + Sync + 'static) -> CStream<T>
+    where
+        S: Stream<Item = T> + Send + 'static,
+    {
+        CStream {
+            build: Arc::new(move || make().boxed()),
+        }
+    }
+
+    /// The items of `items`, cloned for every run.
+    pub fn iter<I>(items: I) -> CStream<T>
+    where
+        I: IntoIterator<Item = T> + Clone + Send + Sync + 'static,
+        I::IntoIter: Send + 'static,
+    {
+        CStream::new(move || stream::iter(items.clone()))
+    }
+
+    pub fn once(t: T) -> CStream<T>
+    where
+        T: Clone + Sync,
+    {
+        CStream::new(move || stream::once(ready(t.clone())))
+    }
+
+    pub fn empty() -> CStream<T> {
+        CStream::new(stream::empty)
+    }
+
+    /// A stream of the value of `fut`, or of nothing if `fut` is cancelled.
+    pub fn from_future(fut: CFuture<T>) -> CStream<T>
+    where
+        T: Clone + Sync,
+    {
+        CStream::new(move || {
+            stream::once(fut.clone().into_outcome()).filter_map(|res| ready(res.ok()))
+        })
+    }
+
+    /// Acquire a resource with `acquire`, stream the items of the stream built from it by
+    /// `use_`, then release it with `release`.
+    ///
+    /// The resource is acquired anew on every run, and released when the run ends.  If
+    /// the run is dropped before it ended, a release which doesn't complete right away
+    /// finishes in the background: on the current tokio runtime with the `tokio`
+    /// feature, and otherwise on a new thread with the built-in executor.  Nothing is
+    /// streamed or released if `acquire` is cancelled.
+    pub fn bracket<R>(
+        acquire: impl Fn() -> CFuture<R> + Send + Sync + 'static,
+        use_: impl Fn(R) -> CStream<T> + Send + Sync + 'static,
+        release: impl Fn(R) -> CFuture<()> + Send + Sync + 'static,
+    ) -> CStream<T>
+    where
+        R: Clone + Send + Sync + 'static,
+    {
+        let use_: Arc<dyn Fn(R) -> CStream<T> + Send + Sync> = Arc::new(use_);
+        let release: Arc<dyn Fn(R) -> CFuture<()> + Send + Sync> = Arc::new(release);
+        CStream::new(move || Bracket {
+            state: BracketState::Acquiring(acquire().into_outcome().boxed()),
+            use_: use_.clone(),
+            release: release.clone(),
+        })
+    }
+
+    /// Start a new run of the stream.
+    pub fn stream(&self) -> BoxStream<'static, T> {
+        (self.build)()
+    }
+
+    pub fn map<U: Send + 'static>(
+        &self,
+        func: impl Fn(T) -> U + Send + Sync + 'static,
+    ) -> CStream<U> {
+        let (build, func) = (self.build.clone(), Arc::new(func));
+        CStream::new(move || {
+            let func = func.clone();
+            build().map(move |t| func(t))
+        })
+    }
+
+    /// Run the stream built from each item by `func`, one after the other.
+    pub fn and_then<U: Send + 'static>(
+        &self,
+        func: impl Fn(T) -> CStream<U> + Send + Sync + 'static,
+    ) -> CStream<U> {
+        let (build, func) = (self.build.clone(), Arc::new(func));
+        CStream::new(move || {
+            let func = func.clone();
+            build().flat_map(move |t| func(t).stream())
+        })
+    }
+
+    /// Group the items in vectors of `size`; the last one may be shorter.
+    pub fn chunks(&self, size: usize) -> CStream<Vec<T>> {
+        let build = self.build.clone();
+        CStream::new(move || build().chunks(size.max(1)))
+    }
+
+    /// The items of both streams as they arrive, ending once both ended.  When both have
+    /// items ready they take turns.
+    pub fn merge(&self, other: &CStream<T>) -> CStream<T> {
+        let (a, b) = (self.build.clone(), other.build.clone());
+        CStream::new(move || stream::select(a(), b()))
+    }
+
+    /// Pairs of the items of both streams, ending as soon as one of them ends.
+    pub fn zip<U: Send + 'static>(&self, other: &CStream<U>) -> CStream<(T, U)> {
+        let (a, b) = (self.build.clone(), other.build.clone());
+        CStream::new(move || a().zip(b()))
+    }
+
+    /// The running totals of the items, combined from `empty`.
+    pub fn scan(&self) -> CStream<T>
+    where
+        T: Monoid + Clone,
+    {
+        let build = self.build.clone();
+        CStream::new(move || {
+            build().scan(T::empty(), |total, t| {
+                *total = T::combine(total.clone(), t);
+                ready(Some(total.clone()))
+            })
+        })
+    }
+
+    /// The items up to the first one for which `pred` is false.
+    pub fn take_while(&self, pred: impl Fn(&T) -> bool + Send + Sync + 'static) -> CStream<T> {
+        let (build, pred) = (self.build.clone(), Arc::new(pred));
+        CStream::new(move || {
+            let pred = pred.clone();
+            build().take_while(move |t| ready(pred(t)))
+        })
+    }
+
+    /// Space the items out so that they are at least `period` apart, as measured by
+    /// `clock`.  Items arriving too soon are delayed, not dropped.
+    pub fn throttle_on(&self, clock: impl Clock + 'static, period: Duration) -> CStream<T> {
+        let (build, clock) = (self.build.clone(), Arc::new(clock));
+        CStream::new(move || {
+            let clock = clock.clone();
+            stream::unfold((build(), None), move |(mut items, last)| {
+                let clock = clock.clone();
+                async move {
+                    let t = items.next().await?;
+                    let now = clock.monotonic().into_outcome().await.ok()?;
+                    let sent = match last {
+                        Some(last) if now < last + period => {
+                            clock.sleep(last + period - now).into_outcome().await.ok()?;
+                            last + period
+                        }
+                        _ => now,
+                    };
+                    Some((t, (items, Some(sent))))
+                }
+            })
+        })
+    }
+
+    /// Like `throttle_on`, on the system clock.
+    #[cfg(feature = "tokio")]
+    pub fn throttle(&self, period: Duration) -> CStream<T> {
+        self.throttle_on(SystemClock::new(), period)
+    }
+
+    /// Run the stream, folding its items into `init` with `func`.
+    pub fn fold<B>(&self, init: B, func: impl Fn(B, T) -> B + Send + 'static) -> CFuture<B>
+    where
+        B: Clone + Send + Sync + 'static,
+    {
+        let items = self.stream();
+        CFuture::new(items.fold(init, move |b, t| ready(func(b, t))))
+    }
+
+    /// Run the stream, collecting its items.
+    pub fn to_vec(&self) -> CFuture<Vec<T>>
+    where
+        T: Clone + Sync,
+    {
+        self.fold(vec![], |mut items, t| {
+            items.push(t);
+            items
+        })
+    }
+}
+
+enum BracketState<R, T> {
+    Acquiring(BoxFuture<'static, Result<R, Cancelled>>),
+    Using(R, BoxStream<'static, T>),
+    Releasing(BoxFuture<'static, Result<(), Cancelled>>),
+    Done,
+}
+
+/// A run of a stream built by `CStream::bracket`.
+struct Bracket<R, T> {
+    state: BracketState<R, T>,
+    use_: Arc<dyn Fn(R) -> CStream<T> + Send + Sync>,
+    release: Arc<dyn Fn(R) -> CFuture<()> + Send + Sync>,
+}
+
+impl<R, T> Stream for Bracket<R, T>
+where
+    R: Clone + Send + Sync + 'static,
+    T: Send + 'static,
+{
+    type Item = T;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let this = self.get_mut();
+        loop {
+            let next = match &mut this.state {
+                BracketState::Acquiring(acquire) => match acquire.poll_unpin(cx) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(Ok(r)) => BracketState::Using(r.clone(), (this.use_)(r).stream()),
+                    Poll::Ready(Err(Cancelled)) => BracketState::Done,
+                },
+                BracketState::Using(r, items) => match items.poll_next_unpin(cx) {
+                    Poll::Ready(None) => {
+                        BracketState::Releasing((this.release)(r.clone()).into_outcome().boxed())
+                    }
+                    polled => return polled,
+                },
+                BracketState::Releasing(release) => match release.poll_unpin(cx) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(_) => BracketState::Done,
+                },
+                BracketState::Done => return Poll::Ready(None),
+            };
+            this.state = next;
+        }
+    }
+}
+
+// The state is never pinned: its futures and streams are boxed.
+impl<R, T> Unpin for Bracket<R, T> {}
+
+impl<R, T> Drop for Bracket<R, T> {
+    fn drop(&mut self) {
+        match std::mem::replace(&mut self.state, BracketState::Done) {
+            BracketState::Using(r, items) => {
+                drop(items);
+                spawn_detached((self.release)(r).into_outcome().map(|_| ()));
+            }
+            BracketState::Releasing(release) => spawn_detached(release.map(|_| ())),
+            _ => {}
+        }
+    }
+}
+
+impl<A: Send + 'static> Semigroup for CStream<A> {
+    fn combine(a: Self, b: Self) -> Self {
+        CStream::new(move || a.stream().chain(b.stream()))
+    }
+}
+
+impl<A: Send + 'static> Monoid for CStream<A> {
+    fn empty() -> Self {
+        CStream::empty()
+    }
+}
+
+impl<T, U> Functor<U> for CStream<T>
+where
+    T: Send + 'static,
+    U: Send + 'static,
+{
+    type FuncT = T;
+    type FunctorOut = CStream<U>;
+    fn fmap(m: Self, func: impl Fn(T) -> U + Send + 'static) -> Self::FunctorOut {
+        m.map(sync_fn(func))
+    }
+}
+
+impl<T, U> Applicative<U> for CStream<T>
+where
+    T: Clone + Send + Sync + 'static,
+    U: Send + 'static,
+{
+    type AppT = T;
+    fn pure(a: T) -> Self {
+        CStream::once(a)
+    }
+}
+
+impl<F, T, U> ApplicativeFunctor<F, U> for CStream<T>
+where
+    F: Fn(T) -> U + Send + Sync + 'static,
+    T: Clone + Send + Sync + 'static,
+    U: Clone + Send + Sync + 'static,
+{
+    type AppFuncT = T;
+    type AppFuncOut = CStream<U>;
+    type AppFuncFn = CStream<F>;
+    fn seq(m: Self, func: Self::AppFuncFn) -> Self::AppFuncOut {
+        func.and_then(move |f| m.map(f))
+    }
+}
+
+impl<T, U> Monad<U> for CStream<T>
+where
+    T: Clone + Send + Sync + 'static,
+    U: Clone + Send + Sync + 'static,
+{
+    type MonadT = T;
+    type MonadOut = CStream<U>;
+    fn bind(m: Self, func: impl Fn(T) -> Self::MonadOut + Send + 'static) -> Self::MonadOut {
+        m.and_then(sync_fn(func))
+    }
+}
+
+impl<A> Traceable for CStream<A> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::{cancel::CancelToken, clock::TestClock};
+    use std::sync::Mutex;
+
+    type Log = Arc<Mutex<Vec<String>>>;
+
+    fn push(log: &Log, entry: String) -> CFuture<()> {
+        let log = log.clone();
+        CFuture::new(async move { log.lock().unwrap().push(entry) })
+    }
+
+    /// A stream of the lines of file `name`, opened and closed around each run.
+    fn lines(log: &Log, name: &'static str) -> CStream<String> {
+        let (open, close) = (log.clone(), log.clone());
+        CStream::bracket(
+            move || fmap(push(&open, format!("open {}", name)), move |_| name),
+            |name| CStream::iter((1..=3).map(move |n| format!("{}{}", name, n))),
+            move |name| push(&close, format!("close {}", name)),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_monoid_stream() {
+        let (a, b) = (CStream::iter(vec![1, 2]), CStream::iter(vec![3]));
+        assert_eq!(combine(a.clone(), b.clone()).to_vec().await, vec![1, 2, 3]);
+        assert_eq!(combine(a.clone(), empty()).to_vec().await, vec![1, 2]);
+        assert_eq!(combine(empty(), b).to_vec().await, vec![3]);
+        assert!(CStream::<u32>::empty().to_vec().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_monad_stream() {
+        let numbers = CStream::iter(vec![3u32, 4]);
+        assert_eq!(fmap(numbers.clone(), |n| n + 4).to_vec().await, vec![7, 8]);
+        assert_eq!(pure::<CStream<_>>(2).to_vec().await, vec![2]);
+
+        let funcs = CStream::iter(vec![|n: u32| n + 2, |n: u32| n * 2]);
+        assert_eq!(seq(numbers.clone(), funcs).to_vec().await, vec![5, 6, 6, 8]);
+
+        let repeated = bind(numbers.clone(), |n| CStream::iter(vec![n; n as usize - 2]));
+        assert_eq!(repeated.to_vec().await, vec![3, 4, 4]);
+        let add = lift_m2::<CStream<_>, _, _>(|a: u32, b: u32| a * 10 + b);
+        let sums = add(numbers.clone(), CStream::iter(vec![1, 2]));
+        assert_eq!(sums.to_vec().await, vec![31, 32, 41, 42]);
+    }
+
+    #[tokio::test]
+    async fn test_combinators() {
+        let numbers = CStream::iter(1..=5u32);
+        let chunks = numbers.chunks(2).to_vec().await;
+        assert_eq!(chunks, vec![vec![1, 2], vec![3, 4], vec![5]]);
+        let zipped = numbers.zip(&CStream::iter("ab".chars())).to_vec().await;
+        assert_eq!(zipped, vec![(1, 'a'), (2, 'b')]);
+        assert_eq!(numbers.scan().to_vec().await, vec![1, 3, 6, 10, 15]);
+        let small = numbers.take_while(|n| *n < 3);
+        assert_eq!(small.to_vec().await, vec![1, 2]);
+        assert_eq!(
+            numbers.fold(String::new(), |s, n| s + &n.to_string()).await,
+            "12345"
+        );
+
+        let mut merged = numbers.merge(&CStream::iter(6..=7)).to_vec().await;
+        assert_eq!(merged.len(), 7);
+        merged.sort();
+        assert_eq!(merged, (1..=7).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_from_future() {
+        let fut = CFuture::new(async { 3 });
+        assert_eq!(CStream::from_future(fut).to_vec().await, vec![3]);
+        let token = CancelToken::new();
+        let cancelled = CFuture::new(futures::future::pending::<u32>()).cancellable(&token);
+        token.cancel();
+        let cancelled = CStream::from_future(cancelled);
+        assert!(cancelled.to_vec().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_bracket_releases() {
+        let log = Log::default();
+        let both = combine(lines(&log, "a"), lines(&log, "b"));
+        assert_eq!(both.to_vec().await.len(), 6);
+        let expected = vec!["open a", "close a", "open b", "close b"];
+        assert_eq!(*log.lock().unwrap(), expected);
+
+        // Stopping early releases the resource too, and runs reacquire it
+        log.lock().unwrap().clear();
+        let first = lines(&log, "a").take_while(|line| line == "a1");
+        assert_eq!(first.to_vec().await, vec!["a1"]);
+        assert_eq!(first.to_vec().await, vec!["a1"]);
+        let expected = vec!["open a", "close a", "open a", "close a"];
+        assert_eq!(*log.lock().unwrap(), expected);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_bracket_async_release_on_drop() {
+        let released = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let r = released.clone();
+        let numbers = CStream::bracket(
+            || CFuture::lazy(()),
+            |_| CStream::iter(1..=3u32),
+            move |_| {
+                let r = r.clone();
+                fmap(
+                    CFuture::new(tokio::time::sleep(Duration::from_millis(1))),
+                    move |_| r.store(true, std::sync::atomic::Ordering::SeqCst),
+                )
+            },
+        );
+        assert_eq!(numbers.take_while(|n| *n < 2).to_vec().await, vec![1]);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(released.load(std::sync::atomic::Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_throttle_on() {
+        let clock = TestClock::new();
+        let sent = CStream::iter(1..=3u32).throttle_on(clock.clone(), Duration::from_secs(1));
+        let c = clock.clone();
+        let times = bind(sent, move |_| CStream::from_future(c.monotonic()));
+        let times = tokio::spawn(times.to_vec());
+        for _ in 0..2 {
+            while clock.sleepers() == 0 {
+                tokio::task::yield_now().await;
+            }
+            clock.advance_to_next();
+        }
+        let secs = |s: Vec<u64>| s.into_iter().map(Duration::from_secs).collect::<Vec<_>>();
+        assert_eq!(times.await.unwrap(), secs(vec![0, 1, 2]));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test(start_paused = true)]
+    async fn test_throttle() {
+        let start = tokio::time::Instant::now();
+        let slow = CStream::new(|| {
+            stream::iter(1..=4u32).then(|n| async move {
+                if n == 3 {
+                    tokio::time::sleep(Duration::from_millis(250)).await;
+                }
+                n
+            })
+        });
+        let throttled = slow.throttle(Duration::from_millis(100));
+        assert_eq!(throttled.to_vec().await, vec![1, 2, 3, 4]);
+        // 1 at 0ms and 2 at 100ms, then 3 comes late at 350ms and isn't held back
+        // further, and 4 follows at 450ms
+        assert_eq!(start.elapsed(), Duration::from_millis(450));
+    }
+}
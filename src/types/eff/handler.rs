@@ -0,0 +1,260 @@
+use std::{
+    any::{Any, TypeId, type_name},
+    collections::HashMap,
+    fmt::{Display, Formatter},
+};
+
+#[cfg(feature = "cfuture")]
+use crate::types::{cancel::Cancelled, cfuture::CFuture};
+#[cfg(feature = "cfuture")]
+use futures::{FutureExt, future::BoxFuture};
+
+use crate::types::eff::{AnyValue, Cont, Eff, Node, OpRequest};
+
+enum ReplyValue {
+    Now(AnyValue),
+    #[cfg(feature = "cfuture")]
+    Later(BoxFuture<'static, Result<AnyValue, Cancelled>>),
+}
+
+/// The reply of a handler to an operation.
+///
+/// The value must have the type the operation was performed with in `Eff::perform`,
+/// otherwise running the program fails with `RunError::ReplyType`.
+pub struct Reply {
+    value: ReplyValue,
+    name: &'static str,
+}
+
+impl Reply {
+    pub fn new<T: Send + 'static>(value: T) -> Reply {
+        Reply {
+            value: ReplyValue::Now(Box::new(value)),
+            name: type_name::<T>(),
+        }
+    }
+
+    /// Reply with the value of a future, for handlers doing I/O.  Programs getting
+    /// such replies must be run with `Handlers::run_async`.
+    #[cfg(feature = "cfuture")]
+    pub fn later<T>(value: CFuture<T>) -> Reply
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        Reply {
+            value: ReplyValue::Later(
+                value
+                    .into_outcome()
+                    .map(|res| res.map(|t| Box::new(t) as AnyValue))
+                    .boxed(),
+            ),
+            name: type_name::<T>(),
+        }
+    }
+}
+
+/// Gives meaning to the operations of effect `E`.
+///
+/// Closures taking an `E` and returning a `Reply` are handlers as well.
+pub trait Handler<E>: Send {
+    fn handle(&mut self, op: E) -> Reply;
+}
+
+impl<E, F> Handler<E> for F
+where
+    F: FnMut(E) -> Reply + Send,
+{
+    fn handle(&mut self, op: E) -> Reply {
+        self(op)
+    }
+}
+
+/// Errors raised while running an `Eff` program.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RunError {
+    /// No handler was registered for this effect
+    Unhandled(&'static str),
+    /// A handler replied with a value of the wrong type
+    ReplyType {
+        effect: &'static str,
+        expected: &'static str,
+        found: &'static str,
+    },
+    /// A handler replied with `Reply::later` to a program run with `Handlers::run`
+    AsyncReply(&'static str),
+    /// The future a handler replied with was cancelled
+    Cancelled(&'static str),
+}
+
+impl Display for RunError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RunError::Unhandled(effect) => write!(f, "no handler for effect {}", effect),
+            RunError::ReplyType {
+                effect,
+                expected,
+                found,
+            } => write!(
+                f,
+                "handler for effect {} replied with {} where {} was expected",
+                effect, found, expected
+            ),
+            RunError::AsyncReply(effect) => write!(
+                f,
+                "handler for effect {} replied with a future; use run_async",
+                effect
+            ),
+            RunError::Cancelled(effect) => {
+                write!(
+                    f,
+                    "reply of the handler for effect {} was cancelled",
+                    effect
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for RunError {}
+
+type ErasedHandler = Box<dyn FnMut(AnyValue) -> Reply + Send>;
+
+/// A set of handlers, one per effect, used to run `Eff` programs.
+///
+/// Programs may use any number of effects; each operation is dispatched to the handler
+/// registered for its effect enum.  Registering a handler for an effect which already
+/// has one replaces it, so a base set of handlers can be specialised per test.
+#[derive(Default)]
+pub struct Handlers {
+    handlers: HashMap<TypeId, ErasedHandler>,
+}
+
+impl Handlers {
+    pub fn new() -> Handlers {
+        Handlers {
+            handlers: HashMap::new(),
+        }
+    }
+
+    /// Add (or replace) the handler for effect `E`.
+    pub fn with<E, H>(mut self, mut handler: H) -> Handlers
+    where
+        E: Send + 'static,
+        H: Handler<E> + 'static,
+    {
+        self.handlers.insert(
+            TypeId::of::<E>(),
+            Box::new(move |op: AnyValue| {
+                let op = op
+                    .downcast::<E>()
+                    .expect("operation dispatched to the wrong handler");
+                handler.handle(*op)
+            }),
+        );
+        self
+    }
+
+    /// Merge in all of the handlers of `other`, replacing any for the same effects.
+    pub fn extend(mut self, other: Handlers) -> Handlers {
+        self.handlers.extend(other.handlers);
+        self
+    }
+
+    pub fn handles<E: 'static>(&self) -> bool {
+        self.handlers.contains_key(&TypeId::of::<E>())
+    }
+
+    /// Run a program to completion.  Fails with `RunError::AsyncReply` if a handler
+    /// replies with a future.
+    pub fn run<A: Send + 'static>(&mut self, program: Eff<A>) -> Result<A, RunError> {
+        let mut conts: Vec<Cont> = vec![];
+        let mut node = program.node;
+        loop {
+            node = match node {
+                Node::Pure(value) => match conts.pop() {
+                    Some(cont) => cont(value),
+                    None => return Ok(output(value)),
+                },
+                Node::Bind(inner, cont) => {
+                    conts.push(cont);
+                    inner.take()
+                }
+                Node::Op(request) => {
+                    let (reply, request) = self.handle(request)?;
+                    match reply.value {
+                        ReplyValue::Now(value) => checked(&request, value, reply.name)?,
+                        #[cfg(feature = "cfuture")]
+                        ReplyValue::Later(_) => {
+                            return Err(RunError::AsyncReply(request.effect_name));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Run a program whose handlers may reply with futures (see `Reply::later`),
+    /// awaiting each reply before continuing the program.  The handlers are moved into
+    /// the returned future, which runs nothing until polled.
+    #[cfg(feature = "cfuture")]
+    pub fn run_async<A>(mut self, program: Eff<A>) -> CFuture<Result<A, RunError>>
+    where
+        A: Clone + Send + Sync + 'static,
+    {
+        CFuture::new(async move {
+            let mut conts: Vec<Cont> = vec![];
+            let mut node = program.node;
+            loop {
+                node = match node {
+                    Node::Pure(value) => match conts.pop() {
+                        Some(cont) => cont(value),
+                        None => return Ok(output(value)),
+                    },
+                    Node::Bind(inner, cont) => {
+                        conts.push(cont);
+                        inner.take()
+                    }
+                    Node::Op(request) => {
+                        let (reply, request) = self.handle(request)?;
+                        let value = match reply.value {
+                            ReplyValue::Now(value) => value,
+                            ReplyValue::Later(fut) => fut
+                                .await
+                                .map_err(|_| RunError::Cancelled(request.effect_name))?,
+                        };
+                        checked(&request, value, reply.name)?
+                    }
+                }
+            }
+        })
+    }
+
+    /// Dispatch an operation to the handler of its effect, returning the request
+    /// without its operation.
+    fn handle(&mut self, mut request: OpRequest) -> Result<(Reply, OpRequest), RunError> {
+        let handler = self
+            .handlers
+            .get_mut(&request.effect)
+            .ok_or(RunError::Unhandled(request.effect_name))?;
+        let op = std::mem::replace(&mut request.op, Box::new(()));
+        Ok((handler(op), request))
+    }
+}
+
+/// Check that a handler replied with the type the operation was performed with.
+fn checked(request: &OpRequest, value: AnyValue, name: &'static str) -> Result<Node, RunError> {
+    if Any::type_id(&*value) != request.reply {
+        return Err(RunError::ReplyType {
+            effect: request.effect_name,
+            expected: request.reply_name,
+            found: name,
+        });
+    }
+    Ok(Node::Pure(value))
+}
+
+fn output<A: 'static>(value: AnyValue) -> A {
+    *value
+        .downcast::<A>()
+        .expect("Eff program produced an unexpected value type")
+}
@@ -0,0 +1,423 @@
+pub mod handler;
+
+use std::{
+    any::{Any, TypeId, type_name},
+    marker::PhantomData,
+};
+
+use crate::prelude::typeclasses::*;
+
+pub(crate) type AnyValue = Box<dyn Any + Send>;
+pub(crate) type Cont = Box<dyn FnOnce(AnyValue) -> Node + Send>;
+
+pub(crate) struct OpRequest {
+    pub(crate) op: AnyValue,
+    pub(crate) effect: TypeId,
+    pub(crate) effect_name: &'static str,
+    pub(crate) reply: TypeId,
+    pub(crate) reply_name: &'static str,
+}
+
+pub(crate) enum Node {
+    Pure(AnyValue),
+    Op(OpRequest),
+    Bind(Inner, Cont),
+}
+
+/// The program run first by a `Node::Bind`.
+///
+/// Left-nested binds form a chain of boxed nodes, which would be dropped recursively;
+/// it is unlinked one node at a time instead, so dropping a deep program which never
+/// ran doesn't overflow the stack.
+pub(crate) struct Inner(Box<Node>);
+
+impl Inner {
+    fn new(node: Node) -> Inner {
+        Inner(Box::new(node))
+    }
+
+    pub(crate) fn take(mut self) -> Node {
+        self.unlink()
+    }
+
+    fn unlink(&mut self) -> Node {
+        std::mem::replace(&mut *self.0, Node::Pure(Box::new(())))
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        let mut node = self.unlink();
+        while let Node::Bind(inner, _) = &mut node {
+            node = inner.unlink();
+        }
+    }
+}
+
+/// A program over algebraic effects (the "Freer" monad).
+///
+/// Effects are declared as plain enums of operations, and a program is built from
+/// `Eff::perform` of these operations, `Eff::pure` values and `bind`.  Building a program
+/// runs nothing: it is only a description of which operations to perform in which order.
+/// Programs are run by a `Handlers` set (see the `handler` module), which gives each
+/// operation its meaning, so the same program can be run against an in-memory store in
+/// tests and a real one in production.  Handlers doing I/O can reply with a `CFuture`
+/// (see `Reply::later`), in which case the program is run with `Handlers::run_async`.
+///
+/// The interpreter keeps the pending continuations on the heap, so deeply nested chains
+/// of `bind` (in either direction) don't grow the stack, and neither does dropping a
+/// program.
+///
+/// ```rust
+/// use rust_effects::prelude::*;
+/// use rust_effects::types::eff::{Eff, handler::{Handlers, Reply}};
+///
+/// enum KvOp {
+///     Get(String),
+///     Put(String, u32),
+/// }
+///
+/// fn get(key: &str) -> Eff<Option<u32>> {
+///     Eff::perform(KvOp::Get(key.to_string()))
+/// }
+/// fn put(key: &str, val: u32) -> Eff<()> {
+///     Eff::perform(KvOp::Put(key.to_string(), val))
+/// }
+///
+/// let program = bind(put("a", 3), |_| get("a"));
+///
+/// let mut store = std::collections::HashMap::new();
+/// let mut handlers = Handlers::new().with(move |op: KvOp| match op {
+///     KvOp::Get(key) => Reply::new(store.get(&key).copied()),
+///     KvOp::Put(key, val) => {
+///         store.insert(key, val);
+///         Reply::new(())
+///     }
+/// });
+/// assert_eq!(handlers.run(program), Ok(Some(3)));
+/// ```
+pub struct Eff<A> {
+    pub(crate) node: Node,
+    _ph: PhantomData<fn() -> A>,
+}
+
+impl<A: Send + 'static> Eff<A> {
+    pub(crate) fn from_node(node: Node) -> Eff<A> {
+        Eff {
+            node,
+            _ph: PhantomData,
+        }
+    }
+
+    pub fn pure(a: A) -> Eff<A> {
+        Eff::from_node(Node::Pure(Box::new(a)))
+    }
+
+    /// A program performing a single operation of effect `E`, whose handler must reply
+    /// with a value of type `A`.
+    pub fn perform<E: Send + 'static>(op: E) -> Eff<A> {
+        Eff::from_node(Node::Op(OpRequest {
+            op: Box::new(op),
+            effect: TypeId::of::<E>(),
+            effect_name: type_name::<E>(),
+            reply: TypeId::of::<A>(),
+            reply_name: type_name::<A>(),
+        }))
+    }
+
+    /// Same as `Monad::bind`, but the continuation only needs to be callable once.
+    pub fn and_then<B: Send + 'static>(
+        self,
+        func: impl FnOnce(A) -> Eff<B> + Send + 'static,
+    ) -> Eff<B> {
+        let cont: Cont = Box::new(move |a: AnyValue| {
+            let a = a
+                .downcast::<A>()
+                .expect("Eff continuation received an unexpected value type");
+            func(*a).node
+        });
+        Eff::from_node(Node::Bind(Inner::new(self.node), cont))
+    }
+
+    /// Same as `Functor::fmap`, but the function only needs to be callable once.
+    pub fn map<B: Send + 'static>(self, func: impl FnOnce(A) -> B + Send + 'static) -> Eff<B> {
+        self.and_then(move |a| Eff::pure(func(a)))
+    }
+
+    /// Run the programs one after another, collecting their results.
+    pub fn sequence(programs: Vec<Eff<A>>) -> Eff<Vec<A>> {
+        programs
+            .into_iter()
+            .fold(Eff::pure(Vec::new()), |acc, program| {
+                acc.and_then(move |mut results| {
+                    program.map(move |a| {
+                        results.push(a);
+                        results
+                    })
+                })
+            })
+    }
+}
+
+impl<A> Semigroup for Eff<A>
+where
+    A: Semigroup + Send + 'static,
+{
+    fn combine(a: Self, b: Self) -> Self {
+        a.and_then(move |a_res| b.map(move |b_res| A::combine(a_res, b_res)))
+    }
+    fn combine_m(a: Self, b: Self) -> Self {
+        a.and_then(move |a_res| b.map(move |b_res| A::combine_m(a_res, b_res)))
+    }
+}
+
+impl<A> Monoid for Eff<A>
+where
+    A: Monoid + Send + 'static,
+{
+    fn empty() -> Self {
+        Eff::pure(A::empty())
+    }
+    fn empty_m() -> Self {
+        Eff::pure(A::empty_m())
+    }
+}
+
+impl<T, U> Functor<U> for Eff<T>
+where
+    T: Send + 'static,
+    U: Send + 'static,
+{
+    type FuncT = T;
+    type FunctorOut = Eff<U>;
+    fn fmap(m: Self, func: impl Fn(T) -> U + Send + 'static) -> Self::FunctorOut {
+        m.map(func)
+    }
+}
+
+impl<T, U> Applicative<U> for Eff<T>
+where
+    T: Send + 'static,
+    U: Send + 'static,
+{
+    type AppT = T;
+    fn pure(a: T) -> Self {
+        Eff::pure(a)
+    }
+}
+
+impl<F, T, U> ApplicativeFunctor<F, U> for Eff<T>
+where
+    F: Fn(T) -> U + Send + 'static,
+    T: Send + 'static,
+    U: Send + 'static,
+{
+    type AppFuncT = T;
+    type AppFuncOut = Eff<U>;
+    type AppFuncFn = Eff<F>;
+    fn seq(m: Self, func: Self::AppFuncFn) -> Self::AppFuncOut {
+        func.and_then(move |f| m.map(f))
+    }
+}
+
+impl<T, U> Monad<U> for Eff<T>
+where
+    T: Send + 'static,
+    U: Send + 'static,
+{
+    type MonadT = T;
+    type MonadOut = Eff<U>;
+    fn bind(m: Self, func: impl Fn(T) -> Self::MonadOut + Send + 'static) -> Self::MonadOut {
+        m.and_then(func)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::handler::*;
+    use super::*;
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    enum KvOp {
+        Get(String),
+        Put(String, u32),
+    }
+
+    enum LogOp {
+        Info(String),
+    }
+
+    fn get(key: &str) -> Eff<Option<u32>> {
+        Eff::perform(KvOp::Get(key.to_string()))
+    }
+
+    fn put(key: &str, val: u32) -> Eff<()> {
+        Eff::perform(KvOp::Put(key.to_string(), val))
+    }
+
+    fn info(msg: impl Into<String>) -> Eff<()> {
+        Eff::perform(LogOp::Info(msg.into()))
+    }
+
+    #[derive(Default)]
+    struct MemoryKv(HashMap<String, u32>);
+
+    impl Handler<KvOp> for MemoryKv {
+        fn handle(&mut self, op: KvOp) -> Reply {
+            match op {
+                KvOp::Get(key) => Reply::new(self.0.get(&key).copied()),
+                KvOp::Put(key, val) => {
+                    self.0.insert(key, val);
+                    Reply::new(())
+                }
+            }
+        }
+    }
+
+    fn increment(key: &'static str) -> Eff<u32> {
+        bind(get(key), move |old: Option<u32>| {
+            let new = old.unwrap_or(0) + 1;
+            bind(info(format!("{} = {}", key, new)), move |_| {
+                fmap(put(key, new), move |_| new)
+            })
+        })
+    }
+
+    #[test]
+    fn test_pure_program() {
+        let program = fmap(pure::<Eff<_>>(3), |a| a + 4);
+        assert_eq!(Handlers::new().run(program), Ok(7));
+    }
+
+    #[test]
+    fn test_swap_handlers() {
+        let logs = Arc::new(Mutex::new(vec![]));
+        let log_sink = logs.clone();
+        let mut handlers =
+            Handlers::new()
+                .with(MemoryKv::default())
+                .with(move |op: LogOp| match op {
+                    LogOp::Info(msg) => {
+                        log_sink.lock().unwrap().push(msg);
+                        Reply::new(())
+                    }
+                });
+        let program = bind(increment("a"), |_| increment("a"));
+        assert_eq!(handlers.run(program), Ok(2));
+        assert_eq!(*logs.lock().unwrap(), vec!["a = 1", "a = 2"]);
+
+        // Same program, different interpretation
+        let mut handlers = Handlers::new()
+            .with(|op: KvOp| match op {
+                KvOp::Get(_) => Reply::new(Some(41u32)),
+                KvOp::Put(_, _) => Reply::new(()),
+            })
+            .with(|_: LogOp| Reply::new(()));
+        assert_eq!(handlers.run(increment("a")), Ok(42));
+    }
+
+    #[test]
+    fn test_unhandled_effect() {
+        let mut handlers = Handlers::new().with(MemoryKv::default());
+        let err = handlers.run(increment("a")).err();
+        assert!(matches!(err, Some(RunError::Unhandled(name)) if name.ends_with("LogOp")));
+    }
+
+    #[test]
+    fn test_wrong_reply_type() {
+        let mut handlers = Handlers::new().with(|_: KvOp| Reply::new("oops"));
+        let err = handlers.run(get("a")).err();
+        assert!(matches!(err, Some(RunError::ReplyType { .. })));
+    }
+
+    #[test]
+    fn test_deep_left_nested_binds() {
+        let program = (0..100_000).fold(pure::<Eff<u64>>(0), |acc, _| {
+            bind(acc, |n| {
+                fmap(get("x"), move |x: Option<u32>| n + x.unwrap_or(1) as u64)
+            })
+        });
+        assert_eq!(
+            Handlers::new().with(MemoryKv::default()).run(program),
+            Ok(100_000)
+        );
+    }
+
+    fn countdown(n: u64) -> Eff<u64> {
+        if n == 0 {
+            Eff::pure(0)
+        } else {
+            put("n", n as u32).and_then(move |_| countdown(n - 1).map(move |m| m + 1))
+        }
+    }
+
+    #[test]
+    fn test_deep_right_nested_binds() {
+        let mut handlers = Handlers::new().with(MemoryKv::default());
+        assert_eq!(handlers.run(countdown(100_000)), Ok(100_000));
+    }
+
+    #[test]
+    fn test_drop_deep_program_without_running() {
+        let program = (0..100_000).fold(pure::<Eff<u64>>(0), |acc, _| {
+            bind(acc, |n| fmap(get("x"), move |_| n + 1))
+        });
+        drop(program);
+    }
+
+    #[cfg(feature = "cfuture")]
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_async_handlers() {
+        use crate::types::cfuture::CFuture;
+
+        let store = Arc::new(Mutex::new(HashMap::new()));
+        let kv = move |op: KvOp| {
+            let store = store.clone();
+            match op {
+                KvOp::Get(key) => Reply::later(CFuture::new(async move {
+                    tokio::task::yield_now().await;
+                    store.lock().unwrap().get(&key).copied()
+                })),
+                KvOp::Put(key, val) => Reply::later(CFuture::new(async move {
+                    tokio::task::yield_now().await;
+                    store.lock().unwrap().insert(key, val);
+                })),
+            }
+        };
+        let handlers = Handlers::new()
+            .with(kv.clone())
+            .with(|_: LogOp| Reply::new(()));
+        let program = bind(increment("a"), |_| increment("a"));
+        assert_eq!(handlers.run_async(program).await, Ok(2));
+
+        let err = Handlers::new().with(kv.clone()).run(get("a")).err();
+        assert!(matches!(err, Some(RunError::AsyncReply(name)) if name.ends_with("KvOp")));
+
+        let wrong = |_: KvOp| Reply::later(CFuture::lazy("oops"));
+        let err = Handlers::new().with(wrong).run_async(get("a")).await.err();
+        assert!(matches!(err, Some(RunError::ReplyType { .. })));
+    }
+
+    #[test]
+    fn test_seq_and_combine() {
+        let mut handlers = Handlers::new().with(|op: KvOp| match op {
+            KvOp::Get(key) => Reply::new(Some(key.len() as u32)),
+            KvOp::Put(_, _) => Reply::new(()),
+        });
+        let add = |a: Option<u32>| move |b: Option<u32>| a.unwrap() + b.unwrap();
+        let program = seq(get("abc"), fmap(get("ab"), add));
+        assert_eq!(handlers.run(program), Ok(5));
+
+        let program = combine(
+            fmap(get("abc"), Option::unwrap),
+            fmap(get("a"), Option::unwrap),
+        );
+        assert_eq!(handlers.run(program), Ok(4));
+
+        let program = Eff::sequence(vec![get("a"), get("abcd")]);
+        assert_eq!(handlers.run(program), Ok(vec![Some(1), Some(4)]));
+    }
+}
@@ -0,0 +1,108 @@
+use crate::types::cfuture::CFuture;
+use futures::future::poll_fn;
+use std::{
+    pin::pin,
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+};
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Run a future to completion on the current thread.
+///
+/// This is a minimal executor for running `CFuture`s (or any other future) without an
+/// async runtime: the thread is parked until the future's waker is called.  Futures
+/// which rely on a particular runtime's reactor (tokio timers or sockets, for instance)
+/// still need that runtime.
+///
+/// ```rust
+/// use rust_effects::prelude::*;
+/// use rust_effects::types::executor::block_on;
+///
+/// let fut = bind(CFuture::new(async { 3 }), |a| pure::<CFuture<_>>(a + 4));
+/// assert_eq!(block_on(fut), 7);
+/// ```
+pub fn block_on<F: Future>(fut: F) -> F::Output {
+    let mut fut = pin!(fut);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match fut.as_mut().poll(&mut cx) {
+            Poll::Ready(out) => return out,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+/// A future which lets the executor switch to another task before it resolves.
+pub fn yield_now() -> CFuture<()> {
+    let mut yielded = false;
+    CFuture::new(poll_fn(move |cx| {
+        if yielded {
+            Poll::Ready(())
+        } else {
+            yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }))
+}
+
+/// Run `fut` without waiting for it, for cleanup which can't be awaited where it is
+/// needed, such as in `Drop`.  It is polled once right away, and if it didn't complete,
+/// it is spawned on the current tokio runtime (with the `tokio` feature) or otherwise
+/// run on a new thread with `block_on`.
+pub(crate) fn spawn_detached(fut: impl Future<Output = ()> + Send + 'static) {
+    let mut fut = Box::pin(fut);
+    if fut
+        .as_mut()
+        .poll(&mut Context::from_waker(Waker::noop()))
+        .is_ready()
+    {
+        return;
+    }
+    #[cfg(feature = "tokio")]
+    if let Ok(handle) = tokio::runtime::Handle::try_current() {
+        handle.spawn(fut);
+        return;
+    }
+    thread::spawn(move || block_on(fut));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prelude::*;
+    use crate::types::cancel::CancelToken;
+    use std::time::Duration;
+
+    #[test]
+    fn test_block_on_ready() {
+        assert_eq!(block_on(fmap(CFuture::lazy(3), |a| a + 4)), 7);
+    }
+
+    #[test]
+    fn test_block_on_woken_from_thread() {
+        let token = CancelToken::new();
+        let canceller = token.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            canceller.cancel();
+        });
+        let fut = bind(CFuture::new(token.cancelled()), |_| {
+            seq(CFuture::new(async { 3 }), CFuture::lazy(|a: u32| a * 2))
+        });
+        assert_eq!(block_on(fut), 6);
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,400 @@
+use crate::prelude::typeclasses::*;
+use crate::types::{
+    cancel::{CancelToken, Cancelled},
+    cfuture::{CFuture, Elapsed, TaskError},
+    clock::Clock,
+};
+use futures::{FutureExt, future::join_all};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// A handle to work running concurrently on the tokio runtime.
+///
+/// Fibers are started from inside a `CFuture` chain, and the work keeps running whether
+/// or not the fiber is joined.  Clones of a fiber refer to the same running work.
+///
+/// ```rust
+/// use rust_effects::prelude::*;
+/// use rust_effects::types::fiber::Fiber;
+///
+/// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+/// let res = bind(Fiber::start(CFuture::new(async { 3u32 })), |fiber| {
+///     // ... do something else while the fiber runs ...
+///     fmap(fiber.join(), |res| res.unwrap() + 4)
+/// });
+/// assert_eq!(res.await, 7);
+/// # });
+/// ```
+#[derive(Clone)]
+pub struct Fiber<T> {
+    result: CFuture<Result<T, TaskError>>,
+    token: CancelToken,
+}
+
+impl<T: Clone + Send + Sync + 'static> Fiber<T> {
+    /// Start running `work` in a new task once the returned future is polled.
+    ///
+    /// Must be polled from within a tokio runtime.
+    pub fn start(work: CFuture<T>) -> CFuture<Fiber<T>> {
+        CFuture::new(async move { Fiber::spawn(work, CancelToken::new()) })
+    }
+
+    fn spawn(work: CFuture<T>, token: CancelToken) -> Fiber<T> {
+        let handle = tokio::spawn(work.cancellable(&token).into_outcome());
+        let result = CFuture::new(handle.map(|res| match res {
+            Ok(Ok(t)) => Ok(t),
+            Ok(Err(Cancelled)) => Err(TaskError::Cancelled),
+            Err(err) => Err(TaskError::from(err)),
+        }));
+        Fiber { result, token }
+    }
+
+    /// Wait for the fiber to finish.  Resolves to `Err(TaskError::Cancelled)` if it was
+    /// cancelled, or to `Err(TaskError::Panicked)` if it panicked.
+    pub fn join(&self) -> CFuture<Result<T, TaskError>> {
+        self.result.clone()
+    }
+
+    /// Wait for the fiber to finish for at most `duration`.  The fiber keeps running
+    /// after a timeout; cancel it explicitly if its result is no longer wanted.
+    pub fn join_timeout(
+        &self,
+        duration: Duration,
+    ) -> CFuture<Result<Result<T, TaskError>, Elapsed>> {
+        self.result.timeout(duration)
+    }
+
+    /// Like `join_timeout`, with `duration` measured by `clock`.
+    pub fn join_timeout_on(
+        &self,
+        clock: &(impl Clock + ?Sized),
+        duration: Duration,
+    ) -> CFuture<Result<Result<T, TaskError>, Elapsed>> {
+        self.result.timeout_on(clock, duration)
+    }
+
+    /// Cancel the fiber, resolving once it stopped.  Cancelling a fiber which already
+    /// finished does nothing.
+    pub fn cancel(&self) -> CFuture<()> {
+        self.token.cancel();
+        fmap(self.result.clone(), |_| ())
+    }
+}
+
+struct Children {
+    token: CancelToken,
+    /// The children to wait for when the scope ends, or `None` once it ended
+    running: Mutex<Option<Vec<CFuture<()>>>>,
+}
+
+/// Ends a scope, including when it is dropped or cancelled before finishing.
+struct ScopeGuard(Arc<Children>);
+
+impl ScopeGuard {
+    /// Cancel the children and stop keeping track of new ones, returning those to wait
+    /// for.
+    fn close(&self) -> Vec<CFuture<()>> {
+        self.0.token.cancel();
+        self.0.running.lock().unwrap().take().unwrap_or_default()
+    }
+}
+
+impl Drop for ScopeGuard {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Owns the fibers started in a `Supervisor::scope`.
+///
+/// When one of the children fails (resolves to an `Err` or panics), the supervisor
+/// cancels all of its remaining children.  When the scope ends, whichever children are
+/// still running are cancelled, and the scope only resolves once they have all stopped,
+/// so that no child outlives it.
+///
+/// ```rust
+/// use rust_effects::prelude::*;
+/// use rust_effects::types::{cfuture::TaskError, fiber::Supervisor};
+/// use std::time::Duration;
+///
+/// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+/// let res = Supervisor::scope(|sup| {
+///     let slow = CFuture::new(async {
+///         tokio::time::sleep(Duration::from_secs(60)).await;
+///         Ok::<u32, String>(1)
+///     });
+///     let failing = CFuture::lazy(Err::<u32, _>("failed".to_string()));
+///     bind(sup.start(slow), move |slow| {
+///         bind(sup.start(failing.clone()), move |_| slow.join())
+///     })
+/// });
+/// // The failing child cancelled its slow sibling
+/// assert_eq!(res.await, Err(TaskError::Cancelled));
+/// # });
+/// ```
+#[derive(Clone)]
+pub struct Supervisor {
+    children: Arc<Children>,
+}
+
+impl Supervisor {
+    /// Run `body` with a new supervisor once the returned future is polled, cancelling
+    /// the children it started once the future returned by `body` resolves.
+    pub fn scope<T: Clone + Send + Sync + 'static>(
+        body: impl FnOnce(Supervisor) -> CFuture<T> + Send + 'static,
+    ) -> CFuture<T> {
+        CFuture::from_outcome(
+            async move {
+                let sup = Supervisor {
+                    children: Arc::new(Children {
+                        token: CancelToken::new(),
+                        running: Mutex::new(Some(vec![])),
+                    }),
+                };
+                let guard = ScopeGuard(sup.children.clone());
+                let res = body(sup).into_outcome().await;
+                let running = guard.close();
+                join_all(running.into_iter().map(CFuture::into_outcome)).await;
+                res
+            },
+            None,
+        )
+    }
+
+    /// Start running `work` in a new task owned by this supervisor, once the returned
+    /// future is polled.  Once the scope ended, children start out cancelled and are
+    /// no longer waited for.
+    ///
+    /// Must be polled from within a tokio runtime.
+    pub fn start<T, E>(&self, work: CFuture<Result<T, E>>) -> CFuture<Fiber<Result<T, E>>>
+    where
+        T: Clone + Send + Sync + 'static,
+        E: Clone + Send + Sync + 'static,
+    {
+        let children = self.children.clone();
+        CFuture::new(async move {
+            let token = CancelToken::new();
+            let child = token.clone();
+            let registration = children.token.on_cancel(move || child.cancel());
+            let fiber = Fiber::spawn(work, token);
+
+            let siblings = children.token.clone();
+            let watch = fmap(fiber.join(), move |res| {
+                registration.remove();
+                if matches!(res, Ok(Err(_)) | Err(TaskError::Panicked(_))) {
+                    siblings.cancel();
+                }
+            });
+            // Watched in its own task, so that failures are noticed without a join
+            tokio::spawn(watch.clone());
+            if let Some(running) = children.running.lock().unwrap().as_mut() {
+                running.push(watch);
+            }
+            fiber
+        })
+    }
+
+    /// Cancel all the children started so far, and any started from now on.
+    pub fn cancel(&self) {
+        self.children.token.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.children.token.is_cancelled()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use tokio::time::Instant;
+
+    fn delayed<A: Clone + Send + Sync + 'static>(millis: u64, val: A) -> CFuture<A> {
+        CFuture::new(async move {
+            tokio::time::sleep(Duration::from_millis(millis)).await;
+            val
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_fiber_join() {
+        let start = Instant::now();
+        let res = bind(Fiber::start(delayed(100, 3u32)), |a| {
+            bind(Fiber::start(delayed(100, 4u32)), move |b| {
+                let a = a.clone();
+                bind(b.join(), move |b| {
+                    fmap(a.join(), move |a| a.unwrap() + b.clone().unwrap())
+                })
+            })
+        });
+        assert_eq!(res.await, 7);
+        assert_eq!(start.elapsed(), Duration::from_millis(100));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_fiber_runs_without_join() {
+        let count = Arc::new(AtomicU32::new(0));
+        let c = count.clone();
+        let work = CFuture::new(async move {
+            c.fetch_add(1, Ordering::SeqCst);
+        });
+        let fiber = Fiber::start(work).await;
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        assert_eq!(fiber.join().await, Ok(()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_fiber_cancel() {
+        let start = Instant::now();
+        let fiber = Fiber::start(delayed(100, 3u32)).await;
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        fiber.cancel().await;
+        assert_eq!(start.elapsed(), Duration::from_millis(10));
+        assert_eq!(fiber.join().await, Err(TaskError::Cancelled));
+
+        let fiber = Fiber::start(delayed(10, 3u32)).await;
+        assert_eq!(fiber.join().await, Ok(3));
+        fiber.cancel().await;
+        assert_eq!(fiber.join().await, Ok(3));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_fiber_join_timeout() {
+        let fiber = Fiber::start(delayed(100, 3u32)).await;
+        assert_eq!(
+            fiber.join_timeout(Duration::from_millis(50)).await,
+            Err(Elapsed(Duration::from_millis(50)))
+        );
+        // Still running after the timeout
+        assert_eq!(
+            fiber.join_timeout(Duration::from_millis(100)).await,
+            Ok(Ok(3))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fiber_panic() {
+        let fiber = Fiber::start(CFuture::<u32>::new(async { panic!("boom") }));
+        let res = bind(fiber, |fiber| fiber.join());
+        assert_eq!(res.await, Err(TaskError::Panicked("boom".to_string())));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_scope_cancels_children_at_end() {
+        let count = Arc::new(AtomicU32::new(0));
+        let start = Instant::now();
+        let c = count.clone();
+        let res = Supervisor::scope(move |sup| {
+            let slow = fmap(delayed(1000, ()), move |_| {
+                c.fetch_add(1, Ordering::SeqCst);
+                Ok::<_, String>(())
+            });
+            let sup2 = sup.clone();
+            bind(sup.start(slow), move |_| {
+                bind(sup2.start(delayed(10, Ok::<_, String>(2u32))), |fast| {
+                    fmap(fast.join(), |res| res.unwrap().unwrap())
+                })
+            })
+        });
+        assert_eq!(res.await, 2);
+        assert_eq!(start.elapsed(), Duration::from_millis(10));
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failing_child_cancels_siblings() {
+        let start = Instant::now();
+        let res = Supervisor::scope(|sup| {
+            let sup2 = sup.clone();
+            bind(
+                sup.start(delayed(1000, Ok::<u32, String>(1))),
+                move |slow| {
+                    let failing = delayed(10, Err::<u32, _>("failed".to_string()));
+                    let sup = sup2.clone();
+                    bind(sup2.start(failing), move |_| {
+                        assert!(!sup.is_cancelled());
+                        slow.join()
+                    })
+                },
+            )
+        });
+        assert_eq!(res.await, Err(TaskError::Cancelled));
+        assert_eq!(start.elapsed(), Duration::from_millis(10));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancelled_child_keeps_siblings() {
+        let res = Supervisor::scope(|sup| {
+            let sup2 = sup.clone();
+            bind(sup.start(delayed(100, Ok::<u32, String>(1))), move |a| {
+                bind(sup2.start(delayed(100, Ok::<u32, String>(2))), move |b| {
+                    let b = b.clone();
+                    bind(a.cancel(), move |_| b.join())
+                })
+            })
+        });
+        assert_eq!(res.await, Ok(Ok(2)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancelled_scope() {
+        let token = CancelToken::new();
+        let count = Arc::new(AtomicU32::new(0));
+        let c = count.clone();
+        let res = Supervisor::scope(move |sup| {
+            let work = fmap(delayed(100, ()), move |_| {
+                c.fetch_add(1, Ordering::SeqCst);
+                Ok::<_, String>(())
+            });
+            bind(sup.start(work), |fiber| fiber.join())
+        })
+        .cancellable(&token);
+        let waiting = tokio::spawn(res.outcome());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        token.cancel();
+        assert!(waiting.await.unwrap().is_err());
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_start_when_polled() {
+        let count = Arc::new(AtomicU32::new(0));
+        let (c1, c2) = (count.clone(), count.clone());
+        let work = CFuture::new(async move {
+            c1.fetch_add(1, Ordering::SeqCst);
+        });
+        let fiber = Fiber::start(work);
+        let scope = Supervisor::scope(move |_| {
+            c2.fetch_add(10, Ordering::SeqCst);
+            CFuture::lazy(())
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+
+        fiber.await.join().await.unwrap();
+        scope.await;
+        assert_eq!(count.load(Ordering::SeqCst), 11);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_children_started_after_scope() {
+        let escaped = Arc::new(Mutex::new(None));
+        let e = escaped.clone();
+        let res = Supervisor::scope(move |sup| {
+            *e.lock().unwrap() = Some(sup);
+            CFuture::lazy(1u32)
+        });
+        assert_eq!(res.await, 1);
+
+        let sup = escaped.lock().unwrap().take().unwrap();
+        let fiber = sup.start(delayed(100, Ok::<u32, String>(2))).await;
+        assert_eq!(fiber.join().await, Err(TaskError::Cancelled));
+        assert!(sup.children.running.lock().unwrap().is_none());
+    }
+}
@@ -0,0 +1,622 @@
+use crate::prelude::typeclasses::*;
+use crate::typeclasses::free_effect::trace::Traceable;
+use crate::types::executor::block_on;
+use futures::future::{BoxFuture, FutureExt};
+use std::{
+    any::Any,
+    fmt::{Display, Formatter},
+    panic::{AssertUnwindSafe, catch_unwind},
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, ThreadId},
+};
+
+/// A panic caught while running an `IO`.
+///
+/// Panics are reported through the `IO`'s error channel, so running an `IO` requires its
+/// error type to be convertible from `Panicked`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Panicked(pub String);
+
+impl Display for Panicked {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "panicked: {}", self.0)
+    }
+}
+
+impl std::error::Error for Panicked {}
+
+impl From<Panicked> for String {
+    fn from(panic: Panicked) -> String {
+        panic.to_string()
+    }
+}
+
+/// The message of a caught panic, if it was a string.
+pub(crate) fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(msg) => *msg,
+        Err(payload) => payload
+            .downcast_ref::<&str>()
+            .map_or("<non-string panic>".to_string(), |msg| msg.to_string()),
+    }
+}
+
+type Thunk<T, E> = Arc<dyn Fn() -> BoxFuture<'static, Result<T, E>> + Send + Sync>;
+
+/// Make a `Send` closure shareable, for the typeclass functions which don't require `Sync`.
+///
+/// Calls from different threads take turns, but a call made on the thread already
+/// running the function (a function running the `IO` it is part of, for instance) goes
+/// ahead instead of waiting for itself.
+pub(crate) fn sync_fn<A, B>(func: impl Fn(A) -> B + Send) -> impl Fn(A) -> B + Send + Sync {
+    let func = Reentrant {
+        func,
+        owner: Mutex::new(None),
+        released: Condvar::new(),
+    };
+    move |a| func.call(a)
+}
+
+/// A function which one thread at a time calls, possibly re-entering it.
+struct Reentrant<F> {
+    func: F,
+    /// The thread calling the function, and how many of its calls are running
+    owner: Mutex<Option<(ThreadId, usize)>>,
+    released: Condvar,
+}
+
+// SAFETY: `func` is only used by the thread recorded in `owner`, so it is never used by
+// two threads at once, as with a `Mutex`.
+unsafe impl<F: Send> Sync for Reentrant<F> {}
+
+impl<F> Reentrant<F> {
+    fn call<A, B>(&self, a: A) -> B
+    where
+        F: Fn(A) -> B,
+    {
+        let me = thread::current().id();
+        let mut owner = self.owner.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            match &mut *owner {
+                Some((id, depth)) if *id == me => *depth += 1,
+                Some(_) => {
+                    owner = self.released.wait(owner).unwrap_or_else(|e| e.into_inner());
+                    continue;
+                }
+                None => *owner = Some((me, 1)),
+            }
+            break;
+        }
+        drop(owner);
+        let _turn = Turn(self);
+        (self.func)(a)
+    }
+}
+
+/// Ends a call of a `Reentrant` function, even if it panicked.
+struct Turn<'a, F>(&'a Reentrant<F>);
+
+impl<F> Drop for Turn<'_, F> {
+    fn drop(&mut self) {
+        let mut owner = self.0.owner.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((_, depth)) = &mut *owner {
+            *depth -= 1;
+            if *depth == 0 {
+                *owner = None;
+                self.0.released.notify_one();
+            }
+        }
+    }
+}
+
+/// A description of a side effect producing a `T` or failing with an `E`.
+///
+/// Unlike `CFuture`, building an `IO` runs nothing and nothing is memoised: every time an
+/// `IO` is run (with `run_sync` or `run_async`), all of its effects are executed again.
+/// Panics raised while running are caught and turned into errors with `E::from(Panicked)`.
+///
+/// Running an `IO` isn't stack safe: each `and_then` (or `map`, `bind`, ...) it was built
+/// with adds stack frames, and a chain nested a few thousand levels deep can overflow a
+/// thread's default 2 MiB stack.  Long loops are better written inside a single step,
+/// such as `from_future` with an `async` loop.
+///
+/// Functions given to `fmap` and `bind` don't need to be `Sync`, so runs of the same
+/// `IO` on different threads take turns calling them.  Functions given to `map` and
+/// `and_then`, which are `Sync`, run concurrently.
+///
+/// ```rust
+/// use rust_effects::prelude::*;
+/// use rust_effects::types::io::IO;
+/// use std::sync::{Arc, atomic::{AtomicU32, Ordering}};
+///
+/// let count = Arc::new(AtomicU32::new(0));
+/// let c = count.clone();
+/// let incr = IO::<u32, String>::delay(move || c.fetch_add(1, Ordering::SeqCst) + 1);
+/// let twice = bind(incr.clone(), move |a| fmap(incr.clone(), move |b| a + b));
+///
+/// assert_eq!(count.load(Ordering::SeqCst), 0);
+/// assert_eq!(twice.run_sync(), Ok(3));
+/// assert_eq!(twice.run_sync(), Ok(7));
+/// ```
+pub struct IO<T, E> {
+    thunk: Thunk<T, E>,
+}
+
+impl<T, E> Clone for IO<T, E> {
+    fn clone(&self) -> Self {
+        IO {
+            thunk: self.thunk.clone(),
+        }
+    }
+}
+
+impl<T: Send + 'static, E: Send + 'static> IO<T, E> {
+    fn from_thunk(
+        thunk: impl Fn() -> BoxFuture<'static, Result<T, E>> + Send + Sync + 'static,
+    ) -> IO<T, E> {
+        IO {
+            thunk: Arc::new(thunk),
+        }
+    }
+
+    pub fn pure(t: T) -> IO<T, E>
+    where
+        T: Clone + Sync,
+    {
+        IO::from_thunk(move || futures::future::ready(Ok(t.clone())).boxed())
+    }
+
+    pub fn raise_error(e: E) -> IO<T, E>
+    where
+        E: Clone + Sync,
+    {
+        IO::from_thunk(move || futures::future::ready(Err(e.clone())).boxed())
+    }
+
+    /// Run `func` every time the `IO` runs.
+    pub fn delay(func: impl Fn() -> T + Send + Sync + 'static) -> IO<T, E> {
+        IO::from_thunk(move || futures::future::ready(Ok(func())).boxed())
+    }
+
+    /// Like `delay`, for side effects which can fail.
+    pub fn try_delay(func: impl Fn() -> Result<T, E> + Send + Sync + 'static) -> IO<T, E> {
+        IO::from_thunk(move || futures::future::ready(func()).boxed())
+    }
+
+    /// Build the `IO` to run with `func` every time it runs.
+    pub fn suspend(func: impl Fn() -> IO<T, E> + Send + Sync + 'static) -> IO<T, E> {
+        IO::from_thunk(move || (func().thunk)())
+    }
+
+    /// Create and await a new future from `func` every time the `IO` runs.
+    pub fn from_future<Fut>(func: impl Fn() -> Fut + Send + Sync + 'static) -> IO<T, E>
+    where
+        Fut: Future<Output = Result<T, E>> + Send + 'static,
+    {
+        IO::from_thunk(move || func().boxed())
+    }
+
+    pub fn map<U: Send + 'static>(
+        &self,
+        func: impl Fn(T) -> U + Send + Sync + 'static,
+    ) -> IO<U, E> {
+        let (thunk, func) = (self.thunk.clone(), Arc::new(func));
+        IO::from_thunk(move || {
+            let func = func.clone();
+            thunk().map(move |res| res.map(|t| func(t))).boxed()
+        })
+    }
+
+    pub fn map_err<E2: Send + 'static>(
+        &self,
+        func: impl Fn(E) -> E2 + Send + Sync + 'static,
+    ) -> IO<T, E2> {
+        let (thunk, func) = (self.thunk.clone(), Arc::new(func));
+        IO::from_thunk(move || {
+            let func = func.clone();
+            thunk().map(move |res| res.map_err(|e| func(e))).boxed()
+        })
+    }
+
+    /// Run the `IO` returned by `func` with this one's result.  See the type
+    /// documentation for how deep chains of `and_then` can get.
+    pub fn and_then<U: Send + 'static>(
+        &self,
+        func: impl Fn(T) -> IO<U, E> + Send + Sync + 'static,
+    ) -> IO<U, E> {
+        let (thunk, func) = (self.thunk.clone(), Arc::new(func));
+        IO::from_thunk(move || {
+            let (fut, func) = (thunk(), func.clone());
+            async move {
+                let t = fut.await?;
+                (func(t).thunk)().await
+            }
+            .boxed()
+        })
+    }
+
+    /// Run this `IO`, catching panics into the error channel.
+    pub fn run_async(&self) -> impl Future<Output = Result<T, E>> + Send + 'static
+    where
+        E: From<Panicked>,
+    {
+        let thunk = self.thunk.clone();
+        async move {
+            let fut = catch_unwind(AssertUnwindSafe(|| thunk()))
+                .map_err(|payload| E::from(Panicked(panic_message(payload))))?;
+            AssertUnwindSafe(fut)
+                .catch_unwind()
+                .await
+                .unwrap_or_else(|payload| Err(E::from(Panicked(panic_message(payload)))))
+        }
+    }
+
+    /// Run this `IO` on the current thread with the built-in executor.
+    pub fn run_sync(&self) -> Result<T, E>
+    where
+        E: From<Panicked>,
+    {
+        block_on(self.run_async())
+    }
+
+    /// An `IO` which never fails, exposing the result (or caught panic) as a value.
+    pub fn attempt<E2: Send + 'static>(&self) -> IO<Result<T, E>, E2>
+    where
+        E: From<Panicked>,
+    {
+        let io = self.clone();
+        IO::from_thunk(move || io.run_async().map(Ok).boxed())
+    }
+
+    /// Recover from errors (and caught panics) with the `IO` returned by `func`.
+    pub fn handle_error_with(
+        &self,
+        func: impl Fn(E) -> IO<T, E> + Send + Sync + 'static,
+    ) -> IO<T, E>
+    where
+        E: From<Panicked>,
+    {
+        let (io, func) = (self.clone(), Arc::new(func));
+        IO::from_thunk(move || {
+            let (fut, func) = (io.run_async(), func.clone());
+            async move {
+                match fut.await {
+                    Ok(t) => Ok(t),
+                    Err(e) => (func(e).thunk)().await,
+                }
+            }
+            .boxed()
+        })
+    }
+
+    /// Acquire a resource with this `IO`, use it, then always release it, whether `use_`
+    /// succeeded, failed or panicked.  The error of `use_` takes precedence over the one
+    /// of `release`.
+    pub fn bracket<U: Send + 'static>(
+        &self,
+        use_: impl Fn(T) -> IO<U, E> + Send + Sync + 'static,
+        release: impl Fn(T) -> IO<(), E> + Send + Sync + 'static,
+    ) -> IO<U, E>
+    where
+        T: Clone,
+        E: From<Panicked>,
+    {
+        let (acquire, use_, release) = (self.clone(), Arc::new(use_), Arc::new(release));
+        IO::from_thunk(move || {
+            let (acquire, use_, release) = (acquire.run_async(), use_.clone(), release.clone());
+            async move {
+                let resource = acquire.await?;
+                let used = {
+                    let resource = resource.clone();
+                    run_built(move || use_(resource)).await
+                };
+                let released = run_built(move || release(resource)).await;
+                let u = used?;
+                released.map(|_| u)
+            }
+            .boxed()
+        })
+    }
+}
+
+/// Build an `IO` and run it, catching panics from building it as well.
+pub(crate) async fn run_built<T, E>(build: impl FnOnce() -> IO<T, E>) -> Result<T, E>
+where
+    T: Send + 'static,
+    E: From<Panicked> + Send + 'static,
+{
+    match catch_unwind(AssertUnwindSafe(build)) {
+        Ok(io) => io.run_async().await,
+        Err(payload) => Err(E::from(Panicked(panic_message(payload)))),
+    }
+}
+
+impl<A, E> Semigroup for IO<A, E>
+where
+    A: Semigroup + Send + 'static,
+    E: Send + 'static,
+{
+    fn combine(a: Self, b: Self) -> Self {
+        a.and_then(move |a_res| {
+            let a_res = Mutex::new(Some(a_res));
+            b.map(move |b_res| A::combine(a_res.lock().unwrap().take().unwrap(), b_res))
+        })
+    }
+    fn combine_m(a: Self, b: Self) -> Self {
+        a.and_then(move |a_res| {
+            let a_res = Mutex::new(Some(a_res));
+            b.map(move |b_res| A::combine_m(a_res.lock().unwrap().take().unwrap(), b_res))
+        })
+    }
+}
+
+impl<A, E> Monoid for IO<A, E>
+where
+    A: Monoid + Clone + Send + Sync + 'static,
+    E: Send + 'static,
+{
+    fn empty() -> Self {
+        IO::pure(A::empty())
+    }
+    fn empty_m() -> Self {
+        IO::pure(A::empty_m())
+    }
+}
+
+impl<T, U, E> Functor<U> for IO<T, E>
+where
+    T: Send + 'static,
+    U: Send + 'static,
+    E: Send + 'static,
+{
+    type FuncT = T;
+    type FunctorOut = IO<U, E>;
+    fn fmap(m: Self, func: impl Fn(T) -> U + Send + 'static) -> Self::FunctorOut {
+        m.map(sync_fn(func))
+    }
+}
+
+impl<T, U, E> Applicative<U> for IO<T, E>
+where
+    T: Clone + Send + Sync + 'static,
+    U: Send + 'static,
+    E: Send + 'static,
+{
+    type AppT = T;
+    fn pure(a: T) -> Self {
+        IO::pure(a)
+    }
+}
+
+impl<F, T, U, E> ApplicativeFunctor<F, U> for IO<T, E>
+where
+    F: Fn(T) -> U + Send + Sync + 'static,
+    T: Clone + Send + Sync + 'static,
+    U: Clone + Send + Sync + 'static,
+    E: Send + 'static,
+{
+    type AppFuncT = T;
+    type AppFuncOut = IO<U, E>;
+    type AppFuncFn = IO<F, E>;
+    fn seq(m: Self, func: Self::AppFuncFn) -> Self::AppFuncOut {
+        func.and_then(move |f| m.map(f))
+    }
+}
+
+impl<T, U, E> Monad<U> for IO<T, E>
+where
+    T: Clone + Send + Sync + 'static,
+    U: Clone + Send + Sync + 'static,
+    E: Send + 'static,
+{
+    type MonadT = T;
+    type MonadOut = IO<U, E>;
+    fn bind(m: Self, func: impl Fn(T) -> Self::MonadOut + Send + 'static) -> Self::MonadOut {
+        m.and_then(sync_fn(func))
+    }
+}
+
+impl<T, E> Traceable for IO<T, E> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn counter() -> (Arc<AtomicU32>, IO<u32, String>) {
+        let count = Arc::new(AtomicU32::new(0));
+        let c = count.clone();
+        (
+            count,
+            IO::delay(move || c.fetch_add(1, Ordering::SeqCst) + 1),
+        )
+    }
+
+    fn log(entries: &Arc<Mutex<Vec<String>>>, entry: &str) -> IO<(), String> {
+        let (entries, entry) = (entries.clone(), entry.to_string());
+        IO::delay(move || entries.lock().unwrap().push(entry.clone()))
+    }
+
+    #[test]
+    fn test_rerun_effects() {
+        let (count, io) = counter();
+        let io = fmap(io, |a| a * 10);
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+        assert_eq!(io.run_sync(), Ok(10));
+        assert_eq!(io.run_sync(), Ok(20));
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_suspend() {
+        let (count, io) = counter();
+        let built = Arc::new(AtomicU32::new(0));
+        let b = built.clone();
+        let suspended = IO::suspend(move || {
+            b.fetch_add(1, Ordering::SeqCst);
+            io.clone()
+        });
+        assert_eq!(built.load(Ordering::SeqCst), 0);
+        assert_eq!(suspended.run_sync(), Ok(1));
+        assert_eq!(suspended.run_sync(), Ok(2));
+        assert_eq!(built.load(Ordering::SeqCst), 2);
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_typeclasses_io() {
+        let (_, io) = counter();
+        let add = |a: u32| move |b: u32| a + b;
+        assert_eq!(seq(io.clone(), fmap(io.clone(), add)).run_sync(), Ok(3));
+        assert_eq!(
+            bind(io.clone(), |a| pure::<IO<_, _>>(a * 2)).run_sync(),
+            Ok(6)
+        );
+        let sum = lift_m2::<IO<u32, String>, _, _>(|a: u32, b: u32| a + b);
+        assert_eq!(sum(io.clone(), io.clone()).run_sync(), Ok(9));
+        assert_eq!(combine(io.clone(), IO::empty()).run_sync(), Ok(6));
+    }
+
+    #[test]
+    fn test_errors_io() {
+        let failing = IO::<u32, String>::raise_error("bad".to_string());
+        let (count, io) = counter();
+        let chained = bind(failing.clone(), move |_| io.clone());
+        assert_eq!(chained.run_sync(), Err("bad".to_string()));
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+
+        assert_eq!(
+            failing.attempt::<String>().run_sync(),
+            Ok(Err("bad".to_string()))
+        );
+        let recovered = failing.handle_error_with(|e| IO::pure(e.len() as u32));
+        assert_eq!(recovered.run_sync(), Ok(3));
+        assert_eq!(
+            failing.map_err(|e| e.to_uppercase()).run_sync(),
+            Err("BAD".to_string())
+        );
+        assert_eq!(
+            IO::<u32, String>::try_delay(|| "7".parse().map_err(|_| "nan".to_string())).run_sync(),
+            Ok(7)
+        );
+    }
+
+    #[test]
+    fn test_panic_capture() {
+        let io = IO::<u32, String>::delay(|| panic!("boom"));
+        assert_eq!(io.run_sync(), Err("panicked: boom".to_string()));
+        let io = fmap(
+            IO::<u32, Box<dyn std::error::Error + Send + Sync>>::pure(3),
+            |_| -> u32 { panic!("in fmap") },
+        );
+        assert_eq!(io.run_sync().unwrap_err().to_string(), "panicked: in fmap");
+
+        let recovered =
+            IO::<u32, String>::delay(|| panic!("boom")).handle_error_with(|_| IO::pure(0));
+        assert_eq!(recovered.run_sync(), Ok(0));
+    }
+
+    #[test]
+    fn test_bracket() {
+        let entries = Arc::new(Mutex::new(vec![]));
+        let acquire = bind(log(&entries, "acquire"), |_| IO::pure(42u32));
+
+        let (e_use, e_rel) = (entries.clone(), entries.clone());
+        let ok = acquire.bracket(
+            move |r| fmap(log(&e_use, "use"), move |_| r + 1),
+            move |_| log(&e_rel, "release"),
+        );
+        assert_eq!(ok.run_sync(), Ok(43));
+        assert_eq!(ok.run_sync(), Ok(43));
+
+        let e_rel = entries.clone();
+        let failed = acquire.bracket(
+            |_| IO::<u32, String>::raise_error("failed".to_string()),
+            move |_| log(&e_rel, "release after error"),
+        );
+        assert_eq!(failed.run_sync(), Err("failed".to_string()));
+
+        let e_rel = entries.clone();
+        let panicked = acquire.bracket(
+            |_| -> IO<u32, String> { panic!("use panicked") },
+            move |_| log(&e_rel, "release after panic"),
+        );
+        assert_eq!(
+            panicked.run_sync(),
+            Err("panicked: use panicked".to_string())
+        );
+
+        assert_eq!(
+            *entries.lock().unwrap(),
+            vec![
+                "acquire",
+                "use",
+                "release",
+                "acquire",
+                "use",
+                "release",
+                "acquire",
+                "release after error",
+                "acquire",
+                "release after panic"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_concurrent_runs() {
+        // Both runs are inside the mapped function at once
+        let barrier = Arc::new(std::sync::Barrier::new(2));
+        let b = barrier.clone();
+        let io = IO::<u32, String>::pure(1).map(move |a| {
+            b.wait();
+            a + 1
+        });
+        let other = io.clone();
+        let handle = std::thread::spawn(move || other.run_sync());
+        assert_eq!(io.run_sync(), Ok(2));
+        assert_eq!(handle.join().unwrap(), Ok(2));
+    }
+
+    #[test]
+    fn test_reentrant_function() {
+        // Not `Sync`, and runs the `IO` it belongs to again from inside
+        let depth = std::cell::Cell::new(0u32);
+        let this = Arc::new(std::sync::OnceLock::<IO<u32, String>>::new());
+        let t = this.clone();
+        let io = fmap(IO::<(), String>::pure(()), move |_| {
+            depth.set(depth.get() + 1);
+            match depth.get() {
+                1..4 => t.get().unwrap().run_sync().unwrap() + 1,
+                _ => 0,
+            }
+        });
+        this.set(io.clone()).ok().unwrap();
+        assert_eq!(io.run_sync(), Ok(3));
+
+        // Runs from other threads take turns
+        let io = fmap(IO::<u32, String>::pure(1), |a| a + 1);
+        let runs: Vec<_> = (0..4)
+            .map(|_| {
+                let io = io.clone();
+                std::thread::spawn(move || io.run_sync())
+            })
+            .collect();
+        assert!(runs.into_iter().all(|run| run.join().unwrap() == Ok(2)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_from_future() {
+        let (count, io) = counter();
+        let slow = IO::from_future(move || {
+            let io = io.clone();
+            async move {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                io.run_async().await
+            }
+        });
+        assert_eq!(slow.run_async().await, Ok(1));
+        assert_eq!(slow.run_async().await, Ok(2));
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+}
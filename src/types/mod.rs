@@ -1,9 +1,37 @@
+#[cfg(feature = "cfuture")]
+pub mod cancel;
+#[cfg(feature = "cfuture")]
 pub mod cfuture;
+#[cfg(feature = "cfuture")]
+pub mod clock;
+#[cfg(feature = "tokio")]
+pub mod concurrent;
+#[cfg(feature = "cfuture")]
+pub mod cstream;
+pub mod eff;
+#[cfg(feature = "cfuture")]
+pub mod executor;
+#[cfg(feature = "tokio")]
+pub mod fiber;
+#[cfg(feature = "cfuture")]
+pub mod io;
+#[cfg(feature = "cfuture")]
+pub mod once_future;
 pub mod option;
+#[cfg(feature = "tokio")]
+pub mod resilience;
+#[cfg(feature = "cfuture")]
+pub mod resource;
 pub mod result;
+pub(crate) mod rng;
+pub mod schedule;
+#[cfg(feature = "cfuture")]
+pub mod stm;
 pub mod vec;
 
-use crate::typeclasses::{applicative::Applicative, functor::Functor, monad::Monad};
+use crate::typeclasses::{
+    applicative::Applicative, free_effect::trace::Traceable, functor::Functor, monad::Monad,
+};
 
 impl<U> Functor<U> for () {
     type FuncT = ();
@@ -14,9 +42,7 @@ impl<U> Functor<U> for () {
 }
 impl<U> Applicative<U> for () {
     type AppT = ();
-    fn pure(_a: ()) -> Self {
-        ()
-    }
+    fn pure(_a: ()) -> Self {}
 }
 
 impl Monad for () {
@@ -29,3 +55,5 @@ impl Monad for () {
         m
     }
 }
+
+impl Traceable for () {}
@@ -0,0 +1,202 @@
+use crate::prelude::typeclasses::*;
+use crate::typeclasses::free_effect::trace::Traceable;
+use crate::types::cfuture::CFuture;
+use futures::future::{BoxFuture, FutureExt, ready};
+
+/// A cold, single-consumer future.
+///
+/// Unlike `CFuture`, a `OnceFuture` is not shared: it can only be awaited once and can't be
+/// cloned.  In exchange, its payload only needs to be `Send`, so it can carry values such as
+/// files, channel receivers or large buffers which are not `Clone` or `Sync`.  Nothing runs
+/// until the future is awaited.
+///
+/// When the result does need to be shared, convert it into a `CFuture` with `into_shared`
+/// (or `CFuture::from`).
+pub struct OnceFuture<A> {
+    inner: BoxFuture<'static, A>,
+}
+
+impl<A: Send + 'static> OnceFuture<A> {
+    pub fn lazy(val: A) -> OnceFuture<A> {
+        OnceFuture::new(ready(val))
+    }
+
+    pub fn new(fut: impl Future<Output = A> + Send + 'static) -> OnceFuture<A> {
+        OnceFuture { inner: fut.boxed() }
+    }
+
+    /// Turn this future into a shared `CFuture`, which requires a cloneable payload.
+    pub fn into_shared(self) -> CFuture<A>
+    where
+        A: Clone + Sync,
+    {
+        CFuture::new(self.inner)
+    }
+}
+
+impl<A> Future for OnceFuture<A> {
+    type Output = A;
+    fn poll(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Self::Output> {
+        self.inner.poll_unpin(cx)
+    }
+}
+
+impl<A> From<OnceFuture<A>> for CFuture<A>
+where
+    A: Clone + Send + Sync + 'static,
+{
+    fn from(fut: OnceFuture<A>) -> CFuture<A> {
+        fut.into_shared()
+    }
+}
+
+impl<A> Monoid for OnceFuture<A>
+where
+    A: Monoid + Send + 'static,
+{
+    fn empty() -> Self {
+        OnceFuture::lazy(A::empty())
+    }
+    fn empty_m() -> Self {
+        OnceFuture::lazy(A::empty_m())
+    }
+}
+
+impl<A> Semigroup for OnceFuture<A>
+where
+    A: Semigroup + Send + 'static,
+{
+    fn combine(a: Self, b: Self) -> Self {
+        OnceFuture::new(async move { A::combine(a.await, b.await) })
+    }
+    fn combine_m(a: Self, b: Self) -> Self {
+        OnceFuture::new(async move { A::combine_m(a.await, b.await) })
+    }
+}
+
+impl<T, U> Functor<U> for OnceFuture<T>
+where
+    T: Send + 'static,
+    U: Send + 'static,
+{
+    type FuncT = T;
+    type FunctorOut = OnceFuture<U>;
+    fn fmap(m: Self, func: impl FnOnce(T) -> U + Send + 'static) -> Self::FunctorOut {
+        OnceFuture::new(m.inner.map(func))
+    }
+}
+
+impl<T, U> Applicative<U> for OnceFuture<T>
+where
+    T: Send + 'static,
+    U: Send + 'static,
+{
+    type AppT = T;
+    fn pure(a: T) -> Self {
+        OnceFuture::lazy(a)
+    }
+}
+
+impl<F, T, U> ApplicativeFunctor<F, U> for OnceFuture<T>
+where
+    F: Fn(T) -> U + Send + 'static,
+    T: Send + 'static,
+    U: Send + 'static,
+{
+    type AppFuncT = T;
+    type AppFuncOut = OnceFuture<U>;
+    type AppFuncFn = OnceFuture<F>;
+    fn seq(m: Self, func: Self::AppFuncFn) -> Self::AppFuncOut {
+        OnceFuture::new(async move {
+            let f = func.await;
+            let t = m.await;
+            f(t)
+        })
+    }
+}
+
+impl<T, U> Monad<U> for OnceFuture<T>
+where
+    T: Send + 'static,
+    U: Send + 'static,
+{
+    type MonadT = T;
+    type MonadOut = OnceFuture<U>;
+    fn bind(m: Self, func: impl FnOnce(T) -> Self::MonadOut + Send + 'static) -> Self::MonadOut {
+        OnceFuture::new(m.inner.then(func))
+    }
+}
+
+impl<A> Traceable for OnceFuture<A> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc;
+
+    // Deliberately neither Clone nor Sync
+    #[derive(Debug, PartialEq)]
+    struct Buffer(Vec<u8>, std::cell::Cell<usize>);
+
+    fn buffer(len: usize) -> Buffer {
+        Buffer(vec![0; len], std::cell::Cell::new(0))
+    }
+
+    #[tokio::test]
+    async fn test_pure_once() {
+        assert_eq!(pure::<OnceFuture<_>>(buffer(3)).await, buffer(3));
+    }
+
+    #[tokio::test]
+    async fn test_fmap_once() {
+        assert_eq!(fmap(OnceFuture::lazy(buffer(3)), |b| b.0.len()).await, 3);
+    }
+
+    #[tokio::test]
+    async fn test_seq_once() {
+        let func = OnceFuture::lazy(|b: Buffer| b.0.len() + 1);
+        assert_eq!(seq(OnceFuture::lazy(buffer(3)), func).await, 4);
+    }
+
+    #[tokio::test]
+    async fn test_bind_once() {
+        let (tx, rx) = mpsc::channel::<u32>();
+        let received = bind(OnceFuture::lazy(rx), |rx: mpsc::Receiver<u32>| {
+            OnceFuture::new(async move { rx.iter().sum::<u32>() })
+        });
+        tx.send(3).unwrap();
+        tx.send(4).unwrap();
+        drop(tx);
+        assert_eq!(received.await, 7);
+    }
+
+    #[tokio::test]
+    async fn test_combine_once() {
+        assert_eq!(combine(OnceFuture::lazy(3), OnceFuture::lazy(4)).await, 7);
+        assert_eq!(
+            combine(OnceFuture::lazy(3), OnceFuture::<u32>::empty()).await,
+            3
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cold_once() {
+        let (tx, rx) = mpsc::channel();
+        let fut = OnceFuture::new(async move { tx.send(1).unwrap() });
+        assert!(rx.try_recv().is_err());
+        fut.await;
+        assert_eq!(rx.try_recv(), Ok(1));
+    }
+
+    #[tokio::test]
+    async fn test_into_shared_once() {
+        let shared = fmap(OnceFuture::lazy(buffer(3)), |b| b.0.len()).into_shared();
+        let other = shared.clone();
+        assert_eq!(shared.await, 3);
+        assert_eq!(CFuture::from(OnceFuture::lazy(4)).await, 4);
+        assert_eq!(other.await, 3);
+    }
+}
@@ -1,4 +1,5 @@
 use crate::prelude::typeclasses::*;
+use crate::typeclasses::free_effect::trace::Traceable;
 
 impl<A: Monoid> Monoid for Option<A> {
     fn empty() -> Self {
@@ -52,7 +53,7 @@ where
     type AppFuncOut = Option<U>;
     type AppFuncFn = Option<F>;
     fn seq(m: Self, func: Self::AppFuncFn) -> Self::AppFuncOut {
-        func.and_then(|f| m.map(|t| f(t)))
+        func.and_then(|f| m.map(f))
     }
 }
 
@@ -72,6 +73,12 @@ impl<T: Send, U: Send> Monad<U> for Option<T> {
     }
 }
 
+impl<A> Traceable for Option<A> {
+    fn is_empty_state(&self) -> Option<bool> {
+        Some(self.is_none())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -126,7 +133,7 @@ mod test {
     }
 
     fn empty_if_even<M: Monad<u32, MonadT = u32> + Monoid + Applicative<u32>>(input: String) -> M {
-        if input.len() % 2 == 0 {
+        if input.len().is_multiple_of(2) {
             M::empty()
         } else {
             M::pure(input.len() as u32)
@@ -0,0 +1,679 @@
+use crate::prelude::typeclasses::*;
+use crate::types::{
+    cfuture::CFuture,
+    clock::{Clock, SystemClock},
+};
+use std::{
+    fmt::{Display, Formatter},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+use tokio::sync::Semaphore;
+
+/// Why a protected call was turned down without running.
+///
+/// Protected functions report rejections through their own error type, so protecting a
+/// function requires its error type to be convertible from `Rejected`.  This keeps the
+/// signature of the function unchanged, so that protections can be stacked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rejected {
+    /// The `CircuitBreaker` is open
+    CircuitOpen,
+    /// The `RateLimiter` has no tokens left within its maximum wait
+    RateLimited,
+    /// The `Bulkhead` has too many calls waiting already
+    BulkheadFull,
+}
+
+impl Display for Rejected {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rejected::CircuitOpen => write!(f, "rejected: circuit breaker is open"),
+            Rejected::RateLimited => write!(f, "rejected: rate limit exceeded"),
+            Rejected::BulkheadFull => write!(f, "rejected: bulkhead is full"),
+        }
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+impl From<Rejected> for String {
+    fn from(rejected: Rejected) -> String {
+        rejected.to_string()
+    }
+}
+
+/// The state of a `CircuitBreaker`, as seen from outside.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BreakerState {
+    /// Calls go through, and consecutive failures are counted
+    Closed,
+    /// Calls are rejected until the reset timeout passes
+    Open,
+    /// Trial calls go through one at a time, to find out whether to close again
+    HalfOpen,
+}
+
+enum Breaker {
+    Closed {
+        failures: u32,
+    },
+    /// Open until the breaker's clock reaches `until`
+    Open {
+        until: Duration,
+    },
+    HalfOpen {
+        successes: u32,
+        trial: bool,
+    },
+}
+
+impl Breaker {
+    /// Move on to half-open once the reset timeout passed.
+    fn refresh(&mut self, now: Duration) {
+        if let Breaker::Open { until } = self
+            && now >= *until
+        {
+            *self = Breaker::HalfOpen {
+                successes: 0,
+                trial: false,
+            };
+        }
+    }
+}
+
+/// A call let through by a `CircuitBreaker`, which is recorded as unfinished if it is
+/// dropped (because it was cancelled or panicked) before `finish` is called.
+struct Admission {
+    breaker: CircuitBreaker,
+    is_trial: bool,
+}
+
+impl Admission {
+    fn finish(&mut self, success: bool, now: Duration) {
+        self.breaker.record(self.is_trial, success, now);
+        // Nothing is left to release
+        self.is_trial = false;
+    }
+}
+
+impl Drop for Admission {
+    fn drop(&mut self) {
+        if self.is_trial {
+            self.breaker.release_trial();
+        }
+    }
+}
+
+/// Stops calling a failing dependency for a while, to let it recover.
+///
+/// The breaker starts closed.  After `failure_threshold` consecutive failures it opens,
+/// and calls are rejected with `Rejected::CircuitOpen` for `reset_after`.  It then turns
+/// half-open: calls are let through one at a time as trials, and it closes again after
+/// `success_threshold` successful trials (1 by default), or opens again on a failure.
+///
+/// Clones of a breaker share its state.
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    state: Arc<Mutex<Breaker>>,
+    failure_threshold: u32,
+    success_threshold: u32,
+    reset_after: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, reset_after: Duration) -> CircuitBreaker {
+        CircuitBreaker {
+            state: Arc::new(Mutex::new(Breaker::Closed { failures: 0 })),
+            failure_threshold: failure_threshold.max(1),
+            success_threshold: 1,
+            reset_after,
+            clock: Arc::new(SystemClock::new()),
+        }
+    }
+
+    /// Measure the reset timeout with `clock` instead of the system clock.
+    pub fn with_clock(self, clock: impl Clock + 'static) -> CircuitBreaker {
+        CircuitBreaker {
+            clock: Arc::new(clock),
+            ..self
+        }
+    }
+
+    /// Require `n` successful trials while half-open before closing again.
+    pub fn with_success_threshold(self, n: u32) -> CircuitBreaker {
+        CircuitBreaker {
+            success_threshold: n.max(1),
+            ..self
+        }
+    }
+
+    pub fn state(&self) -> CFuture<BreakerState> {
+        let state = self.state.clone();
+        bind(self.clock.monotonic(), move |now| {
+            let mut state = state.lock().unwrap();
+            state.refresh(now);
+            CFuture::lazy(match *state {
+                Breaker::Closed { .. } => BreakerState::Closed,
+                Breaker::Open { .. } => BreakerState::Open,
+                Breaker::HalfOpen { .. } => BreakerState::HalfOpen,
+            })
+        })
+    }
+
+    /// Decide whether a call may go through, and whether it is a half-open trial.
+    fn admit(&self, now: Duration) -> Result<bool, Rejected> {
+        let mut state = self.state.lock().unwrap();
+        state.refresh(now);
+        match &mut *state {
+            Breaker::Closed { .. } => Ok(false),
+            Breaker::HalfOpen { trial, .. } if !*trial => {
+                *trial = true;
+                Ok(true)
+            }
+            _ => Err(Rejected::CircuitOpen),
+        }
+    }
+
+    /// Record how an admitted call went, at time `now`.
+    fn record(&self, is_trial: bool, success: bool, now: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let open = Breaker::Open {
+            until: now.saturating_add(self.reset_after),
+        };
+        match (&mut *state, success) {
+            (Breaker::HalfOpen { successes, trial }, true) if is_trial => {
+                *successes += 1;
+                *trial = false;
+                if *successes >= self.success_threshold {
+                    *state = Breaker::Closed { failures: 0 };
+                }
+            }
+            (Breaker::HalfOpen { .. }, false) if is_trial => *state = open,
+            (Breaker::Closed { failures }, true) => *failures = 0,
+            (Breaker::Closed { failures }, false) => {
+                *failures += 1;
+                if *failures >= self.failure_threshold {
+                    *state = open;
+                }
+            }
+            // Results of calls admitted before the breaker last changed state
+            _ => {}
+        }
+    }
+
+    /// Let another trial through, after one which didn't finish.
+    fn release_trial(&self) {
+        if let Breaker::HalfOpen { trial, .. } = &mut *self.state.lock().unwrap() {
+            *trial = false;
+        }
+    }
+
+    /// Protect `func` with this breaker.  Calls rejected by the breaker resolve to
+    /// `E::from(Rejected::CircuitOpen)` without calling `func`.
+    pub fn protect<A, B, E>(
+        &self,
+        func: impl Fn(A) -> CFuture<Result<B, E>> + Send + Sync + 'static,
+    ) -> impl Fn(A) -> CFuture<Result<B, E>> + Clone + Send + Sync + 'static
+    where
+        A: Send + 'static,
+        B: Clone + Send + Sync + 'static,
+        E: From<Rejected> + Clone + Send + Sync + 'static,
+    {
+        let breaker = self.clone();
+        let func = Arc::new(func);
+        move |a| {
+            let (breaker, func) = (breaker.clone(), func.clone());
+            CFuture::from_outcome(
+                async move {
+                    let clock = breaker.clock.clone();
+                    let now = clock.monotonic().into_outcome().await?;
+                    let mut admission = match breaker.admit(now) {
+                        Ok(is_trial) => Admission { breaker, is_trial },
+                        Err(rejected) => return Ok(Err(E::from(rejected))),
+                    };
+                    let res = func(a).into_outcome().await?;
+                    admission.finish(res.is_ok(), clock.monotonic().into_outcome().await?);
+                    Ok(res)
+                },
+                None,
+            )
+        }
+    }
+}
+
+/// Limits the rate of calls with a token bucket.
+///
+/// The bucket holds up to `capacity` tokens and gains one every `refill_every`.  Each
+/// call takes a token, waiting for the next one if the bucket is empty.  Calls which
+/// would have to wait longer than the maximum wait (none by default) are rejected with
+/// `Rejected::RateLimited` instead.
+///
+/// Clones of a limiter share its bucket.
+#[derive(Clone)]
+pub struct RateLimiter {
+    /// When the bucket will be full again on the limiter's clock, were no more tokens
+    /// taken
+    full_at: Arc<Mutex<Duration>>,
+    capacity: u32,
+    refill_every: Duration,
+    max_wait: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl RateLimiter {
+    pub fn new(capacity: u32, refill_every: Duration) -> RateLimiter {
+        RateLimiter {
+            full_at: Arc::new(Mutex::new(Duration::ZERO)),
+            capacity: capacity.max(1),
+            refill_every,
+            max_wait: Duration::ZERO,
+            clock: Arc::new(SystemClock::new()),
+        }
+    }
+
+    /// Let calls wait up to `max_wait` for a token before rejecting them.
+    pub fn with_max_wait(self, max_wait: Duration) -> RateLimiter {
+        RateLimiter { max_wait, ..self }
+    }
+
+    /// Refill the bucket and wait for tokens using `clock` instead of the system clock.
+    /// This starts over with a full bucket.
+    pub fn with_clock(self, clock: impl Clock + 'static) -> RateLimiter {
+        RateLimiter {
+            full_at: Arc::new(Mutex::new(Duration::ZERO)),
+            clock: Arc::new(clock),
+            ..self
+        }
+    }
+
+    /// Take a token at time `now`, returning how long to wait until it is available.
+    fn reserve(&self, now: Duration) -> Result<Duration, Rejected> {
+        let mut full_at = self.full_at.lock().unwrap();
+        // Saturates: a bucket taking that long to refill is as good as never empty
+        let burst = self
+            .refill_every
+            .checked_mul(self.capacity - 1)
+            .unwrap_or(Duration::MAX);
+        let wait = full_at.saturating_sub(now).saturating_sub(burst);
+        if wait > self.max_wait {
+            return Err(Rejected::RateLimited);
+        }
+        *full_at = (*full_at).max(now).saturating_add(self.refill_every);
+        Ok(wait)
+    }
+
+    /// Protect `func` with this limiter.  Calls rejected by the limiter resolve to
+    /// `E::from(Rejected::RateLimited)` without calling `func`.
+    pub fn protect<A, B, E>(
+        &self,
+        func: impl Fn(A) -> CFuture<Result<B, E>> + Send + Sync + 'static,
+    ) -> impl Fn(A) -> CFuture<Result<B, E>> + Clone + Send + Sync + 'static
+    where
+        A: Send + 'static,
+        B: Clone + Send + Sync + 'static,
+        E: From<Rejected> + Clone + Send + Sync + 'static,
+    {
+        let limiter = self.clone();
+        let func = Arc::new(func);
+        move |a| {
+            let (limiter, func) = (limiter.clone(), func.clone());
+            CFuture::from_outcome(
+                async move {
+                    let now = limiter.clock.monotonic().into_outcome().await?;
+                    match limiter.reserve(now) {
+                        Ok(wait) => limiter.clock.sleep(wait).into_outcome().await?,
+                        Err(rejected) => return Ok(Err(E::from(rejected))),
+                    }
+                    func(a).into_outcome().await
+                },
+                None,
+            )
+        }
+    }
+}
+
+struct Compartment {
+    permits: Semaphore,
+    waiting: AtomicUsize,
+}
+
+/// Counts a call as waiting for a permit for as long as it is alive.
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Limits how many calls run at the same time.
+///
+/// Calls beyond `max_concurrent` wait for a running one to finish.  With
+/// `with_max_waiting`, calls arriving while that many calls are already waiting are
+/// rejected with `Rejected::BulkheadFull` instead.
+///
+/// Clones of a bulkhead share its permits.
+#[derive(Clone)]
+pub struct Bulkhead {
+    compartment: Arc<Compartment>,
+    max_waiting: Option<usize>,
+}
+
+impl Bulkhead {
+    /// A bulkhead running up to `max_concurrent` calls at once, and at least one.
+    pub fn new(max_concurrent: usize) -> Bulkhead {
+        Bulkhead {
+            compartment: Arc::new(Compartment {
+                permits: Semaphore::new(max_concurrent.clamp(1, Semaphore::MAX_PERMITS)),
+                waiting: AtomicUsize::new(0),
+            }),
+            max_waiting: None,
+        }
+    }
+
+    /// Reject calls once `max_waiting` calls are already waiting.
+    pub fn with_max_waiting(self, max_waiting: usize) -> Bulkhead {
+        Bulkhead {
+            max_waiting: Some(max_waiting),
+            ..self
+        }
+    }
+
+    /// How many more calls could start right now.
+    pub fn available(&self) -> usize {
+        self.compartment.permits.available_permits()
+    }
+
+    /// Protect `func` with this bulkhead.  Calls rejected by the bulkhead resolve to
+    /// `E::from(Rejected::BulkheadFull)` without calling `func`.
+    pub fn protect<A, B, E>(
+        &self,
+        func: impl Fn(A) -> CFuture<Result<B, E>> + Send + Sync + 'static,
+    ) -> impl Fn(A) -> CFuture<Result<B, E>> + Clone + Send + Sync + 'static
+    where
+        A: Send + 'static,
+        B: Clone + Send + Sync + 'static,
+        E: From<Rejected> + Clone + Send + Sync + 'static,
+    {
+        let (compartment, max_waiting) = (self.compartment.clone(), self.max_waiting);
+        let func = Arc::new(func);
+        move |a| {
+            let (compartment, func) = (compartment.clone(), func.clone());
+            CFuture::from_outcome(
+                async move {
+                    let _permit = match compartment.permits.try_acquire() {
+                        Ok(permit) => permit,
+                        Err(_) => {
+                            let waiting = compartment.waiting.fetch_add(1, Ordering::SeqCst);
+                            let _waiting = Waiting(&compartment.waiting);
+                            if max_waiting.is_some_and(|max| waiting >= max) {
+                                return Ok(Err(E::from(Rejected::BulkheadFull)));
+                            }
+                            // The semaphore is never closed
+                            compartment.permits.acquire().await.unwrap()
+                        }
+                    };
+                    func(a).into_outcome().await
+                },
+                None,
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::clock::TestClock;
+    use std::sync::atomic::{AtomicBool, AtomicU32};
+    use tokio::time::Instant;
+
+    /// A stand-in for a remote dependency, which takes 10ms per call and can be made to
+    /// fail on demand.
+    #[derive(Clone, Default)]
+    struct Dependency {
+        failing: Arc<AtomicBool>,
+        calls: Arc<AtomicU32>,
+        running: Arc<AtomicU32>,
+        max_running: Arc<AtomicU32>,
+    }
+
+    impl Dependency {
+        fn set_failing(&self, failing: bool) {
+            self.failing.store(failing, Ordering::SeqCst);
+        }
+
+        fn calls(&self) -> u32 {
+            self.calls.load(Ordering::SeqCst)
+        }
+
+        fn call(&self) -> impl Fn(u32) -> CFuture<Result<u32, String>> + Send + Sync + use<> {
+            let dep = self.clone();
+            move |a| {
+                let dep = dep.clone();
+                CFuture::new(async move {
+                    dep.calls.fetch_add(1, Ordering::SeqCst);
+                    let running = dep.running.fetch_add(1, Ordering::SeqCst) + 1;
+                    dep.max_running.fetch_max(running, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    dep.running.fetch_sub(1, Ordering::SeqCst);
+                    if dep.failing.load(Ordering::SeqCst) {
+                        Err("down".to_string())
+                    } else {
+                        Ok(a * 2)
+                    }
+                })
+            }
+        }
+    }
+
+    fn rejected(rejected: Rejected) -> Result<u32, String> {
+        Err(rejected.into())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_circuit_breaker() {
+        let dep = Dependency::default();
+        let breaker = CircuitBreaker::new(2, Duration::from_millis(100));
+        let call = breaker.protect(dep.call());
+
+        assert_eq!(call(1).await, Ok(2));
+        dep.set_failing(true);
+        assert_eq!(call(1).await, Err("down".to_string()));
+        assert_eq!(breaker.state().await, BreakerState::Closed);
+        assert_eq!(call(1).await, Err("down".to_string()));
+        assert_eq!(breaker.state().await, BreakerState::Open);
+        assert_eq!(call(1).await, rejected(Rejected::CircuitOpen));
+        assert_eq!(dep.calls(), 3);
+
+        // A failed trial opens the breaker again
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(breaker.state().await, BreakerState::HalfOpen);
+        assert_eq!(call(1).await, Err("down".to_string()));
+        assert_eq!(breaker.state().await, BreakerState::Open);
+
+        // Only one trial runs at a time
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        dep.set_failing(false);
+        let both = CFuture::par_sequence(vec![call(1), call(2)]);
+        assert_eq!(both.await, vec![Ok(2), rejected(Rejected::CircuitOpen)]);
+        assert_eq!(breaker.state().await, BreakerState::Closed);
+        assert_eq!(dep.calls(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_long_reset() {
+        let dep = Dependency::default();
+        let clock = TestClock::new();
+        clock.advance(Duration::from_secs(1));
+        let breaker = CircuitBreaker::new(1, Duration::MAX).with_clock(clock.clone());
+        let call = breaker.protect(dep.call());
+
+        dep.set_failing(true);
+        assert_eq!(call(1).await, Err("down".to_string()));
+        assert_eq!(breaker.state().await, BreakerState::Open);
+        clock.advance(Duration::MAX);
+        assert_eq!(breaker.state().await, BreakerState::HalfOpen);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_circuit_breaker_success_threshold() {
+        let dep = Dependency::default();
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(100)).with_success_threshold(2);
+        let call = breaker.protect(dep.call());
+
+        dep.set_failing(true);
+        assert_eq!(call(1).await, Err("down".to_string()));
+        assert_eq!(breaker.state().await, BreakerState::Open);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        dep.set_failing(false);
+        assert_eq!(call(1).await, Ok(2));
+        assert_eq!(breaker.state().await, BreakerState::HalfOpen);
+        assert_eq!(call(1).await, Ok(2));
+        assert_eq!(breaker.state().await, BreakerState::Closed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_circuit_breaker_cancelled_trial() {
+        let dep = Dependency::default();
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(100));
+        let call = breaker.protect(dep.call());
+
+        dep.set_failing(true);
+        assert_eq!(call(1).await, Err("down".to_string()));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let token = crate::types::cancel::CancelToken::new();
+        let trial = call(1).cancellable(&token);
+        let running = tokio::spawn(trial.clone().outcome());
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        token.cancel();
+        assert!(running.await.unwrap().is_err());
+
+        // The cancelled trial doesn't count, and another one may run
+        dep.set_failing(false);
+        assert_eq!(breaker.state().await, BreakerState::HalfOpen);
+        assert_eq!(call(1).await, Ok(2));
+        assert_eq!(breaker.state().await, BreakerState::Closed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rate_limiter() {
+        let dep = Dependency::default();
+        let limiter = RateLimiter::new(2, Duration::from_millis(100));
+        let call = limiter.protect(dep.call());
+
+        let calls = CFuture::par_traverse(vec![1, 2, 3], &call);
+        assert_eq!(
+            calls.await,
+            vec![Ok(2), Ok(4), rejected(Rejected::RateLimited)]
+        );
+        assert_eq!(dep.calls(), 2);
+        tokio::time::sleep(Duration::from_millis(90)).await;
+        assert_eq!(call(1).await, Ok(2));
+        assert_eq!(call(1).await, rejected(Rejected::RateLimited));
+
+        // Refills up to capacity, and waits for tokens within the maximum wait
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let start = Instant::now();
+        let call = limiter
+            .clone()
+            .with_max_wait(Duration::from_millis(200))
+            .protect(dep.call());
+        let calls = CFuture::par_traverse(vec![1, 2, 3, 4, 5], &call);
+        assert_eq!(
+            calls.await,
+            vec![Ok(2), Ok(4), Ok(6), Ok(8), rejected(Rejected::RateLimited)]
+        );
+        assert_eq!(start.elapsed(), Duration::from_millis(210));
+    }
+
+    #[test]
+    fn test_rate_limiter_long_refill() {
+        let limiter = RateLimiter::new(u32::MAX, Duration::MAX / 2);
+        assert_eq!(limiter.reserve(Duration::ZERO), Ok(Duration::ZERO));
+        assert_eq!(limiter.reserve(Duration::MAX), Ok(Duration::ZERO));
+    }
+
+    #[tokio::test]
+    async fn test_on_test_clock() {
+        let clock = TestClock::new();
+        let failing = Arc::new(AtomicBool::new(true));
+        let f = failing.clone();
+        let call = move |a: u32| {
+            CFuture::lazy(match f.load(Ordering::SeqCst) {
+                true => Err("down".to_string()),
+                false => Ok(a),
+            })
+        };
+        let breaker = CircuitBreaker::new(1, Duration::from_secs(60)).with_clock(clock.clone());
+        let limiter = RateLimiter::new(1, Duration::from_secs(1))
+            .with_max_wait(Duration::from_secs(1))
+            .with_clock(clock.clone());
+        let call = limiter.protect(breaker.protect(call));
+
+        assert_eq!(call(1).await, Err("down".to_string()));
+        assert_eq!(breaker.state().await, BreakerState::Open);
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(breaker.state().await, BreakerState::HalfOpen);
+
+        // The second call waits for a token until the clock moves
+        failing.store(false, Ordering::SeqCst);
+        assert_eq!(call(1).await, Ok(1));
+        let waiting = tokio::spawn(call(2));
+        while clock.sleepers() == 0 {
+            tokio::task::yield_now().await;
+        }
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(waiting.await.unwrap(), Ok(2));
+        assert_eq!(breaker.state().await, BreakerState::Closed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_bulkhead() {
+        let dep = Dependency::default();
+        let bulkhead = Bulkhead::new(2);
+        let call = bulkhead.protect(dep.call());
+
+        let start = Instant::now();
+        let calls = CFuture::par_traverse(vec![1, 2, 3, 4, 5], &call);
+        assert_eq!(calls.await, vec![Ok(2), Ok(4), Ok(6), Ok(8), Ok(10)]);
+        assert_eq!(start.elapsed(), Duration::from_millis(30));
+        assert_eq!(dep.max_running.load(Ordering::SeqCst), 2);
+        assert_eq!(bulkhead.available(), 2);
+
+        let call = bulkhead.with_max_waiting(1).protect(dep.call());
+        let calls = CFuture::par_traverse(vec![1, 2, 3, 4], &call);
+        assert_eq!(
+            calls.await,
+            vec![Ok(2), Ok(4), Ok(6), rejected(Rejected::BulkheadFull)]
+        );
+
+        // A bulkhead always lets one call through
+        let call = Bulkhead::new(0).protect(dep.call());
+        assert_eq!(call(1).await, Ok(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stacked_protections() {
+        let dep = Dependency::default();
+        let breaker = CircuitBreaker::new(1, Duration::from_secs(1));
+        let limiter = RateLimiter::new(10, Duration::from_millis(10));
+        let call = breaker.protect(limiter.protect(Bulkhead::new(1).protect(dep.call())));
+
+        let res = bind(CFuture::lazy(3), call.clone());
+        assert_eq!(res.await, Ok(6));
+        dep.set_failing(true);
+        assert_eq!(
+            bind(CFuture::lazy(3), call.clone()).await,
+            Err("down".to_string())
+        );
+        let res = bind(CFuture::lazy(3), call);
+        assert_eq!(res.await, rejected(Rejected::CircuitOpen));
+        assert_eq!(dep.calls(), 2);
+    }
+}
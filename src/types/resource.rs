@@ -0,0 +1,430 @@
+use crate::prelude::typeclasses::*;
+use crate::types::{
+    executor::spawn_detached,
+    io::{IO, Panicked, run_built, sync_fn},
+};
+use futures::future::{BoxFuture, FutureExt};
+use std::sync::{Arc, Mutex};
+
+/// Releases of the resources acquired so far, in acquisition order.
+type Finalizers<E> = Arc<Mutex<Vec<IO<(), E>>>>;
+type Allocate<R, E> = Arc<dyn Fn(Finalizers<E>) -> BoxFuture<'static, Result<R, E>> + Send + Sync>;
+
+/// Run and remove the finalizers, last acquired first.  All of them run, and the first
+/// error is returned.
+async fn release_all<E: From<Panicked> + Send + 'static>(
+    finalizers: Finalizers<E>,
+) -> Result<(), E> {
+    let mut result = Ok(());
+    loop {
+        let next = finalizers.lock().unwrap().pop();
+        let Some(release) = next else {
+            return result;
+        };
+        let released = release.run_async().await;
+        result = result.and(released);
+    }
+}
+
+/// Releases whatever is left if the future using the resources is dropped before it
+/// finished, e.g. when it was cancelled.
+struct ReleaseGuard<E: From<Panicked> + Send + 'static>(Finalizers<E>);
+
+impl<E: From<Panicked> + Send + 'static> Drop for ReleaseGuard<E> {
+    fn drop(&mut self) {
+        if !self.0.lock().unwrap().is_empty() {
+            let finalizers = self.0.clone();
+            spawn_detached(release_all(finalizers).map(|_| ()));
+        }
+    }
+}
+
+/// A resource which is acquired, used, and then always released.
+///
+/// A `Resource` describes how to acquire an `R` and how to release it again; nothing is
+/// acquired until it is used with `use_resource`.  Resources compose with `fmap`, `seq`
+/// and `bind`: the combined resource acquires its parts in order and releases them in
+/// reverse order once the use is done, whether it succeeded, failed, panicked or was
+/// cancelled.  If acquiring one of the parts fails, the parts acquired before it are
+/// released.
+///
+/// When the `IO` using the resource is dropped before finishing (for instance when it
+/// runs inside a cancelled `CFuture`), the releases run in the background: on the
+/// current tokio runtime with the `tokio` feature, and otherwise on a new thread with the
+/// built-in executor, where releases can't rely on a tokio timer or socket.
+///
+/// ```rust
+/// use rust_effects::prelude::*;
+/// use rust_effects::types::{io::IO, resource::Resource};
+/// use std::sync::{Arc, Mutex};
+///
+/// type Log = Arc<Mutex<Vec<String>>>;
+///
+/// fn push(log: &Log, entry: String) -> IO<(), String> {
+///     let log = log.clone();
+///     IO::delay(move || log.lock().unwrap().push(entry.clone()))
+/// }
+///
+/// fn file(log: &Log, name: &'static str) -> Resource<&'static str, String> {
+///     let log = log.clone();
+///     Resource::make(
+///         fmap(push(&log, format!("open {}", name)), move |_| name),
+///         move |name| push(&log, format!("close {}", name)),
+///     )
+/// }
+///
+/// let log = Log::default();
+/// let l = log.clone();
+/// let both = bind(file(&log, "a"), move |a| fmap(file(&l, "b"), move |b| format!("{}{}", a, b)));
+/// let io = both.use_resource(|ab| IO::pure(ab.len()));
+///
+/// assert_eq!(io.run_sync(), Ok(2));
+/// assert_eq!(*log.lock().unwrap(), vec!["open a", "open b", "close b", "close a"]);
+/// ```
+pub struct Resource<R, E> {
+    allocate: Allocate<R, E>,
+}
+
+impl<R, E> Clone for Resource<R, E> {
+    fn clone(&self) -> Self {
+        Resource {
+            allocate: self.allocate.clone(),
+        }
+    }
+}
+
+impl<R, E> Resource<R, E>
+where
+    R: Send + 'static,
+    E: From<Panicked> + Send + 'static,
+{
+    fn from_allocate(
+        allocate: impl Fn(Finalizers<E>) -> BoxFuture<'static, Result<R, E>> + Send + Sync + 'static,
+    ) -> Resource<R, E> {
+        Resource {
+            allocate: Arc::new(allocate),
+        }
+    }
+
+    /// A resource acquired with `acquire` and released with `release`.
+    pub fn make(
+        acquire: IO<R, E>,
+        release: impl Fn(R) -> IO<(), E> + Send + Sync + 'static,
+    ) -> Resource<R, E>
+    where
+        R: Clone + Sync,
+    {
+        let release = Arc::new(release);
+        Resource::from_allocate(move |finalizers| {
+            let (acquire, release) = (acquire.run_async(), release.clone());
+            async move {
+                let r = acquire.await?;
+                let released = r.clone();
+                finalizers
+                    .lock()
+                    .unwrap()
+                    .push(IO::suspend(move || release(released.clone())));
+                Ok(r)
+            }
+            .boxed()
+        })
+    }
+
+    /// A resource with nothing to release.
+    pub fn lift(acquire: IO<R, E>) -> Resource<R, E> {
+        Resource::from_allocate(move |_| acquire.run_async().boxed())
+    }
+
+    pub fn pure(r: R) -> Resource<R, E>
+    where
+        R: Clone + Sync,
+    {
+        Resource::lift(IO::pure(r))
+    }
+
+    pub fn map<S: Send + 'static>(
+        &self,
+        func: impl Fn(R) -> S + Send + Sync + 'static,
+    ) -> Resource<S, E> {
+        let (allocate, func) = (self.allocate.clone(), Arc::new(func));
+        Resource::from_allocate(move |finalizers| {
+            let func = func.clone();
+            allocate(finalizers)
+                .map(move |r| r.map(|r| func(r)))
+                .boxed()
+        })
+    }
+
+    /// Acquire this resource, then the one built from it by `func`.
+    pub fn and_then<S: Send + 'static>(
+        &self,
+        func: impl Fn(R) -> Resource<S, E> + Send + Sync + 'static,
+    ) -> Resource<S, E> {
+        let (allocate, func) = (self.allocate.clone(), Arc::new(func));
+        Resource::from_allocate(move |finalizers| {
+            let (allocate, func) = (allocate.clone(), func.clone());
+            async move {
+                let r = allocate(finalizers.clone()).await?;
+                (func(r).allocate)(finalizers).await
+            }
+            .boxed()
+        })
+    }
+
+    /// Acquire the resource, use it with `func`, and release it.  The error of `func`
+    /// takes precedence over errors raised while releasing.
+    pub fn use_resource<T: Send + 'static>(
+        &self,
+        func: impl Fn(R) -> IO<T, E> + Send + Sync + 'static,
+    ) -> IO<T, E> {
+        let (allocate, func) = (self.allocate.clone(), Arc::new(func));
+        IO::from_future(move || {
+            let (allocate, func) = (allocate.clone(), func.clone());
+            async move {
+                let guard = ReleaseGuard(Finalizers::default());
+                let used = match allocate(guard.0.clone()).await {
+                    Ok(r) => run_built(move || func(r)).await,
+                    Err(e) => Err(e),
+                };
+                let released = release_all(guard.0.clone()).await;
+                let t = used?;
+                released.map(|_| t)
+            }
+        })
+    }
+}
+
+impl<T, U, E> Functor<U> for Resource<T, E>
+where
+    T: Send + 'static,
+    U: Send + 'static,
+    E: From<Panicked> + Send + 'static,
+{
+    type FuncT = T;
+    type FunctorOut = Resource<U, E>;
+    fn fmap(m: Self, func: impl Fn(T) -> U + Send + 'static) -> Self::FunctorOut {
+        m.map(sync_fn(func))
+    }
+}
+
+impl<T, U, E> Applicative<U> for Resource<T, E>
+where
+    T: Clone + Send + Sync + 'static,
+    U: Send + 'static,
+    E: From<Panicked> + Send + 'static,
+{
+    type AppT = T;
+    fn pure(a: T) -> Self {
+        Resource::pure(a)
+    }
+}
+
+impl<F, T, U, E> ApplicativeFunctor<F, U> for Resource<T, E>
+where
+    F: Fn(T) -> U + Send + Sync + 'static,
+    T: Clone + Send + Sync + 'static,
+    U: Clone + Send + Sync + 'static,
+    E: From<Panicked> + Send + 'static,
+{
+    type AppFuncT = T;
+    type AppFuncOut = Resource<U, E>;
+    type AppFuncFn = Resource<F, E>;
+    fn seq(m: Self, func: Self::AppFuncFn) -> Self::AppFuncOut {
+        func.and_then(move |f| m.map(f))
+    }
+}
+
+impl<T, U, E> Monad<U> for Resource<T, E>
+where
+    T: Clone + Send + Sync + 'static,
+    U: Clone + Send + Sync + 'static,
+    E: From<Panicked> + Send + 'static,
+{
+    type MonadT = T;
+    type MonadOut = Resource<U, E>;
+    fn bind(m: Self, func: impl Fn(T) -> Self::MonadOut + Send + 'static) -> Self::MonadOut {
+        m.and_then(sync_fn(func))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prelude::CFuture;
+    use crate::types::cancel::{CancelToken, Cancelled};
+    use std::time::Duration;
+
+    type Log = Arc<Mutex<Vec<String>>>;
+
+    fn push(log: &Log, entry: String) -> IO<(), String> {
+        let log = log.clone();
+        IO::delay(move || log.lock().unwrap().push(entry.clone()))
+    }
+
+    fn handle(log: &Log, name: &'static str) -> Resource<&'static str, String> {
+        let (l1, l2) = (log.clone(), log.clone());
+        Resource::make(
+            fmap(push(&l1, format!("open {}", name)), move |_| name),
+            move |name| push(&l2, format!("close {}", name)),
+        )
+    }
+
+    fn failing(log: &Log, name: &'static str) -> Resource<&'static str, String> {
+        let log = log.clone();
+        Resource::make(
+            IO::try_delay(move || Err(format!("cannot open {}", name))),
+            move |name| push(&log, format!("close {}", name)),
+        )
+    }
+
+    fn entries(log: &Log) -> Vec<String> {
+        log.lock().unwrap().clone()
+    }
+
+    /// The entries once `len` of them were logged, waiting for releases running in the
+    /// background.
+    async fn settled(log: &Log, len: usize) -> Vec<String> {
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while entries(log).len() < len && std::time::Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        entries(log)
+    }
+
+    fn three(log: &Log) -> Resource<String, String> {
+        let (l2, l3) = (log.clone(), log.clone());
+        bind(handle(log, "a"), move |a| {
+            let l3 = l3.clone();
+            bind(handle(&l2, "b"), move |b| {
+                fmap(handle(&l3, "c"), move |c| format!("{}{}{}", a, b, c))
+            })
+        })
+    }
+
+    #[test]
+    fn test_lifo_release() {
+        let log = Log::default();
+        let l = log.clone();
+        let io = three(&log).use_resource(move |abc| fmap(push(&l, format!("use {}", abc)), |_| 3));
+        assert_eq!(io.run_sync(), Ok(3));
+        let expected = vec![
+            "open a", "open b", "open c", "use abc", "close c", "close b", "close a",
+        ];
+        assert_eq!(entries(&log), expected);
+
+        // Nothing is shared between runs
+        assert_eq!(io.run_sync(), Ok(3));
+        assert_eq!(entries(&log).len(), 2 * expected.len());
+    }
+
+    #[test]
+    fn test_release_on_use_error() {
+        let log = Log::default();
+        let io = three(&log).use_resource(|_| IO::<u32, String>::raise_error("bad".to_string()));
+        assert_eq!(io.run_sync(), Err("bad".to_string()));
+        assert_eq!(
+            entries(&log),
+            vec![
+                "open a", "open b", "open c", "close c", "close b", "close a"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_release_on_panic() {
+        let log = Log::default();
+        let io = three(&log).use_resource(|_| -> IO<u32, String> { panic!("boom") });
+        assert_eq!(io.run_sync(), Err("panicked: boom".to_string()));
+        assert_eq!(
+            entries(&log),
+            vec![
+                "open a", "open b", "open c", "close c", "close b", "close a"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_release_on_acquire_error() {
+        let log = Log::default();
+        let l = log.clone();
+        let res = seq(
+            failing(&log, "b"),
+            fmap(handle(&log, "a"), |a| move |b| format!("{}{}", a, b)),
+        );
+        let io = res.use_resource(move |_| push(&l, "use".to_string()));
+        assert_eq!(io.run_sync(), Err("cannot open b".to_string()));
+        assert_eq!(entries(&log), vec!["open a", "close a"]);
+    }
+
+    #[test]
+    fn test_release_errors() {
+        let log = Log::default();
+        let res = bind(handle(&log, "a"), |_| {
+            Resource::make(IO::pure(1u32), |_| {
+                IO::raise_error("close failed".to_string())
+            })
+        });
+        let io = res.use_resource(|n| IO::pure(n + 1));
+        assert_eq!(io.run_sync(), Err("close failed".to_string()));
+        // The other releases still ran
+        assert_eq!(entries(&log), vec!["open a", "close a"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_release_on_cancel() {
+        let log = Log::default();
+        let io = three(&log).use_resource(|_| {
+            IO::from_future(|| async {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                Ok(1u32)
+            })
+        });
+        let token = CancelToken::new();
+        let fut = CFuture::new(io.run_async()).cancellable(&token);
+        let canceller = token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            canceller.cancel();
+        });
+        assert_eq!(fut.outcome().await, Err(Cancelled));
+        assert_eq!(
+            settled(&log, 6).await,
+            vec![
+                "open a", "open b", "open c", "close c", "close b", "close a"
+            ]
+        );
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn test_async_release_on_cancel() {
+        let log = Log::default();
+        let l = log.clone();
+        let res = Resource::make(IO::<u32, String>::pure(1), move |_| {
+            let l = l.clone();
+            IO::from_future(move || {
+                let l = l.clone();
+                async move {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    l.lock().unwrap().push("released".to_string());
+                    Ok(())
+                }
+            })
+        });
+        let io = res.use_resource(|n| {
+            IO::from_future(move || async move {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                Ok(n)
+            })
+        });
+        let token = CancelToken::new();
+        let fut = CFuture::new(io.run_async()).cancellable(&token);
+        let canceller = token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            canceller.cancel();
+        });
+        assert_eq!(fut.outcome().await, Err(Cancelled));
+        assert_eq!(settled(&log, 1).await, vec!["released"]);
+    }
+}
@@ -1,4 +1,5 @@
 use crate::prelude::typeclasses::*;
+use crate::typeclasses::free_effect::trace::Traceable;
 
 impl<A: Monoid, E: Monoid> Monoid for Result<A, E> {
     fn empty() -> Self {
@@ -52,7 +53,7 @@ where
     type AppFuncOut = Result<U, E>;
     type AppFuncFn = Result<F, E>;
     fn seq(m: Self, func: Self::AppFuncFn) -> Self::AppFuncOut {
-        func.and_then(|f| m.map(|t| f(t)))
+        func.and_then(|f| m.map(f))
     }
 }
 
@@ -64,6 +65,12 @@ impl<T, U: Send, E: Send> Monad<U> for Result<T, E> {
     }
 }
 
+impl<A, E> Traceable for Result<A, E> {
+    fn is_empty_state(&self) -> Option<bool> {
+        Some(self.is_err())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -122,7 +129,7 @@ mod test {
     }
 
     fn empty_if_even<M: Monad<u32, MonadT = u32> + Monoid + Applicative<u32>>(input: String) -> M {
-        if input.len() % 2 == 0 {
+        if input.len().is_multiple_of(2) {
             M::empty()
         } else {
             M::pure(input.len() as u32)
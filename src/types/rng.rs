@@ -0,0 +1,43 @@
+/// The `n`th output (from 0) of a SplitMix64 generator seeded with `seed`, computed
+/// without generating the ones before it, for random numbers which only depend on
+/// their seed (seeded jitter, or the schedules of the test executor).
+pub(crate) fn splitmix64(seed: u64, n: u64) -> u64 {
+    let mut z = seed.wrapping_add(n.wrapping_add(1).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// A seeded pseudo-random generator drawing from `splitmix64` in turn.
+#[cfg(feature = "testing")]
+pub(crate) struct Rng {
+    seed: u64,
+    drawn: u64,
+}
+
+#[cfg(feature = "testing")]
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Rng {
+        Rng { seed, drawn: 0 }
+    }
+
+    /// A number in `0..n`.
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        let bits = splitmix64(self.seed, self.drawn);
+        self.drawn += 1;
+        (bits % n as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_splitmix64() {
+        // Reference outputs of SplitMix64 seeded with 0
+        assert_eq!(splitmix64(0, 0), 0xe220_a839_7b1d_cdaf);
+        assert_eq!(splitmix64(0, 1), 0x6e78_9e6a_a1b9_65f4);
+        assert_ne!(splitmix64(1, 0), splitmix64(0, 0));
+    }
+}
@@ -0,0 +1,267 @@
+use crate::prelude::typeclasses::*;
+use crate::types::rng::splitmix64;
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::Arc,
+    time::Duration,
+};
+
+/// Where a retried or repeated computation is at when its `Schedule` is consulted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Step {
+    /// How many times the computation was already re-run (0 after the first run)
+    pub attempt: u32,
+    /// Time since the first run started
+    pub elapsed: Duration,
+}
+
+type Decide = Arc<dyn Fn(Step) -> Option<Duration> + Send + Sync>;
+
+/// A policy deciding whether, and after how long, to run a computation again.
+///
+/// Schedules are used by `CFuture::retry` and `CFuture::repeat`.  Each time the
+/// computation finishes the schedule is given the current `Step`, and returns either the
+/// delay before the next run, or `None` to stop.
+///
+/// Schedules compose as a `Semigroup`: `combine` is their intersection, which continues
+/// only while both schedules do and waits for the longer of the two delays, while
+/// `combine_m` is their union, which continues while either schedule does and waits for
+/// the shorter delay.  The matching `Monoid` identities are a schedule recurring forever
+/// without delay (`empty`) and one which never recurs (`empty_m`).
+///
+/// ```rust
+/// use rust_effects::prelude::*;
+/// use rust_effects::types::schedule::{Schedule, Step};
+/// use std::time::Duration;
+///
+/// // Exponential backoff from 10ms, for at most 3 retries
+/// let schedule = combine(
+///     Schedule::exponential(Duration::from_millis(10)),
+///     Schedule::recurs(3),
+/// );
+/// let delays: Vec<_> = (0..4)
+///     .map(|attempt| schedule.next_delay(Step { attempt, elapsed: Duration::ZERO }))
+///     .collect();
+/// assert_eq!(
+///     delays,
+///     vec![
+///         Some(Duration::from_millis(10)),
+///         Some(Duration::from_millis(20)),
+///         Some(Duration::from_millis(40)),
+///         None,
+///     ]
+/// );
+/// ```
+#[derive(Clone)]
+pub struct Schedule {
+    decide: Decide,
+}
+
+impl Schedule {
+    fn from_fn(decide: impl Fn(Step) -> Option<Duration> + Send + Sync + 'static) -> Schedule {
+        Schedule {
+            decide: Arc::new(decide),
+        }
+    }
+
+    /// Recur `n` times, without delay.
+    pub fn recurs(n: u32) -> Schedule {
+        Schedule::from_fn(move |step| (step.attempt < n).then_some(Duration::ZERO))
+    }
+
+    /// Recur forever, waiting `delay` between runs.
+    pub fn spaced(delay: Duration) -> Schedule {
+        Schedule::from_fn(move |_| Some(delay))
+    }
+
+    /// Recur forever, doubling the delay after each run, starting from `base`.
+    pub fn exponential(base: Duration) -> Schedule {
+        Schedule::from_fn(move |step| {
+            let factor = 2u32.checked_pow(step.attempt);
+            Some(
+                factor
+                    .and_then(|f| base.checked_mul(f))
+                    .unwrap_or(Duration::MAX),
+            )
+        })
+    }
+
+    /// Recur forever, with delays following the Fibonacci sequence scaled by `base`
+    /// (`base`, `base`, `2 * base`, `3 * base`, `5 * base`, ...).
+    pub fn fibonacci(base: Duration) -> Schedule {
+        Schedule::from_fn(move |step| {
+            let (mut a, mut b) = (base, base);
+            for _ in 0..step.attempt {
+                (a, b) = (b, a.saturating_add(b));
+            }
+            Some(a)
+        })
+    }
+
+    /// Recur without delay for as long as less than `duration` has elapsed since the
+    /// first run.
+    pub fn up_to(duration: Duration) -> Schedule {
+        Schedule::from_fn(move |step| (step.elapsed < duration).then_some(Duration::ZERO))
+    }
+
+    /// Randomly scale each delay of this schedule by a factor between 0.5 and 1.5, so
+    /// that many clients retrying at once don't all do so at the same moment.
+    pub fn jittered(self) -> Schedule {
+        self.jittered_by(|attempt| {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u32(attempt);
+            hasher.finish()
+        })
+    }
+
+    /// Like `jittered`, with factors derived from `seed`, so that the same seed always
+    /// gives the same delays (in tests, for instance).
+    pub fn jittered_with_seed(self, seed: u64) -> Schedule {
+        self.jittered_by(move |attempt| splitmix64(seed, attempt.into()))
+    }
+
+    /// Scale each delay by a factor between 0.5 and 1.5 taken from the random bits
+    /// `bits` gives for the attempt.
+    fn jittered_by(self, bits: impl Fn(u32) -> u64 + Send + Sync + 'static) -> Schedule {
+        Schedule::from_fn(move |step| {
+            let delay = (self.decide)(step)?;
+            let factor = 0.5 + (bits(step.attempt) >> 11) as f64 / (1u64 << 53) as f64;
+            Some(Duration::try_from_secs_f64(delay.as_secs_f64() * factor).unwrap_or(Duration::MAX))
+        })
+    }
+
+    /// The delay before the next run at `step`, or `None` if the schedule is done.
+    pub fn next_delay(&self, step: Step) -> Option<Duration> {
+        (self.decide)(step)
+    }
+}
+
+impl Semigroup for Schedule {
+    fn combine(a: Self, b: Self) -> Self {
+        Schedule::from_fn(move |step| Some(a.next_delay(step)?.max(b.next_delay(step)?)))
+    }
+
+    fn combine_m(a: Self, b: Self) -> Self {
+        Schedule::from_fn(move |step| match (a.next_delay(step), b.next_delay(step)) {
+            (Some(x), Some(y)) => Some(x.min(y)),
+            (x, y) => x.or(y),
+        })
+    }
+}
+
+impl Monoid for Schedule {
+    fn empty() -> Self {
+        Schedule::from_fn(|_| Some(Duration::ZERO))
+    }
+
+    fn empty_m() -> Self {
+        Schedule::from_fn(|_| None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn delays(schedule: &Schedule, n: u32) -> Vec<Option<u64>> {
+        (0..n)
+            .map(|attempt| {
+                let step = Step {
+                    attempt,
+                    elapsed: Duration::from_millis(attempt as u64 * 100),
+                };
+                schedule.next_delay(step).map(|d| d.as_millis() as u64)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_schedules() {
+        let ms = Duration::from_millis;
+        assert_eq!(
+            delays(&Schedule::recurs(2), 3),
+            vec![Some(0), Some(0), None]
+        );
+        assert_eq!(delays(&Schedule::spaced(ms(5)), 2), vec![Some(5), Some(5)]);
+        assert_eq!(
+            delays(&Schedule::exponential(ms(5)), 4),
+            vec![Some(5), Some(10), Some(20), Some(40)]
+        );
+        assert_eq!(
+            delays(&Schedule::fibonacci(ms(5)), 5),
+            vec![Some(5), Some(5), Some(10), Some(15), Some(25)]
+        );
+        assert_eq!(
+            delays(&Schedule::up_to(ms(250)), 4),
+            vec![Some(0), Some(0), Some(0), None]
+        );
+        let step = Step {
+            attempt: 200,
+            elapsed: Duration::ZERO,
+        };
+        assert_eq!(
+            Schedule::exponential(ms(1)).next_delay(step),
+            Some(Duration::MAX)
+        );
+    }
+
+    #[test]
+    fn test_jittered() {
+        let schedule = Schedule::spaced(Duration::from_millis(100)).jittered();
+        for delay in delays(&schedule, 100) {
+            assert!((50..150).contains(&delay.unwrap()));
+        }
+        let schedule = Schedule::recurs(1).jittered();
+        assert_eq!(delays(&schedule, 2), vec![Some(0), None]);
+    }
+
+    #[test]
+    fn test_jittered_with_seed() {
+        let seeded = |seed| Schedule::spaced(Duration::from_millis(100)).jittered_with_seed(seed);
+        let first = delays(&seeded(7), 100);
+        assert!(first.iter().all(|d| (50..150).contains(&d.unwrap())));
+        assert_eq!(delays(&seeded(7), 100), first);
+        assert_ne!(delays(&seeded(8), 100), first);
+        // Delays vary between attempts
+        assert!(first.iter().any(|d| *d != first[0]));
+    }
+
+    #[test]
+    fn test_jittered_saturates() {
+        let step = Step {
+            attempt: 200,
+            elapsed: Duration::ZERO,
+        };
+        // Some of the seeds scale the delay up, past `Duration::MAX`
+        for seed in 0..10 {
+            let schedule = Schedule::exponential(Duration::from_millis(1)).jittered_with_seed(seed);
+            assert!(schedule.next_delay(step).unwrap() >= Duration::MAX / 2);
+        }
+    }
+
+    #[test]
+    fn test_combine_schedules() {
+        let ms = Duration::from_millis;
+        let both = combine(Schedule::exponential(ms(10)), Schedule::spaced(ms(15)));
+        assert_eq!(delays(&both, 3), vec![Some(15), Some(20), Some(40)]);
+        let either = combine_m(Schedule::exponential(ms(10)), Schedule::spaced(ms(15)));
+        assert_eq!(delays(&either, 3), vec![Some(10), Some(15), Some(15)]);
+
+        let both = combine(Schedule::spaced(ms(10)), Schedule::recurs(1));
+        assert_eq!(delays(&both, 2), vec![Some(10), None]);
+        let either = combine_m(Schedule::recurs(1), Schedule::recurs(2));
+        assert_eq!(delays(&either, 3), vec![Some(0), Some(0), None]);
+    }
+
+    #[test]
+    fn test_schedule_identities() {
+        let ms = Duration::from_millis;
+        let fib = || Schedule::fibonacci(ms(10));
+        let expected = delays(&fib(), 5);
+        assert_eq!(delays(&combine(fib(), Schedule::empty()), 5), expected);
+        assert_eq!(delays(&combine(Schedule::empty(), fib()), 5), expected);
+        assert_eq!(delays(&combine_m(fib(), Schedule::empty_m()), 5), expected);
+        assert_eq!(delays(&combine_m(Schedule::empty_m(), fib()), 5), expected);
+    }
+}
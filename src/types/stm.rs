@@ -0,0 +1,642 @@
+use crate::prelude::typeclasses::*;
+use crate::typeclasses::free_effect::trace::Traceable;
+use crate::types::{cfuture::CFuture, executor::yield_now, io::sync_fn};
+use std::{
+    any::Any,
+    collections::HashMap,
+    marker::PhantomData,
+    pin::Pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    task::{Context, Poll, Waker},
+};
+
+type Value = Arc<dyn Any + Send + Sync>;
+
+/// Ids for `TVar`s, used to key transaction logs.
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Ids for blocked transactions, used to key the wakers they leave on `TVar`s.
+static NEXT_WAITER: AtomicU64 = AtomicU64::new(0);
+
+/// Held while committing, so that validating a transaction and publishing its writes
+/// happen as one step for other commits.  Readers don't take it: they are kept from
+/// seeing part of a commit by the slot locks, which it holds until all are written.
+static COMMIT: Mutex<()> = Mutex::new(());
+
+struct Slot {
+    value: Value,
+    /// Bumped by every commit writing to the cell
+    version: u64,
+}
+
+struct Cell {
+    slot: Mutex<Slot>,
+    /// Transactions blocked in `retry` after reading the cell, by waiter id
+    waiters: Mutex<HashMap<u64, Waker>>,
+}
+
+impl Cell {
+    fn read(&self) -> (Value, u64) {
+        let slot = self.slot.lock().unwrap();
+        (slot.value.clone(), slot.version)
+    }
+
+    fn version(&self) -> u64 {
+        self.slot.lock().unwrap().version
+    }
+}
+
+/// What a transaction knows about one `TVar`.
+#[derive(Clone)]
+struct Entry {
+    cell: Arc<Cell>,
+    /// The version of the cell when the transaction first touched it
+    version: u64,
+    /// The committed value at `version`
+    original: Value,
+    /// The value as seen by the transaction, including its own writes
+    value: Value,
+    written: bool,
+}
+
+/// The reads and writes of a running transaction.  Nothing is visible to other
+/// transactions until the log is committed.
+#[derive(Clone, Default)]
+struct Log {
+    entries: HashMap<u64, Entry>,
+}
+
+impl Log {
+    /// Find the entry for a `TVar`, adding it on first use.  `None` if the transaction
+    /// can no longer commit, as another one changed what it read so far.
+    fn entry(&mut self, id: u64, cell: &Arc<Cell>) -> Option<&mut Entry> {
+        if !self.entries.contains_key(&id) {
+            let (value, version) = cell.read();
+            // Validated on every new read, so a transaction never sees an inconsistent
+            // state, even if it is going to be restarted
+            if !self.is_valid() {
+                return None;
+            }
+            let entry = Entry {
+                cell: cell.clone(),
+                version,
+                original: value.clone(),
+                value,
+                written: false,
+            };
+            self.entries.insert(id, entry);
+        }
+        self.entries.get_mut(&id)
+    }
+
+    fn is_valid(&self) -> bool {
+        self.entries.values().all(|e| e.cell.version() == e.version)
+    }
+
+    /// Undo the writes made since `snapshot` was taken, while keeping track of the
+    /// reads, so that a `retry` still waits for them.
+    fn rollback(&mut self, mut snapshot: Log) {
+        for (id, mut entry) in self.entries.drain() {
+            snapshot.entries.entry(id).or_insert_with(|| {
+                entry.value = entry.original.clone();
+                entry.written = false;
+                entry
+            });
+        }
+        *self = snapshot;
+    }
+
+    /// Publish the writes, unless another transaction committed changes to the `TVar`s
+    /// this one used first.  The written slots are all locked before any is updated,
+    /// so a transaction reading them sees either none or all of the writes.
+    fn commit(&self) -> bool {
+        let _commit = COMMIT.lock().unwrap_or_else(|e| e.into_inner());
+        if !self.is_valid() {
+            return false;
+        }
+        let written: Vec<_> = self.entries.values().filter(|e| e.written).collect();
+        let mut slots: Vec<_> = written
+            .iter()
+            .map(|e| e.cell.slot.lock().unwrap())
+            .collect();
+        for (slot, entry) in slots.iter_mut().zip(&written) {
+            slot.value = entry.value.clone();
+            slot.version += 1;
+        }
+        drop(slots);
+        for entry in written {
+            let waiters = std::mem::take(&mut *entry.cell.waiters.lock().unwrap());
+            waiters.into_values().for_each(Waker::wake);
+        }
+        true
+    }
+
+    /// A future resolving once any `TVar` in the log changes.
+    fn changed(self) -> Changed {
+        Changed {
+            log: self,
+            id: NEXT_WAITER.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+}
+
+/// Future returned by `Log::changed`.  Its wakers are removed from the `TVar`s when it
+/// completes or is dropped.
+struct Changed {
+    log: Log,
+    id: u64,
+}
+
+impl Future for Changed {
+    type Output = ();
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        for entry in self.log.entries.values() {
+            let mut waiters = entry.cell.waiters.lock().unwrap();
+            match waiters.get_mut(&self.id) {
+                Some(waker) if waker.will_wake(cx.waker()) => {}
+                Some(waker) => waker.clone_from(cx.waker()),
+                None => {
+                    waiters.insert(self.id, cx.waker().clone());
+                }
+            }
+        }
+        // Checked after registering, as commits bump versions before waking
+        if self.log.is_valid() {
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    }
+}
+
+impl Drop for Changed {
+    fn drop(&mut self) {
+        for entry in self.log.entries.values() {
+            entry.cell.waiters.lock().unwrap().remove(&self.id);
+        }
+    }
+}
+
+enum Step<A> {
+    Done(A),
+    /// The transaction asked to wait for one of the `TVar`s it read to change
+    Retry,
+    /// The transaction read inconsistent values, and must start over
+    Conflict,
+}
+
+type Transaction<A> = Arc<dyn Fn(&mut Log) -> Step<A> + Send + Sync>;
+
+/// A transactional variable, read and written by `STM` transactions.
+///
+/// Clones of a `TVar` refer to the same variable.
+pub struct TVar<A> {
+    id: u64,
+    cell: Arc<Cell>,
+    value: PhantomData<fn() -> A>,
+}
+
+impl<A> Clone for TVar<A> {
+    fn clone(&self) -> Self {
+        TVar {
+            id: self.id,
+            cell: self.cell.clone(),
+            value: PhantomData,
+        }
+    }
+}
+
+impl<A: Clone + Send + Sync + 'static> TVar<A> {
+    pub fn new(value: A) -> TVar<A> {
+        let slot = Slot {
+            value: Arc::new(value),
+            version: 0,
+        };
+        TVar {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            cell: Arc::new(Cell {
+                slot: Mutex::new(slot),
+                waiters: Mutex::new(HashMap::new()),
+            }),
+            value: PhantomData,
+        }
+    }
+
+    pub fn read(&self) -> STM<A> {
+        let tvar = self.clone();
+        STM::from_fn(move |log| match log.entry(tvar.id, &tvar.cell) {
+            Some(entry) => Step::Done(downcast(&entry.value)),
+            None => Step::Conflict,
+        })
+    }
+
+    pub fn write(&self, value: A) -> STM<()> {
+        let tvar = self.clone();
+        let value: Value = Arc::new(value);
+        STM::from_fn(move |log| match log.entry(tvar.id, &tvar.cell) {
+            Some(entry) => {
+                entry.value = value.clone();
+                entry.written = true;
+                Step::Done(())
+            }
+            None => Step::Conflict,
+        })
+    }
+
+    pub fn modify(&self, func: impl Fn(A) -> A + Send + Sync + 'static) -> STM<()> {
+        let tvar = self.clone();
+        self.read().and_then(move |a| tvar.write(func(a)))
+    }
+
+    /// The last committed value, read outside of any transaction.
+    pub fn load(&self) -> A {
+        downcast(&self.cell.read().0)
+    }
+}
+
+fn downcast<A: Clone + 'static>(value: &Value) -> A {
+    // `TVar<A>` only ever stores `A`s
+    value.downcast_ref::<A>().unwrap().clone()
+}
+
+/// A transaction over `TVar`s, run with `atomically`.
+///
+/// Transactions are descriptions: they run when `atomically` is awaited, and may run
+/// several times, as they are restarted whenever another transaction commits changes to
+/// the `TVar`s they read first.  They should thus have no side effects besides reading
+/// and writing `TVar`s.
+///
+/// ```rust
+/// use rust_effects::prelude::*;
+/// use rust_effects::types::stm::{STM, TVar, atomically};
+///
+/// let (from, to) = (TVar::new(100u32), TVar::new(0u32));
+/// let transfer = |amount: u32| {
+///     let (from, to) = (from.clone(), to.clone());
+///     from.read().and_then(move |balance| {
+///         let (from, to) = (from.clone(), to.clone());
+///         STM::check(balance >= amount)
+///             .and_then(move |_| from.write(balance - amount))
+///             .and_then(move |_| to.modify(move |b| b + amount))
+///     })
+/// };
+/// atomically(transfer(30)).wait();
+/// assert_eq!((from.load(), to.load()), (70, 30));
+/// ```
+pub struct STM<A> {
+    run: Transaction<A>,
+}
+
+impl<A> Clone for STM<A> {
+    fn clone(&self) -> Self {
+        STM {
+            run: self.run.clone(),
+        }
+    }
+}
+
+impl<A: Clone + Send + Sync + 'static> STM<A> {
+    fn from_fn(run: impl Fn(&mut Log) -> Step<A> + Send + Sync + 'static) -> STM<A> {
+        STM { run: Arc::new(run) }
+    }
+
+    pub fn pure(a: A) -> STM<A> {
+        STM::from_fn(move |_| Step::Done(a.clone()))
+    }
+
+    /// Abandon the transaction, and run it again once one of the `TVar`s it read so far
+    /// changed.  A transaction which retries without having read anything never resumes.
+    pub fn retry() -> STM<A> {
+        STM::from_fn(|_| Step::Retry)
+    }
+
+    pub fn map<B: Clone + Send + Sync + 'static>(
+        self,
+        func: impl Fn(A) -> B + Send + Sync + 'static,
+    ) -> STM<B> {
+        STM::from_fn(move |log| match (self.run)(log) {
+            Step::Done(a) => Step::Done(func(a)),
+            Step::Retry => Step::Retry,
+            Step::Conflict => Step::Conflict,
+        })
+    }
+
+    pub fn and_then<B: Clone + Send + Sync + 'static>(
+        self,
+        func: impl Fn(A) -> STM<B> + Send + Sync + 'static,
+    ) -> STM<B> {
+        STM::from_fn(move |log| match (self.run)(log) {
+            Step::Done(a) => (func(a).run)(log),
+            Step::Retry => Step::Retry,
+            Step::Conflict => Step::Conflict,
+        })
+    }
+
+    /// Run this transaction, or `other` if this one retries.  The writes of this
+    /// transaction are undone before running `other`.  If both retry, the whole
+    /// transaction waits for a change to any of the `TVar`s either of them read.
+    pub fn or_else(self, other: STM<A>) -> STM<A> {
+        STM::from_fn(move |log| {
+            let snapshot = log.clone();
+            match (self.run)(log) {
+                Step::Retry => {
+                    log.rollback(snapshot);
+                    (other.run)(log)
+                }
+                step => step,
+            }
+        })
+    }
+}
+
+impl STM<()> {
+    /// Continue if `cond` holds, and `retry` otherwise.
+    pub fn check(cond: bool) -> STM<()> {
+        if cond { STM::pure(()) } else { STM::retry() }
+    }
+}
+
+/// Run `stm` as a single atomic transaction, resolving to its result once it committed.
+/// Nothing runs until the returned future is polled.  A transaction restarted after
+/// a conflict yields to the executor first, so that it doesn't hold up other tasks.
+pub fn atomically<A: Clone + Send + Sync + 'static>(stm: STM<A>) -> CFuture<A> {
+    CFuture::new(async move {
+        loop {
+            let mut log = Log::default();
+            match (stm.run)(&mut log) {
+                Step::Done(a) if log.commit() => return a,
+                Step::Retry if log.is_valid() => log.changed().await,
+                // Conflicting, or raced with a commit: start over
+                _ => yield_now().await,
+            }
+        }
+    })
+}
+
+impl<T, U> Functor<U> for STM<T>
+where
+    T: Clone + Send + Sync + 'static,
+    U: Clone + Send + Sync + 'static,
+{
+    type FuncT = T;
+    type FunctorOut = STM<U>;
+    fn fmap(m: Self, func: impl Fn(T) -> U + Send + 'static) -> Self::FunctorOut {
+        m.map(sync_fn(func))
+    }
+}
+
+impl<T, U> Applicative<U> for STM<T>
+where
+    T: Clone + Send + Sync + 'static,
+    U: Clone + Send + Sync + 'static,
+{
+    type AppT = T;
+    fn pure(a: T) -> Self {
+        STM::pure(a)
+    }
+}
+
+impl<F, T, U> ApplicativeFunctor<F, U> for STM<T>
+where
+    F: Fn(T) -> U + Clone + Send + Sync + 'static,
+    T: Clone + Send + Sync + 'static,
+    U: Clone + Send + Sync + 'static,
+{
+    type AppFuncT = T;
+    type AppFuncOut = STM<U>;
+    type AppFuncFn = STM<F>;
+    fn seq(m: Self, func: Self::AppFuncFn) -> Self::AppFuncOut {
+        func.and_then(move |f| m.clone().map(f))
+    }
+}
+
+impl<T, U> Monad<U> for STM<T>
+where
+    T: Clone + Send + Sync + 'static,
+    U: Clone + Send + Sync + 'static,
+{
+    type MonadT = T;
+    type MonadOut = STM<U>;
+    fn bind(m: Self, func: impl Fn(T) -> Self::MonadOut + Send + 'static) -> Self::MonadOut {
+        m.and_then(sync_fn(func))
+    }
+}
+
+impl<A> Traceable for STM<A> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::FutureExt;
+    use std::{sync::atomic::AtomicBool, time::Duration};
+
+    fn transfer(from: &TVar<u32>, to: &TVar<u32>, amount: u32) -> STM<()> {
+        let (from, to) = (from.clone(), to.clone());
+        from.read().and_then(move |balance| {
+            let (from, to) = (from.clone(), to.clone());
+            STM::check(balance >= amount)
+                .and_then(move |_| from.write(balance - amount))
+                .and_then(move |_| to.modify(move |b| b + amount))
+        })
+    }
+
+    #[test]
+    fn test_read_write() {
+        let tvar = TVar::new(1u32);
+        let stm = tvar.read().and_then({
+            let tvar = tvar.clone();
+            move |a| {
+                tvar.write(a + 1).and_then({
+                    let tvar = tvar.clone();
+                    move |_| tvar.read()
+                })
+            }
+        });
+        assert_eq!(atomically(stm.clone()).wait(), 2);
+        assert_eq!(atomically(stm).wait(), 3);
+        assert_eq!(tvar.load(), 3);
+    }
+
+    #[test]
+    fn test_nothing_runs_until_polled() {
+        let tvar = TVar::new(1u32);
+        let fut = atomically(tvar.write(5));
+        assert_eq!(tvar.load(), 1);
+        fut.wait();
+        assert_eq!(tvar.load(), 5);
+    }
+
+    #[test]
+    fn test_typeclasses_stm() {
+        let (a, b) = (TVar::new(3u32), TVar::new(4u32));
+        assert_eq!(atomically(fmap(a.read(), |a| a * 2)).wait(), 6);
+        assert_eq!(atomically(pure::<STM<_>>(1u32)).wait(), 1);
+        let b2 = b.clone();
+        let stm = bind(a.read(), move |a| fmap(b2.read(), move |b| a + b));
+        assert_eq!(atomically(stm).wait(), 7);
+        let sum = lift_m2::<STM<_>, _, _>(|a: u32, b: u32| a * b)(a.read(), b.read());
+        assert_eq!(atomically(sum).wait(), 12);
+        let func = pure::<STM<_>>(|a: u32| a + 1);
+        assert_eq!(atomically(seq(b.read(), func)).wait(), 5);
+    }
+
+    #[test]
+    fn test_or_else() {
+        let (a, b) = (TVar::new(10u32), TVar::new(0u32));
+        // The first branch's write to `b` is undone when it retries
+        let first = b.write(99).and_then(|_| STM::check(false)).map(|_| "first");
+        let second = b.modify(|b| b + 1).map(|_| "second");
+        assert_eq!(atomically(first.or_else(second)).wait(), "second");
+        assert_eq!(b.load(), 1);
+
+        let stm = transfer(&a, &b, 5).map(|_| true).or_else(STM::pure(false));
+        assert!(atomically(stm.clone()).wait());
+        assert!(atomically(stm.clone()).wait());
+        assert!(!atomically(stm).wait());
+        assert_eq!((a.load(), b.load()), (0, 11));
+    }
+
+    #[test]
+    fn test_blocked_transactions_leave_no_wakers() {
+        let tvar = TVar::new(0u32);
+        let blocked = tvar.read().and_then(|n| STM::check(n > 0));
+        let waiters = || tvar.cell.waiters.lock().unwrap().len();
+        for _ in 0..10 {
+            let mut fut = atomically(blocked.clone());
+            let mut cx = Context::from_waker(Waker::noop());
+            assert!(fut.poll_unpin(&mut cx).is_pending());
+            assert!(fut.poll_unpin(&mut cx).is_pending());
+            assert_eq!(waiters(), 1);
+        }
+        assert_eq!(waiters(), 0);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_conflict_yields() {
+        let (a, b) = (TVar::new(0u32), TVar::new(0u32));
+        let other_ran = Arc::new(AtomicBool::new(false));
+        let o = other_ran.clone();
+        tokio::spawn(async move { o.store(true, Ordering::SeqCst) });
+
+        let bumped = Arc::new(AtomicBool::new(false));
+        let stm = a.read().and_then(move |_| {
+            // Another commit to `a` lands in the middle of the first run
+            if !bumped.swap(true, Ordering::SeqCst) {
+                a.cell.slot.lock().unwrap().version += 1;
+            }
+            let other_ran = other_ran.load(Ordering::SeqCst);
+            b.read().map(move |_| other_ran)
+        });
+        // The restarted run came after the other task had its turn
+        assert!(atomically(stm).await);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_retry_blocks_until_changed() {
+        let (a, b) = (TVar::new(0u32), TVar::new(0u32));
+        let waiting = tokio::spawn(atomically(transfer(&a, &b, 10)));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+        atomically(a.write(5)).await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+        atomically(a.modify(|a| a + 5)).await;
+        tokio::time::timeout(Duration::from_secs(5), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((a.load(), b.load()), (0, 10));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_retry_in_both_branches() {
+        let (a, b) = (TVar::new(0u32), TVar::new(0u32));
+        let either = a
+            .read()
+            .and_then(|a| STM::check(a > 0).map(move |_| ('a', a)))
+            .or_else(
+                b.read()
+                    .and_then(|b| STM::check(b > 0).map(move |_| ('b', b))),
+            );
+        let waiting = tokio::spawn(atomically(either));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+        atomically(b.write(2)).await;
+        let res = tokio::time::timeout(Duration::from_secs(5), waiting).await;
+        assert_eq!(res.unwrap().unwrap(), ('b', 2));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn test_concurrent_transfers() {
+        let accounts: Vec<_> = (0..10).map(|_| TVar::new(1000u32)).collect();
+        let tasks = (0..2000).map(|i: usize| {
+            let (from, to) = (&accounts[i % 10], &accounts[(i * 7 + 3) % 10]);
+            let stm = transfer(from, to, (i % 50) as u32).or_else(STM::pure(()));
+            tokio::spawn(atomically(stm))
+        });
+        futures::future::join_all(tasks).await;
+        let total: u32 = accounts.iter().map(TVar::load).sum();
+        assert_eq!(total, 10_000);
+
+        // Every transaction sees a consistent state of all the accounts
+        let all: Vec<_> = accounts.iter().map(TVar::read).collect();
+        let sum = all.into_iter().fold(STM::pure(0u32), |acc, read| {
+            lift_m2::<STM<_>, _, _>(|a: u32, b: u32| a + b)(acc, read)
+        });
+        assert_eq!(atomically(sum).await, 10_000);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn test_transactions_see_consistent_state() {
+        let accounts: Vec<_> = (0..4).map(|_| TVar::new(100u32)).collect();
+        let reads: Vec<_> = accounts.iter().map(TVar::read).collect();
+        let audit = reads
+            .into_iter()
+            .fold(STM::pure(0u32), |acc, read| {
+                lift_m2::<STM<_>, _, _>(|a: u32, b: u32| a + b)(acc, read)
+            })
+            // Checked inside the transaction, which must never see a partial commit
+            .map(|total| assert_eq!(total, 400));
+        let tasks = (0..4000).map(|i: usize| match i % 2 {
+            0 => {
+                let (from, to) = (&accounts[i % 4], &accounts[(i / 2 + 1) % 4]);
+                tokio::spawn(atomically(transfer(from, to, 1).or_else(STM::pure(()))))
+            }
+            _ => tokio::spawn(atomically(audit.clone())),
+        });
+        let results = futures::future::join_all(tasks).await;
+        assert!(results.into_iter().all(|res| res.is_ok()));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn test_concurrent_producers_and_consumers() {
+        let (stock, sold) = (TVar::new(0u32), TVar::new(0u32));
+        let consumers: Vec<_> = (0..50)
+            .map(|_| {
+                let take = {
+                    let (stock, sold) = (stock.clone(), sold.clone());
+                    stock.read().and_then(move |n| {
+                        let (stock, sold) = (stock.clone(), sold.clone());
+                        STM::check(n >= 2)
+                            .and_then(move |_| stock.write(n - 2))
+                            .and_then(move |_| sold.modify(|s| s + 2))
+                    })
+                };
+                tokio::spawn(atomically(take))
+            })
+            .collect();
+        let producers: Vec<_> = (0..100)
+            .map(|_| tokio::spawn(atomically(stock.modify(|n| n + 1))))
+            .collect();
+        futures::future::join_all(producers).await;
+        let res = tokio::time::timeout(
+            Duration::from_secs(10),
+            futures::future::join_all(consumers),
+        );
+        assert!(res.await.unwrap().into_iter().all(|r| r.is_ok()));
+        assert_eq!((stock.load(), sold.load()), (0, 100));
+    }
+}
@@ -1,4 +1,5 @@
 use crate::prelude::typeclasses::*;
+use crate::typeclasses::free_effect::trace::Traceable;
 
 impl<A> Monoid for Vec<A> {
     fn empty() -> Self {
@@ -51,13 +52,19 @@ impl<T: Send, U: Send> Monad<U> for Vec<T> {
     }
 }
 
+impl<A> Traceable for Vec<A> {
+    fn is_empty_state(&self) -> Option<bool> {
+        Some(self.is_empty())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
     #[test]
     fn test_empty_vec() {
-        assert_eq!(Vec::<u32>::empty(), []);
+        assert_eq!(Vec::<u32>::empty(), [0u32; 0]);
     }
     #[test]
     fn test_identity_vec() {
@@ -71,12 +78,12 @@ mod test {
         assert_eq!(combine(vec![3], vec![4]), vec![3, 4]);
         assert_eq!(combine(vec![3], vec![]), vec![3]);
         assert_eq!(combine(vec![], vec![4]), vec![4]);
-        assert_eq!(combine::<Vec<u32>>(vec![], vec![]), vec![]);
+        assert_eq!(combine::<Vec<u32>>(vec![], vec![]), Vec::<u32>::new());
     }
     #[test]
     fn test_fmap_vec() {
         assert_eq!(fmap(vec![3, 4], |i| i + 4), vec![7, 8]);
-        assert_eq!(fmap(vec![], |i: u32| i + 4), vec![]);
+        assert_eq!(fmap(vec![], |i: u32| i + 4), Vec::<u32>::new());
     }
     #[test]
     fn test_pure_vec() {
@@ -90,7 +97,7 @@ mod test {
     }
 
     fn empty_if_even<M: Monad<u32, MonadT = u32> + Monoid + Applicative<u32>>(input: String) -> M {
-        if input.len() % 2 == 0 {
+        if input.len().is_multiple_of(2) {
             M::empty()
         } else {
             M::pure(input.len() as u32)
@@ -103,7 +110,7 @@ mod test {
             bind(vec!["dog".to_string(), "crow".to_string()], empty_if_even),
             vec![3]
         );
-        assert_eq!(bind(vec![], empty_if_even), vec![]);
+        assert_eq!(bind(vec![], empty_if_even), Vec::<u32>::new());
     }
 
     fn add4(x: u32) -> u32 {